@@ -0,0 +1,420 @@
+use crate::internal::{
+    errors,
+    expression::{evaluate, expression_type, sub_expressions, Context, Field, Scope},
+    function::{Functions, UserAggregate},
+    table::{ColumnType, ColumnValue},
+};
+use sqlparser::ast::{Expr, FunctionArg, FunctionArgExpr};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    /// There are no floating point columns yet, so the average is rounded towards zero.
+    Avg,
+    Min,
+    Max,
+    User(Rc<UserAggregate>),
+}
+
+/// A call to an aggregate function such as `COUNT(DISTINCT a)`.
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    /// Empty for `COUNT(*)`. Only user-defined aggregates take several.
+    pub arguments: Vec<Expr>,
+    pub distinct: bool,
+}
+impl Aggregate {
+    /// Returns the aggregate `expr` calls, or `None` when it isn't an aggregate
+    /// call. `functions` are the ones the application registered, if known.
+    pub fn from_expr(
+        expr: &Expr,
+        functions: Option<&Functions>,
+    ) -> Result<Option<Aggregate>, errors::QueryError> {
+        let Expr::Function(function) = expr else {
+            return Ok(None);
+        };
+        // with OVER they are window functions, computed per row rather than per group
+        if function.over.is_some() {
+            return Ok(None);
+        }
+        let name = function.name.to_string().to_lowercase();
+        if let Some(functions) = functions {
+            if let Some(aggregate) = functions.aggregate(&name) {
+                let arguments = function
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Ok(expr.clone()),
+                        _ => Err(errors::QueryError::SyntaxError),
+                    })
+                    .collect::<Result<_, _>>()?;
+                return Ok(Some(Aggregate {
+                    function: AggregateFunction::User(aggregate),
+                    arguments,
+                    distinct: function.distinct,
+                }));
+            }
+            if functions.scalar(&name).is_some() {
+                return Ok(None);
+            }
+        }
+        let aggregate_function = match name.as_str() {
+            "count" => AggregateFunction::Count,
+            "sum" => AggregateFunction::Sum,
+            "avg" => AggregateFunction::Avg,
+            "min" => AggregateFunction::Min,
+            "max" => AggregateFunction::Max,
+            _ => return Ok(None),
+        };
+        // min and max with several arguments are the scalar functions
+        if function.args.len() != 1 {
+            return match aggregate_function {
+                AggregateFunction::Min | AggregateFunction::Max => Ok(None),
+                _ => Err(errors::QueryError::WrongNumberOfArguments),
+            };
+        }
+        let arguments = match &function.args[0] {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => vec![expr.clone()],
+            FunctionArg::Unnamed(FunctionArgExpr::Wildcard)
+                if aggregate_function == AggregateFunction::Count =>
+            {
+                Vec::new()
+            }
+            _ => return Err(errors::QueryError::SyntaxError),
+        };
+        Ok(Some(Aggregate {
+            function: aggregate_function,
+            arguments,
+            distinct: function.distinct,
+        }))
+    }
+    pub fn result_type(
+        &self,
+        fields: &[Field],
+        context: Option<&Context>,
+    ) -> Result<ColumnType, errors::QueryError> {
+        match (&self.function, self.arguments.first()) {
+            (AggregateFunction::Min | AggregateFunction::Max, Some(argument)) => {
+                expression_type(argument, fields, context)
+            }
+            // the type of what user-defined aggregates return is only known from their values
+            _ => Ok(ColumnType::Int),
+        }
+    }
+}
+
+/// Finds the aggregate calls in `expr`, appending the ones not seen yet to `found`.
+pub fn collect_aggregates(
+    expr: &Expr,
+    functions: &Functions,
+    found: &mut Vec<Expr>,
+) -> Result<(), errors::QueryError> {
+    if Aggregate::from_expr(expr, Some(functions))?.is_some() {
+        if !found.contains(expr) {
+            found.push(expr.clone());
+        }
+        return Ok(());
+    }
+    for expr in sub_expressions(expr) {
+        collect_aggregates(expr, functions, found)?;
+    }
+    Ok(())
+}
+
+/// The running state of one aggregate over the rows of a group or window frame.
+#[derive(Debug, Default)]
+pub struct Accumulator {
+    count: i64,
+    sum: Option<i64>,
+    extreme: Option<ColumnValue>,
+    seen: HashSet<Vec<ColumnValue>>,
+    /// The state of a user-defined aggregate.
+    state: Option<Box<dyn Any>>,
+}
+impl Accumulator {
+    /// Adds a row, given the values of the aggregate's arguments for it.
+    pub fn update(
+        &mut self,
+        aggregate: &Aggregate,
+        values: Vec<ColumnValue>,
+    ) -> Result<(), errors::QueryError> {
+        if let AggregateFunction::User(user) = &aggregate.function {
+            // user-defined aggregates see NULLs too and decide for themselves
+            if aggregate.distinct && !self.seen.insert(values.clone()) {
+                return Ok(());
+            }
+            let state = self.state.get_or_insert_with(|| user.init());
+            return user.step(state.as_mut(), &values);
+        }
+        let value = match values.into_iter().next() {
+            // COUNT(*) counts every row
+            None => {
+                self.count += 1;
+                return Ok(());
+            }
+            // everything else ignores NULLs
+            Some(ColumnValue::Null) => return Ok(()),
+            Some(value) => value,
+        };
+        if aggregate.distinct && !self.seen.insert(vec![value.clone()]) {
+            return Ok(());
+        }
+        self.count += 1;
+        match aggregate.function {
+            AggregateFunction::Count => {}
+            AggregateFunction::Sum | AggregateFunction::Avg => {
+                let ColumnValue::Int(value) = value else {
+                    return Err(errors::QueryError::TypeMismatch);
+                };
+                let sum = self.sum.unwrap_or(0).checked_add(value);
+                self.sum = Some(sum.ok_or(errors::QueryError::IntegerOverflow)?);
+            }
+            AggregateFunction::Min => {
+                if self.extreme.as_ref().is_none_or(|extreme| value < *extreme) {
+                    self.extreme = Some(value);
+                }
+            }
+            AggregateFunction::Max => {
+                if self.extreme.as_ref().is_none_or(|extreme| value > *extreme) {
+                    self.extreme = Some(value);
+                }
+            }
+            AggregateFunction::User(_) => unreachable!(),
+        }
+        Ok(())
+    }
+    /// The value of a built-in aggregate over the rows so far; `None` for
+    /// user-defined aggregates, which can only be finalized once.
+    pub fn value(&self, aggregate: &Aggregate) -> Option<ColumnValue> {
+        Some(match &aggregate.function {
+            AggregateFunction::Count => ColumnValue::Int(self.count),
+            AggregateFunction::Sum => self.sum.map_or(ColumnValue::Null, ColumnValue::Int),
+            AggregateFunction::Avg => match self.sum {
+                Some(sum) => ColumnValue::Int(sum / self.count),
+                None => ColumnValue::Null,
+            },
+            AggregateFunction::Min | AggregateFunction::Max => {
+                self.extreme.clone().unwrap_or(ColumnValue::Null)
+            }
+            AggregateFunction::User(_) => return None,
+        })
+    }
+    pub fn finish(self, aggregate: &Aggregate) -> Result<ColumnValue, errors::QueryError> {
+        match &aggregate.function {
+            // groups without rows still get a fresh state to finalize
+            AggregateFunction::User(user) => {
+                user.finalize(self.state.unwrap_or_else(|| user.init()))
+            }
+            _ => Ok(self.value(aggregate).unwrap()),
+        }
+    }
+}
+
+struct Group {
+    /// The group's first row, which bare columns outside of aggregates read from, like SQLite.
+    first_row: Vec<ColumnValue>,
+    keys: Vec<ColumnValue>,
+    accumulators: Vec<Accumulator>,
+}
+
+/// Groups rows by the values of the GROUP BY expressions in a hash table and
+/// computes the aggregates of each group.
+pub struct HashAggregate {
+    group_by: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+    groups: HashMap<Vec<ColumnValue>, usize>,
+    /// Groups in the order they were first seen, so results don't depend on hashing.
+    ordered_groups: Vec<Group>,
+}
+impl HashAggregate {
+    pub fn new(
+        group_by: Vec<Expr>,
+        aggregates: &[Expr],
+        functions: &Functions,
+    ) -> Result<HashAggregate, errors::QueryError> {
+        let aggregates = aggregates
+            .iter()
+            .map(|expr| Ok(Aggregate::from_expr(expr, Some(functions))?.unwrap()))
+            .collect::<Result<_, errors::QueryError>>()?;
+        Ok(HashAggregate {
+            group_by,
+            aggregates,
+            groups: HashMap::new(),
+            ordered_groups: Vec::new(),
+        })
+    }
+    pub fn push(
+        &mut self,
+        fields: &[Field],
+        row: Vec<ColumnValue>,
+        context: &Context,
+    ) -> Result<(), errors::QueryError> {
+        let scope = Scope::new(fields, &row).within(Some(context));
+        let keys = self
+            .group_by
+            .iter()
+            .map(|expr| evaluate(expr, &scope))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut values = Vec::new();
+        for aggregate in &self.aggregates {
+            let arguments = aggregate
+                .arguments
+                .iter()
+                .map(|argument| evaluate(argument, &scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            values.push(arguments);
+        }
+        let index = match self.groups.get(&keys) {
+            Some(index) => *index,
+            None => {
+                self.groups.insert(keys.clone(), self.ordered_groups.len());
+                self.ordered_groups.push(Group {
+                    first_row: row,
+                    keys,
+                    accumulators: self
+                        .aggregates
+                        .iter()
+                        .map(|_| Accumulator::default())
+                        .collect(),
+                });
+                self.ordered_groups.len() - 1
+            }
+        };
+        let group = &mut self.ordered_groups[index];
+        for ((aggregate, accumulator), value) in self
+            .aggregates
+            .iter()
+            .zip(group.accumulators.iter_mut())
+            .zip(values)
+        {
+            accumulator.update(aggregate, value)?;
+        }
+        Ok(())
+    }
+    /// Returns one row per group: the group's first row, then its keys, then its aggregates.
+    /// Without GROUP BY there is always exactly one group, even over no rows.
+    pub fn finish(mut self, fields: &[Field]) -> Result<Vec<Vec<ColumnValue>>, errors::QueryError> {
+        if self.ordered_groups.is_empty() && self.group_by.is_empty() {
+            self.ordered_groups.push(Group {
+                first_row: fields.iter().map(|_| ColumnValue::Null).collect(),
+                keys: Vec::new(),
+                accumulators: self
+                    .aggregates
+                    .iter()
+                    .map(|_| Accumulator::default())
+                    .collect(),
+            });
+        }
+        let aggregates = self.aggregates;
+        self.ordered_groups
+            .into_iter()
+            .map(|group| {
+                let mut row = group.first_row;
+                row.extend(group.keys);
+                for (aggregate, accumulator) in aggregates.iter().zip(group.accumulators) {
+                    row.push(accumulator.finish(aggregate)?);
+                }
+                Ok(row)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        table::ColumnValue::Null,
+        testing::{error, int, ints, text, values, with_tables, Fixture},
+    };
+
+    /// Sales of each product by region, one with an unknown amount.
+    const SALES: Fixture = (
+        "sales",
+        "region TEXT, product TEXT, amount INT",
+        &[
+            "'north', 'apple', 10",
+            "'north', 'pear', 5",
+            "'south', 'apple', 7",
+            "'south', 'apple', NULL",
+            "'east', 'plum', 4",
+        ],
+    );
+
+    #[test]
+    fn aggregates_over_all_rows() {
+        with_tables(&[SALES], |database| {
+            let result = values(
+                database,
+                "SELECT count(*), count(amount), sum(amount), avg(amount), min(amount), \
+                max(product) FROM sales",
+            );
+            let expected = [int(5), int(4), int(26), int(6), int(4), text("plum")];
+            assert_eq!(result, expected);
+        });
+    }
+
+    #[test]
+    fn aggregates_over_no_rows() {
+        with_tables(&[SALES], |database| {
+            let sql = "SELECT count(*), sum(amount), max(amount) FROM sales WHERE amount > 100";
+            assert_eq!(values(database, sql), [int(0), Null, Null]);
+            let sql = "SELECT region, count(*) FROM sales WHERE amount > 100 GROUP BY region";
+            assert!(values(database, sql).is_empty());
+        });
+    }
+
+    #[test]
+    fn group_by_and_having() {
+        with_tables(&[SALES], |database| {
+            let result = values(
+                database,
+                "SELECT region, sum(amount) FROM sales GROUP BY region ORDER BY region",
+            );
+            let expected = [
+                [text("east"), int(4)],
+                [text("north"), int(15)],
+                [text("south"), int(7)],
+            ];
+            assert_eq!(result, expected.concat());
+            let sql = "SELECT count(*) FROM sales GROUP BY region HAVING sum(amount) > 5 \
+                ORDER BY count(*)";
+            assert_eq!(ints(database, sql), [2, 2]);
+        });
+    }
+
+    #[test]
+    fn count_distinct() {
+        with_tables(&[SALES], |database| {
+            let sql = "SELECT count(DISTINCT product), count(DISTINCT region) FROM sales";
+            assert_eq!(values(database, sql), [int(3), int(3)]);
+        });
+    }
+
+    #[test]
+    fn aggregates_in_expressions() {
+        with_tables(&[SALES], |database| {
+            let sql = "SELECT product, max(amount) - min(amount) FROM sales GROUP BY product \
+                ORDER BY 2 DESC, 1";
+            let result = values(database, sql);
+            assert_eq!(result[..2], [text("apple"), int(3)]);
+        });
+    }
+
+    #[test]
+    fn wrong_aggregate_calls() {
+        with_tables(&[SALES], |database| {
+            let e = error(database, "SELECT sum(amount, amount) FROM sales");
+            assert!(matches!(e, QueryError::WrongNumberOfArguments));
+            let e = error(database, "SELECT sum(*) FROM sales");
+            assert!(matches!(e, QueryError::SyntaxError));
+        });
+    }
+}
@@ -0,0 +1,431 @@
+use crate::internal::{
+    errors,
+    table::{Column, ColumnType, ColumnValue, ResultSet, Table},
+};
+use sqlparser::ast::{CopyLegacyCsvOption, CopyLegacyOption, CopyOption, Ident};
+use std::{
+    fs::{self, File},
+    io,
+};
+
+/// How the fields of a CSV file read or written by `COPY` are laid out.
+pub struct CopyOptions {
+    /// Whether the first line names the columns.
+    header: bool,
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    /// The field standing for NULL, empty by default. Like in PostgreSQL, only
+    /// an unquoted field matching it is NULL, and `COPY TO` quotes the values
+    /// that would match it, so empty strings and NULLs stay apart.
+    null: String,
+}
+
+impl CopyOptions {
+    /// Reads the options of a `COPY` statement, in either PostgreSQL's
+    /// `WITH (HEADER, DELIMITER ',')` form or its older `CSV HEADER` form.
+    pub fn new(
+        options: &[CopyOption],
+        legacy_options: &[CopyLegacyOption],
+    ) -> Result<CopyOptions, errors::QueryError> {
+        let mut copy = CopyOptions {
+            header: false,
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            null: String::new(),
+        };
+        for option in options {
+            match option {
+                CopyOption::Format(format) if format.value.eq_ignore_ascii_case("csv") => {}
+                CopyOption::Header(header) => copy.header = *header,
+                CopyOption::Delimiter(delimiter) => copy.delimiter = byte(*delimiter)?,
+                CopyOption::Quote(quote) => copy.quote = byte(*quote)?,
+                CopyOption::Escape(escape) => copy.escape = Some(byte(*escape)?),
+                CopyOption::Null(null) => copy.null = null.clone(),
+                _ => return Err(errors::QueryError::UnkownOperation),
+            }
+        }
+        for option in legacy_options {
+            match option {
+                CopyLegacyOption::Delimiter(delimiter) => copy.delimiter = byte(*delimiter)?,
+                CopyLegacyOption::Null(null) => copy.null = null.clone(),
+                CopyLegacyOption::Csv(options) => {
+                    for option in options {
+                        match option {
+                            CopyLegacyCsvOption::Header => copy.header = true,
+                            CopyLegacyCsvOption::Quote(quote) => copy.quote = byte(*quote)?,
+                            CopyLegacyCsvOption::Escape(escape) => {
+                                copy.escape = Some(byte(*escape)?)
+                            }
+                            _ => return Err(errors::QueryError::UnkownOperation),
+                        }
+                    }
+                }
+                CopyLegacyOption::Binary => return Err(errors::QueryError::UnkownOperation),
+            }
+        }
+        Ok(copy)
+    }
+}
+
+/// Only single byte delimiters and quotes are supported.
+fn byte(character: char) -> Result<u8, errors::QueryError> {
+    u8::try_from(character).map_err(|_| errors::QueryError::UnkownOperation)
+}
+
+/// Reads the rows of the CSV file at `path` into `table`, and returns how
+/// many there were.
+///
+/// The fields of each line go to `columns` in order, or to the columns named
+/// by the header, or else to all of the table's columns. Columns without a
+/// field are NULL. Every line is converted to the types of the table's columns
+/// before any is written, so a line that can't be leaves the table as it was
+/// and is reported with its line number.
+pub fn copy_from(
+    table: &Table,
+    columns: &[Ident],
+    path: &str,
+    options: &CopyOptions,
+) -> Result<usize, errors::QueryError> {
+    let position = |name: &str| {
+        let position = table.columns.iter().position(|column| column.name == name);
+        position.ok_or(errors::QueryError::UnknownColumn)
+    };
+    // kept whole to tell the quoted fields apart
+    let data = fs::read(path).map_err(errors::QueryError::IOError)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .escape(options.escape)
+        .from_reader(&data[..]);
+    let mut records = reader.records();
+    // the index of the table column each field goes to
+    let mut targets = columns
+        .iter()
+        .map(|column| position(&column.value))
+        .collect::<Result<Vec<_>, _>>()?;
+    if options.header {
+        if let Some(header) = records.next() {
+            let header = header.map_err(csv_error)?;
+            if columns.is_empty() {
+                targets = header
+                    .iter()
+                    .map(position)
+                    .collect::<Result<_, _>>()
+                    .map_err(|error| row_error(1, error))?;
+            }
+        }
+    }
+    if targets.is_empty() {
+        targets = (0..table.columns.len()).collect();
+    }
+    let mut rows = Vec::new();
+    for record in records {
+        let record = record.map_err(csv_error)?;
+        let (line, start) = record.position().map_or((0, 0), |position| {
+            (position.line(), position.byte() as usize)
+        });
+        if record.len() != targets.len() {
+            return Err(row_error(line, errors::QueryError::WrongNumberOfColumns));
+        }
+        let quoted = quoted_fields(&data[start..], options);
+        let mut row = vec![ColumnValue::Null; table.columns.len()];
+        for ((field, quoted), target) in record.iter().zip(quoted).zip(&targets) {
+            row[*target] = coerce(field, quoted, &table.columns[*target], options)
+                .map_err(|error| row_error(line, error))?;
+        }
+        table
+            .row_size(&row)
+            .map_err(|error| row_error(line, errors::QueryError::InsertRowError(error)))?;
+        rows.push(row);
+    }
+    let count = rows.len();
+    table
+        .insert_rows(rows)
+        .map_err(errors::QueryError::InsertRowError)?;
+    Ok(count)
+}
+
+/// Which fields of the CSV record that `record` starts with were quoted. The
+/// csv reader drops the quotes, so this follows them through the raw bytes.
+fn quoted_fields(record: &[u8], options: &CopyOptions) -> Vec<bool> {
+    let mut quoted = vec![record.first() == Some(&options.quote)];
+    let mut in_quotes = quoted[0];
+    let mut i = usize::from(in_quotes);
+    while i < record.len() {
+        let byte = record[i];
+        if in_quotes {
+            if Some(byte) == options.escape {
+                // the escaped byte
+                i += 1;
+            } else if byte == options.quote {
+                // a doubled quote stands for one
+                if record.get(i + 1) == Some(&options.quote) {
+                    i += 1;
+                } else {
+                    in_quotes = false;
+                }
+            }
+        } else if byte == options.delimiter {
+            in_quotes = record.get(i + 1) == Some(&options.quote);
+            quoted.push(in_quotes);
+            i += usize::from(in_quotes);
+        } else if byte == b'\n' || byte == b'\r' {
+            break;
+        }
+        i += 1;
+    }
+    quoted
+}
+
+/// Converts a field of a CSV file to a value of `column`'s type.
+fn coerce(
+    field: &str,
+    quoted: bool,
+    column: &Column,
+    options: &CopyOptions,
+) -> Result<ColumnValue, errors::QueryError> {
+    if !quoted && field == options.null {
+        return Ok(ColumnValue::Null);
+    }
+    match column._type {
+        ColumnType::Int => match field.trim().parse::<i64>() {
+            Ok(value) => Ok(ColumnValue::Int(value)),
+            Err(_) => Err(errors::QueryError::TypeMismatch),
+        },
+        ColumnType::Text => Ok(ColumnValue::Text(field.to_string())),
+    }
+}
+
+fn row_error(line: u64, error: errors::QueryError) -> errors::QueryError {
+    errors::QueryError::CopyRowError {
+        line,
+        error: Box::new(error),
+    }
+}
+
+fn csv_error(error: csv::Error) -> errors::QueryError {
+    let line = error.position().map_or(0, |position| position.line());
+    match error.into_kind() {
+        csv::ErrorKind::Io(e) => errors::QueryError::IOError(e),
+        _ => row_error(line, errors::QueryError::SyntaxError),
+    }
+}
+
+/// Writes `result` to the CSV file at `path`, replacing it, and returns how
+/// many rows there were.
+pub fn copy_to(
+    result: &ResultSet,
+    path: &str,
+    options: &CopyOptions,
+) -> Result<usize, errors::QueryError> {
+    let file = File::create(path).map_err(errors::QueryError::IOError)?;
+    // fields are quoted by `field`, which knows which values need it
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .quote_style(csv::QuoteStyle::Never)
+        .from_writer(file);
+    let io_error = |e: csv::Error| match e.into_kind() {
+        csv::ErrorKind::Io(e) => errors::QueryError::IOError(e),
+        kind => errors::QueryError::IOError(io::Error::other(format!("{kind:?}"))),
+    };
+    if options.header {
+        let header = result.columns.iter().map(|column| {
+            let name = ColumnValue::Text(column.name.clone());
+            field(&name, options)
+        });
+        writer.write_record(header).map_err(io_error)?;
+    }
+    for row in &result.rows {
+        let fields = row.iter().map(|value| field(value, options));
+        writer.write_record(fields).map_err(io_error)?;
+    }
+    writer.flush().map_err(errors::QueryError::IOError)?;
+    Ok(result.rows.len())
+}
+
+/// `value` as a field of a CSV file. It is quoted when it holds a delimiter,
+/// a quote or a line break, or when it would read as the NULL string, with
+/// its quotes doubled, or escaped when there's an escape.
+fn field(value: &ColumnValue, options: &CopyOptions) -> Vec<u8> {
+    let text = match value {
+        ColumnValue::Int(value) => value.to_string(),
+        ColumnValue::Text(value) => value.clone(),
+        ColumnValue::Null => return options.null.clone().into_bytes(),
+    };
+    let special = |byte: u8| {
+        [options.delimiter, options.quote, b'\n', b'\r'].contains(&byte)
+            || Some(byte) == options.escape
+    };
+    if text != options.null && !text.bytes().any(special) {
+        return text.into_bytes();
+    }
+    let mut field = vec![options.quote];
+    for byte in text.bytes() {
+        if byte == options.quote || Some(byte) == options.escape {
+            field.push(options.escape.unwrap_or(options.quote));
+        }
+        field.push(byte);
+    }
+    field.push(options.quote);
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        database::Database,
+        errors::{InsertRowError, QueryError},
+        table::{ColumnValue, QueryResult},
+        testing::{error, execute, int, ints, rows, text, with_tables, Fixture},
+    };
+    use std::fs;
+
+    const PEOPLE: Fixture = ("people", "id INT, name TEXT", &[]);
+
+    /// The line and the error a `COPY FROM` failed with.
+    fn row_error(database: &mut Database, sql: &str) -> (u64, QueryError) {
+        match error(database, sql) {
+            QueryError::CopyRowError { line, error } => (line, *error),
+            other => panic!("{sql:?} failed with {other:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        with_tables(&[PEOPLE], |database| {
+            let contents = "-2,\"x, \"\"y\"\"\"\n7,\n8,plain\n9,\"\"\n";
+            fs::write("in.csv", contents).unwrap();
+            execute(database, "COPY people FROM 'in.csv'");
+            execute(
+                database,
+                "COPY (SELECT * FROM people) TO 'out.csv' WITH (HEADER)",
+            );
+            let out = fs::read_to_string("out.csv").unwrap();
+            assert_eq!(out, format!("id,name\n{contents}"));
+            execute(database, "CREATE TABLE copy (id INT, name TEXT)");
+            execute(database, "COPY copy FROM 'out.csv' WITH (HEADER)");
+            let expected = [
+                [int(-2), text("x, \"y\"")],
+                [int(7), ColumnValue::Null],
+                [int(8), text("plain")],
+                [int(9), text("")],
+            ];
+            assert_eq!(rows(database, "SELECT * FROM copy"), expected);
+        });
+    }
+
+    #[test]
+    fn copy_from_reports_how_many_rows() {
+        with_tables(&[PEOPLE], |database| {
+            let lines: String = (0..1000).map(|i| format!("{i},name {i}\n")).collect();
+            fs::write("in.csv", lines).unwrap();
+            let result = execute(database, "COPY people FROM 'in.csv'");
+            assert!(matches!(result, QueryResult::CopySucceeded(1000)));
+            let sql = "SELECT count(*), sum(id), max(name) FROM people";
+            assert_eq!(
+                rows(database, sql),
+                [[int(1000), int(499500), text("name 999")]]
+            );
+        });
+    }
+
+    #[test]
+    fn fields_go_to_the_columns_named() {
+        with_tables(&[PEOPLE], |database| {
+            fs::write("header.csv", "name,id\nann,1\n").unwrap();
+            execute(database, "COPY people FROM 'header.csv' CSV HEADER");
+            fs::write("list.csv", "bob\n").unwrap();
+            execute(database, "COPY people (name) FROM 'list.csv'");
+            assert_eq!(
+                rows(database, "SELECT * FROM people"),
+                [[int(1), text("ann")], [ColumnValue::Null, text("bob")]]
+            );
+            execute(database, "COPY people (name, id) TO 'out.csv'");
+            assert_eq!(fs::read_to_string("out.csv").unwrap(), "ann,1\nbob,\n");
+        });
+    }
+
+    #[test]
+    fn delimiter_null_and_escape() {
+        with_tables(&[PEOPLE], |database| {
+            let contents = "1;NA\n2;\n3;\"\\\"q\\\"\"\n4;\"NA\"\n";
+            fs::write("in.csv", contents).unwrap();
+            execute(
+                database,
+                "COPY people FROM 'in.csv' WITH (DELIMITER ';', NULL 'NA', ESCAPE '\\')",
+            );
+            assert_eq!(
+                rows(database, "SELECT name FROM people"),
+                [
+                    [ColumnValue::Null],
+                    [text("")],
+                    [text("\"q\"")],
+                    [text("NA")]
+                ]
+            );
+            execute(
+                database,
+                "COPY people TO 'out.csv' WITH (DELIMITER ';', NULL 'NA', ESCAPE '\\')",
+            );
+            assert_eq!(fs::read_to_string("out.csv").unwrap(), contents);
+        });
+    }
+
+    #[test]
+    fn only_unquoted_empty_fields_are_null_by_default() {
+        with_tables(&[PEOPLE], |database| {
+            fs::write("in.csv", "1,\"\"\n2,\n").unwrap();
+            execute(database, "COPY people FROM 'in.csv'");
+            let sql = "SELECT id FROM people WHERE name IS NULL";
+            assert_eq!(ints(database, sql), [2]);
+            assert_eq!(ints(database, "SELECT id FROM people WHERE name = ''"), [1]);
+        });
+    }
+
+    #[test]
+    fn a_bad_line_is_reported_and_nothing_is_copied() {
+        with_tables(&[PEOPLE], |database| {
+            let cases = [
+                ("1,a\nx,b\n", 2, QueryError::TypeMismatch),
+                ("1,a\n\"\",b\n", 2, QueryError::TypeMismatch),
+                ("1,a\n2,b\n3\n", 3, QueryError::WrongNumberOfColumns),
+                ("1,a\n2,b,c\n", 2, QueryError::WrongNumberOfColumns),
+            ];
+            for (contents, expected, kind) in cases {
+                fs::write("in.csv", contents).unwrap();
+                let (line, e) = row_error(database, "COPY people FROM 'in.csv'");
+                assert_eq!(line, expected, "{contents:?}");
+                assert_eq!(format!("{e:?}"), format!("{kind:?}"));
+            }
+            let long = "x".repeat(5000);
+            fs::write("in.csv", format!("1,a\n2,{long}\n")).unwrap();
+            let (line, e) = row_error(database, "COPY people FROM 'in.csv'");
+            assert_eq!(line, 2);
+            assert!(matches!(
+                e,
+                QueryError::InsertRowError(InsertRowError::RowTooBig)
+            ));
+            fs::write("in.csv", "id,age\n1,2\n").unwrap();
+            let sql = "COPY people FROM 'in.csv' WITH (HEADER)";
+            let (line, e) = row_error(database, sql);
+            assert_eq!(line, 1);
+            assert!(matches!(e, QueryError::UnknownColumn));
+            assert_eq!(ints(database, "SELECT count(*) FROM people"), [0]);
+        });
+    }
+
+    #[test]
+    fn missing_files_and_tables() {
+        with_tables(&[PEOPLE], |database| {
+            let e = error(database, "COPY people FROM 'nope.csv'");
+            assert!(matches!(e, QueryError::IOError(_)));
+            fs::write("in.csv", "1,a\n").unwrap();
+            let e = error(database, "COPY nope FROM 'in.csv'");
+            assert!(matches!(e, QueryError::UnknownTable));
+        });
+    }
+}
@@ -0,0 +1,352 @@
+use crate::internal::{
+    errors,
+    expression::{parse_value, resolve, truthy},
+    join::{is_equi_join, using_columns, JoinAlgorithm, JoinKind},
+    plan::Plan,
+    statistics::{ColumnStatistics, TableStatistics},
+    table::ColumnValue,
+};
+use sqlparser::ast::{BinaryOperator, Expr, JoinConstraint, SetOperator, UnaryOperator};
+
+/// How many rows a table that was never analyzed is assumed to have.
+static DEFAULT_ROWS: f64 = 1000.0;
+/// The fractions of rows conditions are assumed to keep when the statistics
+/// can't tell.
+static EQUALITY_SELECTIVITY: f64 = 0.1;
+static RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+static DEFAULT_SELECTIVITY: f64 = 0.25;
+/// How many pairs of rows a nested loop compares in the time a hash join
+/// takes to hash one row.
+static HASH_COST: f64 = 2.0;
+
+/// A column read straight from a table that was analyzed.
+type ColumnSource<'a> = (&'a TableStatistics, &'a ColumnStatistics);
+
+/// Estimates how many rows `plan` produces.
+pub fn estimate_rows(plan: &Plan, statistics: &[TableStatistics]) -> f64 {
+    let estimate = |plan: &Plan| estimate_rows(plan, statistics);
+    match plan {
+        Plan::Scan { table, filter, .. } => {
+            let rows = statistics
+                .iter()
+                .find(|s| s.table == *table)
+                .map_or(DEFAULT_ROWS, |s| s.rows as f64);
+            match filter {
+                Some(filter) => rows * selectivity(filter, plan, statistics),
+                None => rows,
+            }
+        }
+        Plan::Values { result, .. } => result.rows.len() as f64,
+        Plan::Derived { input, .. }
+        | Plan::Window { input, .. }
+        | Plan::Sort { input, .. }
+        | Plan::Project { input, .. }
+        | Plan::Distinct { input } => estimate(input),
+        Plan::Filter { input, predicate } => {
+            estimate(input) * selectivity(predicate, input, statistics)
+        }
+        Plan::Join {
+            left, right, kind, ..
+        } => {
+            let (left_rows, right_rows) = (estimate(left), estimate(right));
+            let matched = left_rows * right_rows * join_selectivity(plan, statistics);
+            // outer joins keep every row of their outer sides
+            match kind {
+                JoinKind::Inner => matched,
+                JoinKind::Left => matched.max(left_rows),
+                JoinKind::Right => matched.max(right_rows),
+                JoinKind::Full => matched.max(left_rows).max(right_rows),
+            }
+        }
+        Plan::Aggregate {
+            input, group_by, ..
+        } => {
+            let rows = estimate(input);
+            if group_by.is_empty() {
+                return 1.0;
+            }
+            let groups: f64 = group_by
+                .iter()
+                .map(|key| match column_source(key, input, statistics) {
+                    Some((_, column)) => column.distinct.max(1) as f64,
+                    None => rows * EQUALITY_SELECTIVITY,
+                })
+                .product();
+            groups.min(rows)
+        }
+        Plan::Limit {
+            input,
+            offset,
+            limit,
+        } => {
+            let rows = (estimate(input) - *offset as f64).max(0.0);
+            limit.map_or(rows, |limit| rows.min(limit as f64))
+        }
+        Plan::SetOperation {
+            op, left, right, ..
+        } => match op {
+            SetOperator::Union => estimate(left) + estimate(right),
+            SetOperator::Intersect => estimate(left).min(estimate(right)),
+            SetOperator::Except => estimate(left),
+        },
+    }
+}
+
+/// Chooses how to join `left` and `right`. A hash join needs equalities
+/// between the two sides to use as keys, and is only worth building when
+/// there are more pairs of rows than a nested loop can compare in that time.
+pub fn join_algorithm(
+    left: &Plan,
+    right: &Plan,
+    constraint: &JoinConstraint,
+    statistics: &[TableStatistics],
+) -> Result<JoinAlgorithm, errors::QueryError> {
+    let (left_fields, right_fields) = (left.schema()?.fields, right.schema()?.fields);
+    if !is_equi_join(&left_fields, &right_fields, constraint) {
+        return Ok(JoinAlgorithm::NestedLoop);
+    }
+    let left_rows = estimate_rows(left, statistics);
+    let right_rows = estimate_rows(right, statistics);
+    Ok(
+        if left_rows * right_rows <= HASH_COST * (left_rows + right_rows) {
+            JoinAlgorithm::NestedLoop
+        } else {
+            JoinAlgorithm::Hash
+        },
+    )
+}
+
+/// Estimates the fraction of the rows of `plan` for which `predicate` holds.
+pub fn selectivity(predicate: &Expr, plan: &Plan, statistics: &[TableStatistics]) -> f64 {
+    let column = |expr: &Expr| column_source(expr, plan, statistics);
+    let selectivity = |expr: &Expr| selectivity(expr, plan, statistics);
+    if let Some(value) = constant(predicate) {
+        return if truthy(&value) { 1.0 } else { 0.0 };
+    }
+    match predicate {
+        Expr::Nested(expr) => selectivity(expr),
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => 1.0 - selectivity(expr),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => selectivity(left) * selectivity(right),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => {
+            let (left, right) = (selectivity(left), selectivity(right));
+            left + right - left * right
+        }
+        Expr::BinaryOp { left, op, right } => match (constant(left), constant(right)) {
+            (None, Some(value)) => comparison(column(left), op, &value),
+            (Some(value), None) => comparison(column(right), &flipped(op), &value),
+            (None, None) if *op == BinaryOperator::Eq => equal_columns(column(left), column(right)),
+            _ => comparison(None, op, &ColumnValue::Null),
+        },
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let between = match (column(expr), constant(low), constant(high)) {
+                (Some((table, column)), Some(low), Some(high)) => {
+                    let inside = column.fraction_below(&high) - column.fraction_below(&low)
+                        + 1.0 / column.distinct.max(1) as f64;
+                    (1.0 - table.null_fraction(column)) * inside.clamp(0.0, 1.0)
+                }
+                _ => DEFAULT_SELECTIVITY,
+            };
+            if *negated {
+                1.0 - between
+            } else {
+                between
+            }
+        }
+        Expr::IsNull(expr) => null_fraction(column(expr)),
+        Expr::IsNotNull(expr) => 1.0 - null_fraction(column(expr)),
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let source = column(expr);
+            let any: f64 = list
+                .iter()
+                .map(|item| match constant(item) {
+                    Some(value) => comparison(source, &BinaryOperator::Eq, &value),
+                    None => EQUALITY_SELECTIVITY,
+                })
+                .sum();
+            if *negated {
+                1.0 - any.min(1.0)
+            } else {
+                any.min(1.0)
+            }
+        }
+        _ => DEFAULT_SELECTIVITY,
+    }
+}
+
+/// Estimates the fraction of the pairs of rows of a join's two sides that match.
+fn join_selectivity(join: &Plan, statistics: &[TableStatistics]) -> f64 {
+    let Plan::Join {
+        left,
+        right,
+        constraint,
+        ..
+    } = join
+    else {
+        return 1.0;
+    };
+    match constraint {
+        JoinConstraint::On(on) => selectivity(on, join, statistics),
+        JoinConstraint::None => 1.0,
+        JoinConstraint::Using(_) | JoinConstraint::Natural => {
+            let (Ok(left_schema), Ok(right_schema)) = (left.schema(), right.schema()) else {
+                return 1.0;
+            };
+            let source = |plan: &Plan, fields, name| {
+                let index = resolve(fields, std::slice::from_ref(name)).ok()?;
+                field_source(plan, index, statistics)
+            };
+            using_columns(&left_schema.fields, &right_schema.fields, constraint)
+                .iter()
+                .map(|name| {
+                    equal_columns(
+                        source(left, &left_schema.fields, name),
+                        source(right, &right_schema.fields, name),
+                    )
+                })
+                .product()
+        }
+    }
+}
+
+/// The fraction of rows for which `column op value` holds.
+fn comparison(column: Option<ColumnSource>, op: &BinaryOperator, value: &ColumnValue) -> f64 {
+    let Some((table, column)) = column else {
+        return match op {
+            BinaryOperator::Eq => EQUALITY_SELECTIVITY,
+            BinaryOperator::NotEq => 1.0 - EQUALITY_SELECTIVITY,
+            BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq => RANGE_SELECTIVITY,
+            _ => DEFAULT_SELECTIVITY,
+        };
+    };
+    if *value == ColumnValue::Null {
+        return 0.0;
+    }
+    let not_null = 1.0 - table.null_fraction(column);
+    let equal = if column.covers(value) {
+        1.0 / column.distinct.max(1) as f64
+    } else {
+        0.0
+    };
+    let below = column.fraction_below(value);
+    not_null
+        * match op {
+            BinaryOperator::Eq => equal,
+            BinaryOperator::NotEq => 1.0 - equal,
+            BinaryOperator::Lt => below,
+            BinaryOperator::LtEq => (below + equal).min(1.0),
+            BinaryOperator::Gt => (1.0 - below - equal).max(0.0),
+            BinaryOperator::GtEq => 1.0 - below,
+            _ => return DEFAULT_SELECTIVITY,
+        }
+}
+
+/// `a op b` is `b op' a`.
+fn flipped(op: &BinaryOperator) -> BinaryOperator {
+    match op {
+        BinaryOperator::Lt => BinaryOperator::Gt,
+        BinaryOperator::LtEq => BinaryOperator::GtEq,
+        BinaryOperator::Gt => BinaryOperator::Lt,
+        BinaryOperator::GtEq => BinaryOperator::LtEq,
+        op => op.clone(),
+    }
+}
+
+/// The fraction of pairs of values of two columns that are equal, assuming the
+/// values of the column with fewer of them all appear in the other one.
+fn equal_columns(left: Option<ColumnSource>, right: Option<ColumnSource>) -> f64 {
+    if left.is_none() && right.is_none() {
+        return EQUALITY_SELECTIVITY;
+    }
+    let (mut selectivity, mut distinct) = (1.0, 1);
+    for (table, column) in left.into_iter().chain(right) {
+        selectivity *= 1.0 - table.null_fraction(column);
+        distinct = distinct.max(column.distinct);
+    }
+    selectivity / distinct as f64
+}
+
+fn null_fraction(column: Option<ColumnSource>) -> f64 {
+    match column {
+        Some((table, column)) => table.null_fraction(column),
+        None => EQUALITY_SELECTIVITY,
+    }
+}
+
+fn constant(expr: &Expr) -> Option<ColumnValue> {
+    match expr {
+        Expr::Value(value) => parse_value(value).ok(),
+        _ => None,
+    }
+}
+
+/// The analyzed table column `expr` reads, when it names one of the fields of
+/// `plan` that come straight from a table.
+fn column_source<'a>(
+    expr: &Expr,
+    plan: &Plan,
+    statistics: &'a [TableStatistics],
+) -> Option<ColumnSource<'a>> {
+    let name = match expr {
+        Expr::Identifier(ident) => std::slice::from_ref(ident),
+        Expr::CompoundIdentifier(idents) => idents,
+        Expr::Nested(expr) => return column_source(expr, plan, statistics),
+        _ => return None,
+    };
+    let index = resolve(&plan.schema().ok()?.fields, name).ok()?;
+    field_source(plan, index, statistics)
+}
+
+/// Follows the field at `index` of `plan` down to the table column it was read from.
+fn field_source<'a>(
+    plan: &Plan,
+    index: usize,
+    statistics: &'a [TableStatistics],
+) -> Option<ColumnSource<'a>> {
+    match plan {
+        Plan::Scan { table, fields, .. } => {
+            let table = statistics.iter().find(|s| s.table == *table)?;
+            Some((table, table.column(&fields.get(index)?.column.name)?))
+        }
+        Plan::Derived { input, .. }
+        | Plan::Filter { input, .. }
+        | Plan::Window { input, .. }
+        | Plan::Sort { input, .. }
+        | Plan::Limit { input, .. }
+        | Plan::Distinct { input } => field_source(input, index, statistics),
+        Plan::Project { input, exprs, .. } => column_source(exprs.get(index)?, input, statistics),
+        // USING and NATURAL joins put the columns they merge first
+        Plan::Join {
+            left,
+            right,
+            constraint: JoinConstraint::On(_) | JoinConstraint::None,
+            ..
+        } => match index.checked_sub(left.schema().ok()?.fields.len()) {
+            None => field_source(left, index, statistics),
+            Some(index) => field_source(right, index, statistics),
+        },
+        _ => None,
+    }
+}
@@ -1,119 +1,377 @@
 use crate::internal::{
+    copy::{copy_from, copy_to, CopyOptions},
     errors,
-    table::{Column, ColumnType, ColumnValue, QueryResult, Table},
+    explain::explain,
+    expression::{evaluate, parse_value, Context, Scope},
+    function::Functions,
+    journal::Journal,
+    lock::{Lock, LockLevel},
+    operator::Operator,
+    optimizer::optimize,
+    plan::{limit_and_offset, plan, rename_columns, subquery},
+    schema::{describe, show_tables},
+    statement::Statement as PreparedStatement,
+    statistics::TableStatistics,
+    table::{Column, ColumnType, ColumnValue, QueryResult, ResultSet, Table},
 };
 use serde::{Deserialize, Serialize};
 use serde_json;
-use sqlparser::ast::{SetExpr, Statement};
+use sqlparser::ast::{
+    AlterTableOperation, ColumnOption, CopySource, CopyTarget, Cte, DataType, Expr, Ident,
+    ObjectName, Query, SetExpr, SetOperator, SetQuantifier, Statement,
+};
 use sqlparser::dialect::GenericDialect;
+use sqlparser::keywords::Keyword;
 use sqlparser::parser::Parser;
-use std::{fs, io};
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
+use std::{collections::HashSet, fs, io, path::Path, rc::Rc, time::Duration};
+
+static CATALOG_FILE: &str = "database.json";
+static DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// The largest parameter number a statement may use, like in SQLite.
+static MAX_PARAMETER: usize = 32766;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Database {
     tables: Vec<Table>,
+    /// What the last `ANALYZE` of each table found.
+    #[serde(default)]
+    statistics: Vec<TableStatistics>,
+    #[serde(skip)]
+    lock: Lock,
+    #[serde(skip, default = "default_busy_timeout")]
+    busy_timeout: Duration,
+    #[serde(skip)]
+    pub(crate) functions: Functions,
+    /// The catalog as last read, to tell when another statement changed it.
+    #[serde(skip)]
+    catalog: String,
+    /// Counts the changes to the catalog, so prepared statements know when
+    /// the operators they planned are out of date.
+    #[serde(skip)]
+    catalog_version: u64,
+}
+
+fn default_busy_timeout() -> Duration {
+    DEFAULT_BUSY_TIMEOUT
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Database {
     pub fn new() -> Database {
-        Database { tables: Vec::new() }
+        Database {
+            tables: Vec::new(),
+            statistics: Vec::new(),
+            lock: Lock::default(),
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            functions: Functions::default(),
+            catalog: String::new(),
+            catalog_version: 0,
+        }
+    }
+    /// Sets how long to wait for other processes to release their locks
+    /// before giving up with `QueryError::DatabaseBusy`.
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.busy_timeout = timeout;
     }
-    pub fn create_table(&mut self, name: &String, columns: Vec<Column>) -> Result<(), io::Error> {
-        let table = Table::new(name.clone(), columns);
-        table.save()?;
+    pub fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
+    /// Registers a scalar function taking `arity` arguments, callable by
+    /// `name` from any expression in later queries.
+    pub fn create_function(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[ColumnValue]) -> Result<ColumnValue, errors::QueryError> + 'static,
+    ) {
+        self.functions.create_function(name, arity, function);
+    }
+    /// Registers an aggregate function: every group starts from `init()`, is
+    /// updated by `step` with each row's arguments and produces `finalize(state)`.
+    pub fn create_aggregate<S: 'static>(
+        &mut self,
+        name: &str,
+        init: impl Fn() -> S + 'static,
+        step: impl Fn(&mut S, &[ColumnValue]) -> Result<(), errors::QueryError> + 'static,
+        finalize: impl Fn(S) -> Result<ColumnValue, errors::QueryError> + 'static,
+    ) {
+        self.functions.create_aggregate(name, init, step, finalize);
+    }
+    pub fn create_table(
+        &mut self,
+        name: &str,
+        columns: Vec<Column>,
+    ) -> Result<(), errors::QueryError> {
+        self.lock(LockLevel::Exclusive)?;
+        let result = self.create_table_locked(name, columns);
+        self.unlock()?;
+        result
+    }
+    fn create_table_locked(
+        &mut self,
+        name: &str,
+        columns: Vec<Column>,
+    ) -> Result<(), errors::QueryError> {
+        if self.tables.iter().any(|t| t.name == name) {
+            return Err(errors::QueryError::TableAlreadyExists);
+        }
+        let table = Table::new(name.to_string(), columns);
+        table.save().map_err(errors::QueryError::IOError)?;
         self.tables.push(table);
         self.save();
         Ok(())
     }
-    pub fn save(&self) -> () {
+    pub fn save(&self) {
         let serialized = serde_json::to_string(&self).unwrap();
-        fs::write(format!("database.json"), serialized).unwrap();
-        ()
+        fs::write(CATALOG_FILE, serialized).unwrap();
+    }
+    /// Opens the database in the current directory. Fails with
+    /// `QueryError::DatabaseBusy` if a writer holds it for longer than the
+    /// busy timeout.
+    pub fn load() -> Result<Database, errors::QueryError> {
+        let mut database = Database::new();
+        if Journal::is_hot() {
+            database
+                .lock
+                .acquire(LockLevel::Exclusive, database.busy_timeout)?;
+            let recovered = database.recover();
+            database.unlock()?;
+            recovered.map_err(errors::QueryError::IOError)?;
+        }
+        database.lock(LockLevel::Shared)?;
+        database.unlock()?;
+        Ok(database)
+    }
+    /// Undoes the transaction of a process that stopped before ending it, from
+    /// the journal it left behind, like SQLite does with a hot journal.
+    fn recover(&mut self) -> Result<(), io::Error> {
+        // a catalog cut short while it was being put back only names tables
+        // that were deleted before it, so it can be left unread
+        let tables = match self.reload() {
+            Ok(()) => &self.tables[..],
+            Err(_) => &[],
+        };
+        Journal::recover(CATALOG_FILE, tables)
     }
-    pub fn load() -> Database {
-        let metadata = fs::read_to_string("database.json").unwrap();
-        let metadata: Database = serde_json::from_str(&metadata).unwrap();
-        metadata
+    /// Takes the process-wide lock at `level` and re-reads the catalog,
+    /// since another process may have changed it since we last looked.
+    fn lock(&mut self, level: LockLevel) -> Result<(), errors::QueryError> {
+        self.lock.acquire(level, self.busy_timeout)?;
+        if let Err(e) = self.reload() {
+            self.lock.release()?;
+            return Err(errors::QueryError::IOError(e));
+        }
+        Ok(())
+    }
+    fn unlock(&mut self) -> Result<(), errors::QueryError> {
+        self.lock.release()?;
+        Ok(())
+    }
+    fn reload(&mut self) -> Result<(), io::Error> {
+        // there is no catalog until the first table is created, or once a
+        // transaction that created it was undone
+        let catalog = match Path::new(CATALOG_FILE).exists() {
+            true => fs::read_to_string(CATALOG_FILE)?,
+            false => String::new(),
+        };
+        if catalog.is_empty() {
+            self.tables.clear();
+            self.statistics.clear();
+        } else {
+            let metadata: Database = serde_json::from_str(&catalog)?;
+            self.tables = metadata.tables;
+            self.statistics = metadata.statistics;
+        }
+        if catalog != self.catalog {
+            self.catalog = catalog;
+            self.catalog_version += 1;
+        }
+        Ok(())
     }
     pub fn query(&mut self, sql: String) -> Result<QueryResult, errors::QueryError> {
-        let dialect = GenericDialect {}; // or AnsiDialect, or your own dialect ...
-
-        let statements = Parser::parse_sql(&dialect, &sql).unwrap();
-        let first = statements.first().unwrap();
+        let (statements, _) = tokenize(&sql)?;
+        let first = statements.into_iter().next();
+        let first = parse(first.ok_or(errors::QueryError::SyntaxError)?)?;
+        self.run(&first)
+    }
+    /// Takes the lock `statement` needs for as long as it runs.
+    fn run(&mut self, statement: &Statement) -> Result<QueryResult, errors::QueryError> {
+        self.lock(lock_level(statement))?;
+        let result = self.execute(statement, &[]);
+        self.unlock()?;
+        result
+    }
+    /// Runs every statement of `sql` in order, such as the statements of a
+    /// migration file, and returns what each of them did. Stops at the first
+    /// one that fails, leaving the ones before it done.
+    pub fn execute_batch(&mut self, sql: &str) -> Result<Vec<QueryResult>, errors::BatchError> {
+        let (statements, _) =
+            tokenize(sql).map_err(|error| errors::BatchError { index: 0, error })?;
+        let mut results = Vec::new();
+        for (index, tokens) in statements.into_iter().enumerate() {
+            let result = parse(tokens).and_then(|statement| self.run(&statement));
+            results.push(result.map_err(|error| errors::BatchError { index, error })?);
+        }
+        Ok(results)
+    }
+    /// Like `execute_batch`, but runs the statements as one transaction: the
+    /// database stays locked until all of them ran, and when one fails, what
+    /// the ones before it did is undone. `BEGIN` and `COMMIT` statements in
+    /// `sql` do nothing.
+    pub fn execute_batch_in_transaction(
+        &mut self,
+        sql: &str,
+    ) -> Result<Vec<QueryResult>, errors::BatchError> {
+        let (statements, _) =
+            tokenize(sql).map_err(|error| errors::BatchError { index: 0, error })?;
+        let count = statements.len();
+        let at = |index| move |error| errors::BatchError { index, error };
+        self.lock(LockLevel::Exclusive).map_err(at(0))?;
+        let journal = match Journal::begin(CATALOG_FILE, &self.tables) {
+            Ok(journal) => journal,
+            Err(e) => {
+                self.unlock().map_err(at(0))?;
+                return Err(at(0)(errors::QueryError::IOError(e)));
+            }
+        };
+        let mut results = Vec::new();
+        let mut failure = None;
+        for (index, tokens) in statements.into_iter().enumerate() {
+            let result = parse(tokens).and_then(|statement| match statement {
+                // the script already runs as one transaction, as migration
+                // files that begin and commit their own expect
+                Statement::StartTransaction { .. } | Statement::Commit { .. } => {
+                    Ok(QueryResult::TransactionSucceeded)
+                }
+                statement => self.execute(&statement, &[]),
+            });
+            match result {
+                Ok(result) => results.push(result),
+                Err(error) => {
+                    failure = Some(errors::BatchError { index, error });
+                    break;
+                }
+            }
+        }
+        let ended = match failure {
+            None => journal.commit(),
+            Some(_) => journal
+                .rollback(CATALOG_FILE, &self.tables)
+                .and_then(|()| self.reload()),
+        };
+        let unlocked = self.unlock();
+        // failing to end the transaction is reported for the statement that
+        // failed, or past the last one
+        let index = failure.as_ref().map_or(count, |failure| failure.index);
+        ended.map_err(|e| at(index)(errors::QueryError::IOError(e)))?;
+        unlocked.map_err(at(index))?;
+        match failure {
+            Some(failure) => Err(failure),
+            None => Ok(results),
+        }
+    }
+    /// Parses the statement `sql` to be run later, any number of times, with
+    /// the values bound to its parameters. Unlike `execute_batch`, `sql` must
+    /// hold a single statement.
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement, errors::QueryError> {
+        let (statements, names) = tokenize(sql)?;
+        let [statement] =
+            <[_; 1]>::try_from(statements).map_err(|_| errors::QueryError::SyntaxError)?;
+        Ok(PreparedStatement::new(parse(statement)?, names))
+    }
+    /// Runs a prepared statement. A query reuses the operators planned by its
+    /// last run, unless the catalog changed since.
+    pub(crate) fn execute_prepared(
+        &mut self,
+        prepared: &mut PreparedStatement,
+    ) -> Result<QueryResult, errors::QueryError> {
+        self.lock(lock_level(&prepared.statement))?;
+        let result = match &prepared.statement {
+            Statement::Query(query) => self
+                .select_prepared(query, &prepared.parameters, &mut prepared.operator)
+                .map(QueryResult::Rows),
+            statement => self.execute(statement, &prepared.parameters),
+        };
+        self.unlock()?;
+        result
+    }
+    fn execute(
+        &mut self,
+        first: &Statement,
+        parameters: &[ColumnValue],
+    ) -> Result<QueryResult, errors::QueryError> {
         // match select statement
         match first {
-            Statement::Query(query) => match *query.body.clone() {
-                SetExpr::Select(select) => {
-                    let mut selected_columns = Vec::new();
-                    let table_name = match &select.from[0].relation {
-                        sqlparser::ast::TableFactor::Table { name, .. } => name.to_string(),
-                        _ => todo!("only simple selects are implemented"),
-                    };
-                    let table = &self.tables.iter().find(|t| t.name == table_name);
-                    match table {
-                        None => return Err(errors::QueryError::UnknownTable),
-                        Some(table) => {
-                            for projection in select.projection {
-                                let column = projection;
-                                if let sqlparser::ast::SelectItem::UnnamedExpr(expr) = column {
-                                    selected_columns.push(expr.to_string());
-                                    if selected_columns.len() > table.columns.len() {
-                                        return Err(errors::QueryError::UnknownColumn);
-                                    }
-                                    let known_columns: Vec<_> = selected_columns
-                                        .iter()
-                                        .filter(|c| {
-                                            let column =
-                                                table.columns.iter().find(|col| col.name == **c);
-                                            match column {
-                                                Some(_) => true,
-                                                None => false,
-                                            }
-                                        })
-                                        .collect();
-                                    // println!("known_columns = {:?}", known_columns);
-                                    // println!("selected_columns = {:?}", selected_columns);
-                                    if known_columns.len() != selected_columns.len() {
-                                        return Err(errors::QueryError::UnknownColumn);
-                                    }
-                                } else if let sqlparser::ast::SelectItem::Wildcard(_) = column {
-                                    selected_columns = table
-                                        .columns
-                                        .iter()
-                                        .map(|c| {
-                                            return c.name.clone();
-                                        })
-                                        .collect();
-                                } else {
-                                    todo!("Not implemented!");
-                                }
-                                // match column {
-                                //     sqlparser::ast::SelectItem::UnnamedExpr(expr) => {}
-                                //     // sqlparser::ast::SelectItem::Wildcard(expr) => {
-                                //     //     let name = String::from("*");
-                                //     //     let column = Column {
-                                //     //         name,
-                                //     //         _type: ColumnType::Int,
-                                //     //     };
-                                //     //     columns.push(column);
-                                //     // }
-                                //     // sqlparser::ast::SelectItem::ExprWithAlias { expr, alias } => {
-                                //     //     let name = alias.value.clone();
-                                //     //     let column = Column {
-                                //     //         name,
-                                //     //         _type: ColumnType::Int,
-                                //     //     };
-                                //     //     columns.push(column);
-                                //     // }
-                                //     _ => todo!("not implemented"),
-                                // };
-                            }
-                            let r = table.select(&selected_columns);
-                            return Ok(QueryResult::Rows(r));
-                        }
-                    };
-                }
-                _ => panic!("no"),
+            Statement::Query(query) => Ok(QueryResult::Rows(self.select(query, parameters)?)),
+            Statement::Explain {
+                analyze, statement, ..
+            } => match &**statement {
+                Statement::Query(query) => Ok(QueryResult::Rows(
+                    self.explain(query, *analyze, parameters)?,
+                )),
+                _ => Err(errors::QueryError::UnkownOperation),
             },
+            Statement::Copy {
+                source,
+                to,
+                target,
+                options,
+                legacy_options,
+                ..
+            } => {
+                let CopyTarget::File { filename } = target else {
+                    return Err(errors::QueryError::UnkownOperation);
+                };
+                let options = CopyOptions::new(options, legacy_options)?;
+                let rows = match (source, to) {
+                    (
+                        CopySource::Table {
+                            table_name,
+                            columns,
+                        },
+                        false,
+                    ) => {
+                        let table = self.table(&table_name.to_string());
+                        let table = table.ok_or(errors::QueryError::UnknownTable)?;
+                        copy_from(table, columns, filename, &options)?
+                    }
+                    (
+                        CopySource::Table {
+                            table_name,
+                            columns,
+                        },
+                        true,
+                    ) => {
+                        let table = self.table(&table_name.to_string());
+                        let table = table.ok_or(errors::QueryError::UnknownTable)?;
+                        copy_to(&table_rows(table, columns)?, filename, &options)?
+                    }
+                    (CopySource::Query(query), _) => {
+                        copy_to(&self.select(query, parameters)?, filename, &options)?
+                    }
+                };
+                Ok(QueryResult::CopySucceeded(rows))
+            }
+            Statement::ShowTables { filter, .. } => {
+                Ok(QueryResult::Rows(show_tables(self, filter)?))
+            }
+            Statement::ExplainTable { table_name, .. } => {
+                let table = self.table(&table_name.to_string());
+                let table = table.ok_or(errors::QueryError::UnknownTable)?;
+                Ok(QueryResult::Rows(describe(table, &None)?))
+            }
+            Statement::ShowColumns {
+                table_name, filter, ..
+            } => {
+                let table = self.table(&table_name.to_string());
+                let table = table.ok_or(errors::QueryError::UnknownTable)?;
+                Ok(QueryResult::Rows(describe(table, filter)?))
+            }
             // ! all columns must be inserted in order!
             Statement::Insert {
                 table_name,
@@ -127,21 +385,13 @@ impl Database {
                 match table {
                     None => return Err(errors::QueryError::UnknownTable),
                     Some(table) => {
-                        println!("columns to insert {:?}", columns);
-                        let column_names: Vec<String> = columns
-                            .iter()
-                            .map(|c| {
-                                return c.value.to_string();
-                            })
-                            .collect();
+                        let column_names: Vec<String> =
+                            columns.iter().map(|c| c.value.to_string()).collect();
                         let existing_columns: Vec<_> = column_names
                             .iter()
                             .filter(|c| {
                                 let column = table.columns.iter().find(|col| col.name == **c);
-                                match column {
-                                    Some(_) => true,
-                                    None => false,
-                                }
+                                column.is_some()
                             })
                             .collect();
                         // insert statement must containt all columns
@@ -150,110 +400,1146 @@ impl Database {
                         }
                     }
                 }
-                println!("source {}", source);
                 match *source.body.clone() {
                     sqlparser::ast::SetExpr::Values(values) => {
-                        let values = values.rows[0].clone();
-                        println!("values {:?}", values);
-                        let mut inserted_row: Vec<ColumnValue> = Vec::new();
-                        for v in values {
-                            if let sqlparser::ast::Expr::Value(val) = v {
-                                // let parsed_value = parse_value(val.to_string());
-                                // inserted_row.push(parsed_value);
-                                match val {
-                                    sqlparser::ast::Value::Number(val, _) => {
-                                        // ! should handle floats too
-                                        let parsed = val.parse::<i64>();
-                                        if let Err(_) = parsed {
-                                            return Err(errors::QueryError::InsertRowError(
-                                                errors::InsertRowError::UnmatchingType,
-                                            ));
-                                        } else {
-                                            inserted_row.push(ColumnValue::Int(parsed.unwrap()))
-                                        }
-                                    }
-                                    sqlparser::ast::Value::SingleQuotedString(val) => {
-                                        inserted_row.push(ColumnValue::Text(val.to_string()));
-                                    }
-                                    _ => todo!("type"),
-                                }
-                            } else {
-                                panic!("shouldn't be here")
-                            }
+                        let table = table.unwrap();
+                        let context = Context::new(self, None).with_parameters(parameters);
+                        let scope = Scope::new(&[], &[]).within(Some(&context));
+                        // every row is checked before any is written, so a row
+                        // that doesn't fit inserts none of them
+                        let mut inserted_rows = Vec::new();
+                        for row in &values.rows {
+                            let inserted_row = row
+                                .iter()
+                                .map(|expr| evaluate(expr, &scope))
+                                .collect::<Result<Vec<_>, _>>()?;
+                            table
+                                .row_size(&inserted_row)
+                                .map_err(errors::QueryError::InsertRowError)?;
+                            inserted_rows.push(inserted_row);
                         }
-                        let result = table.unwrap().insert_row(inserted_row);
-                        if let Err(e) = result {
-                            return Err(errors::QueryError::InsertRowError(e));
-                        } else {
-                            return Ok(QueryResult::InsertRowSucceeded);
+                        for inserted_row in inserted_rows {
+                            table
+                                .insert_row(inserted_row)
+                                .map_err(errors::QueryError::InsertRowError)?;
                         }
+                        Ok(QueryResult::InsertRowSucceeded)
                     }
-                    _ => panic!("Shouldn't reach here"),
+                    // INSERT ... SELECT
+                    _ => Err(errors::QueryError::UnkownOperation),
                 }
             }
             Statement::CreateTable {
                 name,
                 columns,
-                constraints,
+                if_not_exists,
+                query,
                 ..
             } => {
                 let table_name = name.to_string();
+                if self.tables.iter().any(|t| t.name == table_name) {
+                    if *if_not_exists {
+                        return Ok(QueryResult::CreateTableSucceeded);
+                    }
+                    return Err(errors::QueryError::TableAlreadyExists);
+                }
                 // let mut columns: Vec<Column> = Vec::new();
-                println!("columns, {:?}", columns);
-                println!("constraints, {:?}", constraints);
                 let mut columns_to_create: Vec<Column> = Vec::new();
                 for column in columns {
-                    println!("column {}", column);
-                    let column_to_create_type = match &column.data_type {
-                        sqlparser::ast::DataType::Int(_) => ColumnType::Int,
-                        sqlparser::ast::DataType::Text => ColumnType::Text,
-                        __ => {
-                            println!("unexpected column type {:?}", __);
-                            todo!("not implemented")
-                        }
-                    };
+                    let column_to_create_type = column_type(&column.data_type)?;
                     columns_to_create.push(Column {
                         name: column.name.to_string(),
                         _type: column_to_create_type,
                     });
                 }
+                // CREATE TABLE ... AS SELECT takes its columns from the query
+                // unless they are spelled out
+                let mut rows = Vec::new();
+                if let Some(query) = query {
+                    let result = self.select(query, parameters)?;
+                    if columns_to_create.is_empty() {
+                        columns_to_create = result.columns;
+                    } else if columns_to_create.len() != result.columns.len() {
+                        return Err(errors::QueryError::InsertRowError(
+                            errors::InsertRowError::InsertedValuesDoNotMatchNumberOfTableColumns,
+                        ));
+                    }
+                    rows = result.rows;
+                }
                 let table = Table {
                     name: table_name,
                     columns: columns_to_create,
                 };
-                table.save().unwrap();
+                table.save().map_err(errors::QueryError::IOError)?;
+                for row in rows {
+                    if let Err(e) = table.insert_row(row) {
+                        table.drop().map_err(errors::QueryError::IOError)?;
+                        return Err(errors::QueryError::InsertRowError(e));
+                    }
+                }
                 self.tables.push(table);
                 self.save();
-                return Ok(QueryResult::CreateTableSucceeded);
+                Ok(QueryResult::CreateTableSucceeded)
             }
             Statement::Delete {
-                tables,
-                from,
-                selection,
-                ..
+                from, selection, ..
             } => {
-                println!("tables {:?}", tables);
-                println!("from {:?}", from);
-                println!("selection {:?}", selection);
                 let table_name = match &from[0].relation {
                     sqlparser::ast::TableFactor::Table { name, .. } => name.to_string(),
                     _ => panic!("not implemented"),
                 };
                 let table = &self.tables.iter().find(|t| t.name == table_name);
-                if let Some(table) = table {
+                if let Some(_table) = table {
                     match selection {
                         None => panic!("qweq"),
                         Some(body) => match body {
-                            sqlparser::ast::Expr::BinaryOp { left, op, right } => {
+                            sqlparser::ast::Expr::BinaryOp {
+                                left: _,
+                                op: _,
+                                right: _,
+                            } => {
                                 todo!("not implemented");
                             }
                             _ => panic!("no"),
                         },
                     }
                 }
-                return Err(errors::QueryError::UnknownTable);
+                Err(errors::QueryError::UnknownTable)
+            }
+            Statement::Drop {
+                object_type: sqlparser::ast::ObjectType::Table,
+                if_exists,
+                names,
+                ..
+            } => {
+                // a missing table fails the statement before any is dropped
+                let missing = names.iter().any(|name| {
+                    let name = name.to_string();
+                    !self.tables.iter().any(|t| t.name == name)
+                });
+                if missing && !*if_exists {
+                    return Err(errors::QueryError::UnknownTable);
+                }
+                for name in names {
+                    let table_name = name.to_string();
+                    let position = self.tables.iter().position(|t| t.name == table_name);
+                    if let Some(position) = position {
+                        let table = self.tables.remove(position);
+                        table.drop().map_err(errors::QueryError::IOError)?;
+                        self.statistics.retain(|s| s.table != table_name);
+                        self.save();
+                    }
+                }
+                Ok(QueryResult::DropTableSucceeded)
+            }
+            Statement::AlterTable { name, operation } => {
+                self.alter_table(&name.to_string(), operation)?;
+                self.save();
+                Ok(QueryResult::AlterTableSucceeded)
+            }
+            // an unnamed table stands for all of them
+            Statement::Analyze { table_name, .. } if table_name.0.is_empty() => {
+                self.statistics = self.tables.iter().map(TableStatistics::compute).collect();
+                self.save();
+                Ok(QueryResult::AnalyzeSucceeded)
+            }
+            Statement::Analyze { table_name, .. } => {
+                let table_name = table_name.to_string();
+                let table = self
+                    .table(&table_name)
+                    .ok_or(errors::QueryError::UnknownTable)?;
+                let statistics = TableStatistics::compute(table);
+                self.statistics.retain(|s| s.table != table_name);
+                self.statistics.push(statistics);
+                self.save();
+                Ok(QueryResult::AnalyzeSucceeded)
+            }
+            _ => Err(errors::QueryError::UnkownOperation),
+        }
+    }
+    /// The tables of the database, as of the last statement.
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+    /// The stored table called `name`.
+    pub(crate) fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+    fn select(
+        &self,
+        query: &Query,
+        parameters: &[ColumnValue],
+    ) -> Result<ResultSet, errors::QueryError> {
+        self.select_in(query, &Context::new(self, None).with_parameters(parameters))
+    }
+    /// Runs the query of a prepared statement with the operators in `cached`,
+    /// when they were planned against the current catalog, or plans it anew.
+    fn select_prepared(
+        &self,
+        query: &Query,
+        parameters: &[ColumnValue],
+        cached: &mut Option<(u64, Operator)>,
+    ) -> Result<ResultSet, errors::QueryError> {
+        let context = Context::new(self, None).with_parameters(parameters);
+        if let Some((version, operator)) = cached {
+            if *version == self.catalog_version {
+                return operator.result(&context);
+            }
+        }
+        let operator = self.operator(query, &context)?;
+        // common tables run while planning and parameters read then, like a
+        // LIMIT's, become part of the plan, which then only fits this run
+        let reusable = context.bindings() == 0 && !context.parameters_read();
+        let result = operator.result(&context);
+        *cached = reusable.then_some((self.catalog_version, operator));
+        result
+    }
+    /// Runs a SELECT, as a subquery when `context` has an outer row.
+    pub(crate) fn select_in(
+        &self,
+        query: &Query,
+        context: &Context,
+    ) -> Result<ResultSet, errors::QueryError> {
+        // the query's common tables are only visible while it runs
+        let bindings = context.bindings();
+        let result = self
+            .operator(query, context)
+            .and_then(|operator| operator.result(context));
+        context.truncate_bindings(bindings);
+        // cached subquery results are keyed by the address of the subquery in
+        // the plan, which is gone now
+        context.clear_subqueries();
+        result
+    }
+    /// Plans `query` and chooses the operators that run it.
+    fn operator(&self, query: &Query, context: &Context) -> Result<Operator, errors::QueryError> {
+        let plan = optimize(plan(query, context)?, &self.functions, &self.statistics)?;
+        Operator::new(plan, &self.statistics)
+    }
+    /// Describes the operators that run `query`. With `analyze`, the query
+    /// runs too, and what each operator did is reported along.
+    fn explain(
+        &self,
+        query: &Query,
+        analyze: bool,
+        parameters: &[ColumnValue],
+    ) -> Result<ResultSet, errors::QueryError> {
+        let context = Context::new(self, None).with_parameters(parameters);
+        let mut operator = self.operator(query, &context)?;
+        if analyze {
+            operator.enable_profiling();
+            operator.result(&context)?;
+        }
+        Ok(explain(&operator, analyze))
+    }
+    /// Evaluates the common table expressions of a WITH clause, binding each
+    /// one before the next so later ones can read earlier ones.
+    pub(crate) fn bind_ctes(
+        &self,
+        query: &Query,
+        context: &Context,
+    ) -> Result<(), errors::QueryError> {
+        let Some(with) = &query.with else {
+            return Ok(());
+        };
+        for cte in &with.cte_tables {
+            let result = match &*cte.query.body {
+                SetExpr::SetOperation {
+                    op: SetOperator::Union,
+                    set_quantifier,
+                    left,
+                    right,
+                } if with.recursive => {
+                    let distinct = *set_quantifier != SetQuantifier::All;
+                    self.recursive_cte(cte, (left, right), distinct, context)?
+                }
+                _ => {
+                    let mut result = self.select_in(&cte.query, context)?;
+                    rename_columns(&mut result.columns, &cte.alias)?;
+                    result
+                }
+            };
+            context.bind(cte.alias.name.value.clone(), Rc::new(result));
+        }
+        Ok(())
+    }
+    /// Evaluates `anchor UNION [ALL] recursive` to a fixed point: the recursive
+    /// term runs over the rows the previous iteration added, until it adds none.
+    /// A LIMIT on the common table stops the iteration once enough rows were found.
+    fn recursive_cte(
+        &self,
+        cte: &Cte,
+        (anchor, recursive): (&SetExpr, &SetExpr),
+        distinct: bool,
+        context: &Context,
+    ) -> Result<ResultSet, errors::QueryError> {
+        let name = &cte.alias.name.value;
+        let mut result = self.select_in(&subquery(anchor), context)?;
+        rename_columns(&mut result.columns, &cte.alias)?;
+        let (offset, limit) = limit_and_offset(&cte.query, context)?;
+        let limit = limit.map(|limit| limit.saturating_add(offset));
+
+        let mut seen = HashSet::new();
+        if distinct {
+            result.rows.retain(|row| seen.insert(row.clone()));
+        }
+        let recursive = subquery(recursive);
+        let mut working = result.rows.clone();
+        while !working.is_empty() && limit.is_none_or(|limit| result.rows.len() < limit) {
+            let bindings = context.bindings();
+            context.bind(
+                name.clone(),
+                Rc::new(ResultSet {
+                    columns: result.columns.clone(),
+                    rows: working,
+                }),
+            );
+            // subqueries reading the working table see different rows each time
+            context.clear_subqueries();
+            let iteration = self.select_in(&recursive, context);
+            let is_recursive = context.last_binding_read();
+            context.truncate_bindings(bindings);
+            context.clear_subqueries();
+            working = iteration?.rows;
+            if distinct {
+                working.retain(|row| seen.insert(row.clone()));
+            }
+            result.rows.extend(working.iter().cloned());
+            if !is_recursive {
+                break;
+            }
+        }
+        result.rows = result.rows.into_iter().skip(offset).collect();
+        if let Some(limit) = limit {
+            result.rows.truncate(limit.saturating_sub(offset));
+        }
+        Ok(result)
+    }
+    fn alter_table(
+        &mut self,
+        table_name: &str,
+        operation: &AlterTableOperation,
+    ) -> Result<(), errors::QueryError> {
+        let position = self.tables.iter().position(|t| t.name == table_name);
+        let Some(position) = position else {
+            return Err(errors::QueryError::UnknownTable);
+        };
+        match operation {
+            AlterTableOperation::RenameTable {
+                table_name: new_table_name,
+            } => {
+                let new_name = new_table_name.to_string();
+                if self.tables.iter().any(|t| t.name == new_name) {
+                    return Err(errors::QueryError::TableAlreadyExists);
+                }
+                let table = &mut self.tables[position];
+                table
+                    .rename(&new_name)
+                    .map_err(errors::QueryError::IOError)?;
+                for statistics in &mut self.statistics {
+                    if statistics.table == table_name {
+                        statistics.table = new_name.clone();
+                    }
+                }
+            }
+            AlterTableOperation::RenameColumn {
+                old_column_name,
+                new_column_name,
+            } => {
+                let table = &mut self.tables[position];
+                if table
+                    .columns
+                    .iter()
+                    .any(|c| c.name == new_column_name.value)
+                {
+                    return Err(errors::QueryError::ColumnAlreadyExists);
+                }
+                let mut columns = table.columns.clone();
+                let column = columns
+                    .iter_mut()
+                    .find(|c| c.name == old_column_name.value)
+                    .ok_or(errors::QueryError::UnknownColumn)?;
+                column.name = new_column_name.value.clone();
+                table
+                    .rewrite(columns, table.rows())
+                    .map_err(errors::QueryError::InsertRowError)?;
+                let columns = self
+                    .statistics
+                    .iter_mut()
+                    .filter(|s| s.table == table_name)
+                    .flat_map(|s| s.columns.iter_mut());
+                for column in columns {
+                    if column.name == old_column_name.value {
+                        column.name = new_column_name.value.clone();
+                    }
+                }
+            }
+            AlterTableOperation::AddColumn {
+                if_not_exists,
+                column_def,
+                ..
+            } => {
+                let table = &mut self.tables[position];
+                if table
+                    .columns
+                    .iter()
+                    .any(|c| c.name == column_def.name.value)
+                {
+                    if *if_not_exists {
+                        return Ok(());
+                    }
+                    return Err(errors::QueryError::ColumnAlreadyExists);
+                }
+                let column = Column {
+                    name: column_def.name.value.clone(),
+                    _type: column_type(&column_def.data_type)?,
+                };
+                // existing rows get the column's default, or NULL when it has none
+                let mut default = ColumnValue::Null;
+                for option in &column_def.options {
+                    if let ColumnOption::Default(expr) = &option.option {
+                        default = match expr {
+                            Expr::Value(value) => parse_value(value)?,
+                            _ => return Err(errors::QueryError::SyntaxError),
+                        };
+                    }
+                }
+                match (&default, &column._type) {
+                    (ColumnValue::Int(_), ColumnType::Text)
+                    | (ColumnValue::Text(_), ColumnType::Int) => {
+                        return Err(errors::QueryError::InsertRowError(
+                            errors::InsertRowError::UnmatchingType,
+                        ))
+                    }
+                    _ => {}
+                }
+                let mut rows = table.rows();
+                for row in rows.iter_mut() {
+                    row.push(default.clone());
+                }
+                let mut columns = table.columns.clone();
+                columns.push(column);
+                table
+                    .rewrite(columns, rows)
+                    .map_err(errors::QueryError::InsertRowError)?;
             }
-            _ => panic!("Err(SelectRowError::UnkownOperation)"),
+            AlterTableOperation::DropColumn {
+                column_name,
+                if_exists,
+                ..
+            } => {
+                let table = &mut self.tables[position];
+                let index = table
+                    .columns
+                    .iter()
+                    .position(|c| c.name == column_name.value);
+                let Some(index) = index else {
+                    if *if_exists {
+                        return Ok(());
+                    }
+                    return Err(errors::QueryError::UnknownColumn);
+                };
+                if table.columns.len() == 1 {
+                    return Err(errors::QueryError::CannotDropOnlyColumn);
+                }
+                let mut rows = table.rows();
+                for row in rows.iter_mut() {
+                    row.remove(index);
+                }
+                let mut columns = table.columns.clone();
+                columns.remove(index);
+                table
+                    .rewrite(columns, rows)
+                    .map_err(errors::QueryError::InsertRowError)?;
+                for statistics in &mut self.statistics {
+                    if statistics.table == table_name {
+                        statistics.columns.retain(|c| c.name != column_name.value);
+                    }
+                }
+            }
+            _ => return Err(errors::QueryError::UnkownOperation),
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites SQLite's `x [NOT] GLOB pattern`, which the parser doesn't know, into
+/// `x [NOT] SIMILAR TO pattern`, which binds the same way and is evaluated as
+/// GLOB. `SIMILAR TO` itself isn't supported, so it can't be confused with it,
+/// but it is how the condition reads in unaliased column names. A `glob` word
+/// that doesn't follow an operand, as in `glob(pattern, text)`, is left alone.
+fn glob_operators(tokens: Vec<Token>) -> Result<Vec<Token>, errors::QueryError> {
+    let mut rewritten: Vec<Token> = Vec::new();
+    for token in tokens {
+        let Token::Word(word) = &token else {
+            rewritten.push(token);
+            continue;
+        };
+        let operator = word.quote_style.is_none() && follows_operand(&rewritten);
+        if operator && word.keyword == Keyword::SIMILAR {
+            return Err(errors::QueryError::UnkownOperation);
+        }
+        if operator && word.value.eq_ignore_ascii_case("glob") {
+            rewritten.push(Token::make_keyword("SIMILAR"));
+            rewritten.push(Token::Whitespace(Whitespace::Space));
+            rewritten.push(Token::make_keyword("TO"));
+        } else {
+            rewritten.push(token);
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Whether the last of `tokens`, skipping a `NOT`, can end an operand.
+fn follows_operand(tokens: &[Token]) -> bool {
+    let mut previous = tokens
+        .iter()
+        .rev()
+        .filter(|token| !matches!(token, Token::Whitespace(_)));
+    let mut last = previous.next();
+    if matches!(last, Some(Token::Word(word)) if word.keyword == Keyword::NOT) {
+        last = previous.next();
+    }
+    match last {
+        Some(Token::Word(word)) => matches!(
+            word.keyword,
+            Keyword::NoKeyword | Keyword::NULL | Keyword::TRUE | Keyword::FALSE | Keyword::END
+        ),
+        Some(token) => matches!(
+            token,
+            Token::Number(..)
+                | Token::SingleQuotedString(_)
+                | Token::DoubleQuotedString(_)
+                | Token::Placeholder(_)
+                | Token::RParen
+        ),
+        None => false,
+    }
+}
+
+/// The rows of `table`, with only `columns` if there are any.
+fn table_rows(table: &Table, columns: &[Ident]) -> Result<ResultSet, errors::QueryError> {
+    let mut indexes = Vec::new();
+    for column in columns {
+        let index = table.columns.iter().position(|c| c.name == column.value);
+        indexes.push(index.ok_or(errors::QueryError::UnknownColumn)?);
+    }
+    if indexes.is_empty() {
+        indexes = (0..table.columns.len()).collect();
+    }
+    Ok(ResultSet {
+        columns: indexes.iter().map(|i| table.columns[*i].clone()).collect(),
+        rows: table.scan_columns(&indexes).collect(),
+    })
+}
+
+/// Whether `statement` only reads the database, returning rows.
+pub(crate) fn returns_rows(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Query(_)
+            | Statement::Explain { .. }
+            | Statement::ShowTables { .. }
+            | Statement::ExplainTable { .. }
+            | Statement::ShowColumns { .. }
+    )
+}
+
+/// The lock a statement needs: readers share the database, writers don't.
+fn lock_level(statement: &Statement) -> LockLevel {
+    if returns_rows(statement) || matches!(statement, Statement::Copy { to: true, .. }) {
+        LockLevel::Shared
+    } else {
+        LockLevel::Exclusive
+    }
+}
+
+/// The tokens of each statement of a script, and the names of its parameters.
+type Script = (Vec<Vec<Token>>, Vec<Option<String>>);
+
+/// Splits `sql` into the tokens of each statement, numbering their parameters
+/// like SQLite: `?` takes the number after the largest one so far, `?N` and
+/// `$N` take N, and named parameters like `:name`, `@name` or `$name` take the
+/// next number the first time they appear. Every placeholder is rewritten to
+/// `?N`. Along with the statements come the names of the parameters, by number.
+fn tokenize(sql: &str) -> Result<Script, errors::QueryError> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|_| errors::QueryError::SyntaxError)?;
+    let tokens = glob_operators(tokens)?;
+    let mut statements = Vec::new();
+    let mut numbered = Vec::new();
+    let mut names: Vec<Option<String>> = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let placeholder = match token {
+            Token::Placeholder(placeholder) => placeholder,
+            Token::Colon | Token::AtSign if matches!(tokens.peek(), Some(Token::Word(_))) => {
+                let Some(Token::Word(word)) = tokens.next() else {
+                    unreachable!()
+                };
+                format!("{token}{}", word.value)
+            }
+            // the tokenizer reads `@name` as a word
+            Token::Word(word) if word.quote_style.is_none() && word.value.starts_with('@') => {
+                word.value
+            }
+            Token::SemiColon => {
+                statements.push(std::mem::take(&mut numbered));
+                continue;
+            }
+            token => {
+                numbered.push(token);
+                continue;
+            }
+        };
+        let number = match placeholder.split_at(1) {
+            ("?", "") => names.len() + 1,
+            ("?" | "$", number) if number.bytes().all(|b| b.is_ascii_digit()) => {
+                match number.parse::<usize>() {
+                    Ok(number) if (1..=MAX_PARAMETER).contains(&number) => number,
+                    _ => return Err(errors::QueryError::UnknownParameter),
+                }
+            }
+            _ => match names.iter().position(|n| *n == Some(placeholder.clone())) {
+                Some(index) => index + 1,
+                None => {
+                    names.push(Some(placeholder));
+                    names.len()
+                }
+            },
+        };
+        if names.len() < number {
+            names.resize(number, None);
+        }
+        numbered.push(Token::Placeholder(format!("?{number}")));
+    }
+    statements.push(numbered);
+    // comments are whitespace too
+    statements.retain(|tokens| tokens.iter().any(|t| !matches!(t, Token::Whitespace(_))));
+    Ok((statements, names))
+}
+
+/// Parses the tokens of a single statement.
+fn parse(tokens: Vec<Token>) -> Result<Statement, errors::QueryError> {
+    if let Some(statement) = parse_analyze(&tokens).or_else(|| parse_explain_query_plan(&tokens)) {
+        return Ok(statement);
+    }
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(&dialect).with_tokens(tokens);
+    let statement = parser
+        .parse_statement()
+        .map_err(|_| errors::QueryError::SyntaxError)?;
+    if parser.peek_token().token != Token::EOF {
+        return Err(errors::QueryError::SyntaxError);
+    }
+    Ok(statement)
+}
+
+/// Recognizes SQLite's `ANALYZE [table]`, which the parser only knows in
+/// Hive's `ANALYZE TABLE table` form. Without a table, the statement's table
+/// name is left empty.
+fn parse_analyze(tokens: &[Token]) -> Option<Statement> {
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(&dialect).with_tokens(tokens.to_vec());
+    if !parser.parse_keyword(Keyword::ANALYZE) || parser.parse_keyword(Keyword::TABLE) {
+        return None;
+    }
+    let end = |token: Token| matches!(token, Token::EOF | Token::SemiColon);
+    let table_name = if end(parser.peek_token().token) {
+        ObjectName(Vec::new())
+    } else {
+        parser.parse_object_name().ok()?
+    };
+    if !end(parser.peek_token().token) {
+        return None;
+    }
+    Some(Statement::Analyze {
+        table_name,
+        partitions: None,
+        for_columns: false,
+        columns: Vec::new(),
+        cache_metadata: false,
+        noscan: false,
+        compute_statistics: true,
+    })
+}
+
+/// Recognizes SQLite's `EXPLAIN QUERY PLAN statement`, which is read as
+/// `EXPLAIN statement`. Plain `EXPLAIN` already describes the plan rather than
+/// bytecode, so both return the same operator tree.
+fn parse_explain_query_plan(tokens: &[Token]) -> Option<Statement> {
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(&dialect).with_tokens(tokens.to_vec());
+    if !parser.parse_keywords(&[Keyword::EXPLAIN, Keyword::QUERY]) {
+        return None;
+    }
+    match parser.next_token().token {
+        Token::Word(word) if word.value.eq_ignore_ascii_case("plan") => {}
+        _ => return None,
+    }
+    Some(Statement::Explain {
+        describe_alias: false,
+        analyze: false,
+        verbose: false,
+        statement: Box::new(parser.parse_statement().ok()?),
+        format: None,
+    })
+}
+
+/// The type of a column declared as `data_type`, which must be an integer or
+/// a string type.
+fn column_type(data_type: &DataType) -> Result<ColumnType, errors::QueryError> {
+    match data_type {
+        DataType::Int(_) | DataType::Integer(_) | DataType::BigInt(_) => Ok(ColumnType::Int),
+        DataType::Text | DataType::Varchar(_) | DataType::Char(_) | DataType::String => {
+            Ok(ColumnType::Text)
+        }
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{
+        cost::estimate_rows,
+        plan::Plan,
+        testing::{error, execute, in_temp_dir, int, ints, text, values, with_tables, Fixture},
+    };
+
+    const PEOPLE: Fixture = ("people", "id INT, name TEXT", &["1, 'ada'", "2, 'bob'"]);
+
+    #[test]
+    fn drop_table_removes_its_files() {
+        with_tables(&[PEOPLE], |database| {
+            execute(database, "DROP TABLE people");
+            assert!(database.tables.is_empty());
+            assert!(!Path::new("people").exists());
+            let e = error(database, "SELECT * FROM people");
+            assert!(matches!(e, errors::QueryError::UnknownTable));
+            let e = error(database, "DROP TABLE people");
+            assert!(matches!(e, errors::QueryError::UnknownTable));
+            execute(database, "DROP TABLE IF EXISTS people");
+        });
+    }
+
+    #[test]
+    fn rename_table() {
+        with_tables(&[PEOPLE, ("other", "id INT", &[])], |database| {
+            execute(database, "ALTER TABLE people RENAME TO persons");
+            let result = values(database, "SELECT name FROM persons");
+            assert_eq!(result, [text("ada"), text("bob")]);
+            let e = error(database, "SELECT * FROM people");
+            assert!(matches!(e, errors::QueryError::UnknownTable));
+            let e = error(database, "ALTER TABLE persons RENAME TO other");
+            assert!(matches!(e, errors::QueryError::TableAlreadyExists));
+        });
+    }
+
+    #[test]
+    fn add_column_fills_existing_rows() {
+        with_tables(&[PEOPLE], |database| {
+            execute(database, "ALTER TABLE people ADD COLUMN age INT");
+            execute(
+                database,
+                "ALTER TABLE people ADD COLUMN city TEXT DEFAULT 'paris'",
+            );
+            let sql = "INSERT INTO people (id, name, age, city) VALUES (3, 'cy', 30, 'rome')";
+            execute(database, sql);
+            let null = ColumnValue::Null;
+            assert_eq!(
+                values(database, "SELECT * FROM people"),
+                [
+                    [int(1), text("ada"), null.clone(), text("paris")],
+                    [int(2), text("bob"), null, text("paris")],
+                    [int(3), text("cy"), int(30), text("rome")],
+                ]
+                .concat()
+            );
+            // the new columns are in the catalog a new handle reads
+            let mut reopened = Database::load().unwrap();
+            let result = values(&mut reopened, "SELECT city FROM people");
+            assert_eq!(result, [text("paris"), text("paris"), text("rome")]);
+        });
+    }
+
+    #[test]
+    fn add_column_errors_leave_the_table_alone() {
+        with_tables(&[PEOPLE], |database| {
+            let e = error(database, "ALTER TABLE people ADD COLUMN name TEXT");
+            assert!(matches!(e, errors::QueryError::ColumnAlreadyExists));
+            execute(
+                database,
+                "ALTER TABLE people ADD COLUMN IF NOT EXISTS name TEXT",
+            );
+            let e = error(database, "ALTER TABLE people ADD COLUMN ratio FLOAT");
+            assert!(matches!(e, errors::QueryError::UnkownOperation));
+            let e = error(database, "ALTER TABLE people ADD COLUMN n INT DEFAULT 'x'");
+            assert!(matches!(e, errors::QueryError::InsertRowError(_)));
+            let people = [int(1), text("ada"), int(2), text("bob")];
+            assert_eq!(values(database, "SELECT * FROM people"), people);
+        });
+    }
+
+    #[test]
+    fn add_column_too_big_for_a_page_keeps_the_rows() {
+        with_tables(&[PEOPLE], |database| {
+            let long = "x".repeat(4100);
+            let sql = format!("ALTER TABLE people ADD COLUMN bio TEXT DEFAULT '{long}'");
+            let e = error(database, &sql);
+            assert!(matches!(
+                e,
+                errors::QueryError::InsertRowError(errors::InsertRowError::RowTooBig)
+            ));
+            assert!(!Path::new("people-rewrite").exists());
+            let people = [int(1), text("ada"), int(2), text("bob")];
+            assert_eq!(values(database, "SELECT * FROM people"), people);
+            let mut reopened = Database::load().unwrap();
+            assert_eq!(values(&mut reopened, "SELECT * FROM people"), people);
+        });
+    }
+
+    #[test]
+    fn add_column_accepts_type_aliases() {
+        with_tables(&[PEOPLE], |database| {
+            execute(database, "ALTER TABLE people ADD COLUMN nick VARCHAR(10)");
+            execute(database, "ALTER TABLE people ADD COLUMN score BIGINT");
+            let types: Vec<&ColumnType> = database.tables[0]
+                .columns
+                .iter()
+                .map(|c| &c._type)
+                .collect();
+            let (int, text) = (&ColumnType::Int, &ColumnType::Text);
+            assert_eq!(types, [int, text, text, int]);
+        });
+    }
+
+    #[test]
+    fn drop_column() {
+        with_tables(&[PEOPLE], |database| {
+            execute(database, "ALTER TABLE people DROP COLUMN id");
+            let result = values(database, "SELECT * FROM people");
+            assert_eq!(result, [text("ada"), text("bob")]);
+            let e = error(database, "ALTER TABLE people DROP COLUMN id");
+            assert!(matches!(e, errors::QueryError::UnknownColumn));
+            execute(database, "ALTER TABLE people DROP COLUMN IF EXISTS id");
+            let e = error(database, "ALTER TABLE people DROP COLUMN name");
+            assert!(matches!(e, errors::QueryError::CannotDropOnlyColumn));
+        });
+    }
+
+    #[test]
+    fn rename_column() {
+        with_tables(&[PEOPLE], |database| {
+            execute(database, "ALTER TABLE people RENAME COLUMN name TO label");
+            let result = values(database, "SELECT label FROM people");
+            assert_eq!(result, [text("ada"), text("bob")]);
+            let mut reopened = Database::load().unwrap();
+            let result = values(&mut reopened, "SELECT label FROM people");
+            assert_eq!(result, [text("ada"), text("bob")]);
+            let e = error(database, "ALTER TABLE people RENAME COLUMN id TO label");
+            assert!(matches!(e, errors::QueryError::ColumnAlreadyExists));
+            let e = error(database, "ALTER TABLE people RENAME COLUMN nope TO other");
+            assert!(matches!(e, errors::QueryError::UnknownColumn));
+        });
+    }
+
+    #[test]
+    fn alter_unknown_table() {
+        in_temp_dir(|| {
+            let mut database = Database::load().unwrap();
+            let e = error(&mut database, "ALTER TABLE nope ADD COLUMN a INT");
+            assert!(matches!(e, errors::QueryError::UnknownTable));
+        });
+    }
+
+    #[test]
+    fn create_table_twice() {
+        with_tables(&[PEOPLE], |database| {
+            let e = error(database, "CREATE TABLE people (other INT)");
+            assert!(matches!(e, errors::QueryError::TableAlreadyExists));
+            execute(database, "CREATE TABLE IF NOT EXISTS people (other INT)");
+            // the existing table is kept as it was
+            let people = [int(1), text("ada"), int(2), text("bob")];
+            assert_eq!(values(database, "SELECT * FROM people"), people);
+            assert_eq!(database.tables.len(), 1);
+        });
+    }
+
+    #[test]
+    fn create_table_as_select() {
+        with_tables(&[PEOPLE], |database| {
+            execute(database, "CREATE TABLE names AS SELECT name FROM people");
+            let result = values(database, "SELECT * FROM names");
+            assert_eq!(result, [text("ada"), text("bob")]);
+            let e = error(database, "CREATE TABLE names AS SELECT id FROM people");
+            assert!(matches!(e, errors::QueryError::TableAlreadyExists));
+        });
+    }
+
+    #[test]
+    fn drop_several_tables_is_all_or_nothing() {
+        with_tables(&[PEOPLE, ("other", "id INT", &[])], |database| {
+            let e = error(database, "DROP TABLE people, missing");
+            assert!(matches!(e, errors::QueryError::UnknownTable));
+            assert_eq!(database.tables.len(), 2);
+            assert!(Path::new("people").exists());
+            execute(database, "DROP TABLE IF EXISTS people, missing, other");
+            assert!(database.tables.is_empty());
+        });
+    }
+
+    #[test]
+    fn common_table_expressions() {
+        with_tables(&[PEOPLE], |database| {
+            let sql = "WITH named (n) AS (SELECT name FROM people WHERE id = 2), \
+                again AS (SELECT n FROM named) SELECT n FROM again";
+            assert_eq!(values(database, sql), [text("bob")]);
+            let e = error(database, "WITH t (a, b) AS (SELECT 1) SELECT * FROM t");
+            assert!(matches!(e, errors::QueryError::WrongNumberOfColumns));
+        });
+    }
+
+    #[test]
+    fn recursive_cte_counts() {
+        with_tables(&[], |database| {
+            let sql =
+                "WITH RECURSIVE c (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c WHERE n < 5) \
+                SELECT n FROM c";
+            assert_eq!(ints(database, sql), [1, 2, 3, 4, 5]);
+            // a LIMIT stops a recursion that would never end
+            let sql = "WITH RECURSIVE c (n) AS (SELECT 1 UNION ALL SELECT n * 2 FROM c LIMIT 4) \
+                SELECT n FROM c";
+            assert_eq!(ints(database, sql), [1, 2, 4, 8]);
+        });
+    }
+
+    #[test]
+    fn recursive_cte_reaches_a_fixed_point() {
+        let edges = (
+            "edges",
+            "src INT, dst INT",
+            &["1, 2", "2, 3", "3, 1", "4, 5"][..],
+        );
+        with_tables(&[edges], |database| {
+            // UNION drops the rows already found, so the cycle ends the recursion
+            let sql = "WITH RECURSIVE reach (node) AS (SELECT 1 UNION \
+                SELECT dst FROM edges JOIN reach ON src = node) \
+                SELECT node FROM reach ORDER BY node";
+            assert_eq!(ints(database, sql), [1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn insert_evaluates_expressions() {
+        with_tables(&[("n", "i INT", &["1", "2", "3", "4", "5"])], |database| {
+            execute(database, "INSERT INTO n (i) VALUES (-1)");
+            execute(database, "INSERT INTO n (i) VALUES (1 + 1)");
+            execute(
+                database,
+                "INSERT INTO n (i) VALUES ((SELECT max(i) FROM n) * 10)",
+            );
+            let sql = "SELECT i FROM n WHERE i < 1 OR i > 5";
+            assert_eq!(ints(database, sql), [-1, 50]);
+            let e = error(database, "INSERT INTO n (i) VALUES (9223372036854775808)");
+            assert!(matches!(e, errors::QueryError::IntegerOverflow));
+        });
+    }
+
+    #[test]
+    fn insert_every_row_or_none() {
+        with_tables(&[PEOPLE], |database| {
+            execute(
+                database,
+                "INSERT INTO people (id, name) VALUES (3, 'cy'), (4, 'di')",
+            );
+            assert_eq!(ints(database, "SELECT id FROM people"), [1, 2, 3, 4]);
+            let sql = "INSERT INTO people (id, name) VALUES (5, 'ed'), (6, 7)";
+            let e = error(database, sql);
+            assert!(matches!(
+                e,
+                errors::QueryError::InsertRowError(errors::InsertRowError::UnmatchingType)
+            ));
+            assert_eq!(ints(database, "SELECT id FROM people"), [1, 2, 3, 4]);
+            let e = error(
+                database,
+                "INSERT INTO people (id, name) SELECT id, name FROM people",
+            );
+            assert!(matches!(e, errors::QueryError::UnkownOperation));
+        });
+    }
+
+    /// The rows of a table `big` of the numbers from 1 to `count`, each next
+    /// to 1 if it's odd and 0 if not.
+    fn numbered(count: i64) -> Vec<String> {
+        (1..=count).map(|i| format!("{i}, {}", i % 2)).collect()
+    }
+
+    /// The plan of the query `sql` once optimized.
+    fn optimized(database: &Database, sql: &str) -> Plan {
+        let statement = Parser::parse_sql(&GenericDialect {}, sql)
+            .unwrap()
+            .remove(0);
+        let Statement::Query(query) = statement else {
+            panic!("{sql:?} isn't a query");
         };
+        let context = Context::new(database, None);
+        plan(&query, &context)
+            .and_then(|plan| optimize(plan, &database.functions, &database.statistics))
+            .unwrap()
+    }
+
+    /// How many rows the optimizer expects the query `sql` to return.
+    fn estimate(database: &Database, sql: &str) -> f64 {
+        estimate_rows(&optimized(database, sql), &database.statistics)
+    }
+
+    #[test]
+    fn analyze_counts_rows_and_distinct_values() {
+        let rows = numbered(20);
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        with_tables(&[("big", "x INT, odd INT", &rows)], |database| {
+            execute(database, "INSERT INTO big (x, odd) VALUES (NULL, 1)");
+            execute(database, "ANALYZE big");
+            let statistics = &database.statistics[0];
+            assert_eq!((statistics.table.as_str(), statistics.rows), ("big", 21));
+            let x = statistics.column("x").unwrap();
+            assert_eq!((x.distinct, x.nulls), (20, 1));
+            assert_eq!(x.histogram.len(), 11);
+            assert_eq!(x.histogram.first(), Some(&int(1)));
+            assert_eq!(x.histogram.last(), Some(&int(20)));
+            assert_eq!(statistics.column("odd").unwrap().distinct, 2);
+            assert!(statistics.column("nope").is_none());
+        });
+    }
+
+    #[test]
+    fn analyze_of_every_table_is_saved() {
+        let rows = numbered(20);
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let tables = [
+            ("big", "x INT, odd INT", &rows[..]),
+            ("small", "x INT", &["1"]),
+        ];
+        with_tables(&tables, |database| {
+            assert_eq!(estimate(database, "SELECT * FROM big"), 1000.0);
+            execute(database, "ANALYZE");
+            let reopened = Database::load().unwrap();
+            assert_eq!(estimate(&reopened, "SELECT * FROM big"), 20.0);
+            assert_eq!(estimate(&reopened, "SELECT * FROM small"), 1.0);
+            let e = error(database, "ANALYZE nope");
+            assert!(matches!(e, errors::QueryError::UnknownTable));
+        });
+    }
+
+    #[test]
+    fn analyze_follows_renamed_and_dropped_columns() {
+        let rows = numbered(4);
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        with_tables(&[("big", "x INT, odd INT", &rows)], |database| {
+            execute(database, "ANALYZE");
+            execute(database, "ALTER TABLE big RENAME COLUMN odd TO parity");
+            assert!(database.statistics[0].column("parity").is_some());
+            execute(database, "ALTER TABLE big DROP COLUMN parity");
+            assert!(database.statistics[0].column("parity").is_none());
+            execute(database, "DROP TABLE big");
+            assert!(database.statistics.is_empty());
+        });
+    }
+
+    #[test]
+    fn estimates_use_the_histograms() {
+        let rows = numbered(20);
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        with_tables(&[("big", "x INT, odd INT", &rows)], |database| {
+            execute(database, "ANALYZE big");
+            assert_eq!(
+                estimate(database, "SELECT * FROM big WHERE x < 5").round(),
+                5.0
+            );
+            assert_eq!(
+                estimate(database, "SELECT * FROM big WHERE x = 3").round(),
+                1.0
+            );
+            assert_eq!(
+                estimate(database, "SELECT * FROM big WHERE odd = 1").round(),
+                10.0
+            );
+        });
+    }
+
+    #[test]
+    fn joins_start_from_the_fewest_rows() {
+        let rows = numbered(20);
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let tables = [
+            ("big", "x INT, odd INT", &rows[..]),
+            ("mid", "x INT", &["1", "2", "3", "4", "5"]),
+            ("small", "x INT", &["1"]),
+        ];
+        with_tables(&tables, |database| {
+            execute(database, "ANALYZE");
+            let sql = "SELECT big.x FROM big JOIN mid ON big.x = mid.x \
+                       JOIN small ON mid.x = small.x";
+            let plan = optimized(database, sql);
+            // the innermost join, the one reading two tables, reads the smallest ones
+            let mut plans = vec![&plan];
+            let mut tables = Vec::new();
+            while let Some(plan) = plans.pop() {
+                let inputs = plan.inputs();
+                if let [Plan::Scan { table: left, .. }, Plan::Scan { table: right, .. }] =
+                    inputs[..]
+                {
+                    tables = vec![left.as_str(), right.as_str()];
+                }
+                plans.extend(inputs);
+            }
+            tables.sort();
+            assert_eq!(tables, ["mid", "small"]);
+            assert_eq!(values(database, sql), [int(1)]);
+        });
+    }
+
+    #[test]
+    fn batches_run_every_statement_in_order() {
+        with_tables(&[PEOPLE], |database| {
+            let results = database
+                .execute_batch(
+                    "INSERT INTO people (id, name) VALUES (3, 'cy');
+                    -- a comment between statements
+                    SELECT count(*) FROM people;
+                    SELECT max(id) FROM people;",
+                )
+                .unwrap();
+            assert_eq!(results.len(), 3);
+            assert!(matches!(results[0], QueryResult::InsertRowSucceeded));
+            let QueryResult::Rows(count) = &results[1] else {
+                panic!("{results:?}")
+            };
+            assert_eq!(count.rows, [[int(3)]]);
+        });
+    }
+
+    #[test]
+    fn batches_stop_at_the_first_failure() {
+        with_tables(&[PEOPLE], |database| {
+            let e = database
+                .execute_batch(
+                    "INSERT INTO people (id, name) VALUES (3, 'cy');
+                    SELECT * FROM nope;
+                    INSERT INTO people (id, name) VALUES (4, 'di');",
+                )
+                .unwrap_err();
+            assert_eq!(e.index, 1);
+            assert!(matches!(e.error, errors::QueryError::UnknownTable));
+            // what ran before the failure stays done
+            assert_eq!(ints(database, "SELECT id FROM people"), [1, 2, 3]);
+
+            let e = database.execute_batch("SELECT 1; SELEC 2").unwrap_err();
+            assert_eq!(e.index, 1);
+            assert!(matches!(e.error, errors::QueryError::SyntaxError));
+        });
     }
 }
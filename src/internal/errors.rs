@@ -5,6 +5,7 @@ pub enum InsertRowError {
     IOError(io::Error),
     InsertedValuesDoNotMatchNumberOfTableColumns,
     UnmatchingType,
+    RowTooBig,
 }
 
 #[derive(Debug)]
@@ -20,7 +21,56 @@ pub enum QueryError {
     UnknownTable,
     SyntaxError,
     UnknownColumn,
+    AmbiguousColumn,
     UnkownOperation,
     InsertMustSpecifyAllColumns,
     InsertRowError(InsertRowError),
+    TableAlreadyExists,
+    ColumnAlreadyExists,
+    CannotDropOnlyColumn,
+    TypeMismatch,
+    IntegerOverflow,
+    MisusedAggregate,
+    MisusedWindowFunction,
+    UnknownWindow,
+    WrongNumberOfArguments,
+    UnknownFunction,
+    SubqueryMustReturnOneColumn,
+    WrongNumberOfColumns,
+    UnknownParameter,
+    /// A line of a file read by `COPY` that couldn't be imported, counting from one.
+    CopyRowError {
+        line: u64,
+        error: Box<QueryError>,
+    },
+    DatabaseBusy,
+    IOError(io::Error),
+}
+
+/// A statement of a script that failed, by its index counting from zero.
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: usize,
+    pub error: QueryError,
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    IOError(io::Error),
+    Busy,
+}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::IOError(e)
+    }
+}
+
+impl From<LockError> for QueryError {
+    fn from(e: LockError) -> Self {
+        match e {
+            LockError::Busy => QueryError::DatabaseBusy,
+            LockError::IOError(e) => QueryError::IOError(e),
+        }
+    }
 }
@@ -0,0 +1,259 @@
+use crate::internal::{
+    join::{JoinAlgorithm, JoinKind},
+    operator::{Node, Operator},
+    table::{Column, ColumnType, ColumnValue, ResultSet},
+};
+use sqlparser::ast::{JoinConstraint, SetQuantifier};
+use std::fmt::Display;
+
+/// Describes `operator` and the operators below it, one row each, parents
+/// before their inputs. Every operator has an `id` and the `parent` it feeds
+/// its rows to.
+///
+/// With `analyze`, the operators must have run with profiling enabled, and
+/// each row also reports how many rows the operator produced and how long it
+/// took, in microseconds, including the time of the operators below it.
+pub fn explain(operator: &Operator, analyze: bool) -> ResultSet {
+    let mut columns = vec![
+        column("id", ColumnType::Int),
+        column("parent", ColumnType::Int),
+        column("operator", ColumnType::Text),
+        column("detail", ColumnType::Text),
+        column("estimated_rows", ColumnType::Int),
+    ];
+    if analyze {
+        columns.push(column("actual_rows", ColumnType::Int));
+        columns.push(column("time_us", ColumnType::Int));
+    }
+    let mut rows = Vec::new();
+    describe(operator, None, analyze, &mut rows);
+    ResultSet { columns, rows }
+}
+
+fn column(name: &str, _type: ColumnType) -> Column {
+    Column {
+        name: name.to_string(),
+        _type,
+    }
+}
+
+fn describe(
+    operator: &Operator,
+    parent: Option<i64>,
+    analyze: bool,
+    rows: &mut Vec<Vec<ColumnValue>>,
+) {
+    let id = rows.len() as i64 + 1;
+    let (name, detail) = node_description(operator);
+    let mut row = vec![
+        ColumnValue::Int(id),
+        parent.map_or(ColumnValue::Null, ColumnValue::Int),
+        ColumnValue::Text(name.to_string()),
+        ColumnValue::Text(detail),
+        ColumnValue::Int(operator.estimated_rows.round() as i64),
+    ];
+    if analyze {
+        let (actual_rows, time) = match &operator.profile {
+            Some(profile) => (
+                ColumnValue::Int(profile.rows.get() as i64),
+                ColumnValue::Int(profile.time.get().as_micros() as i64),
+            ),
+            None => (ColumnValue::Null, ColumnValue::Null),
+        };
+        row.extend([actual_rows, time]);
+    }
+    rows.push(row);
+    for input in &operator.inputs {
+        describe(input, Some(id), analyze, rows);
+    }
+}
+
+/// The operator's name and what it works on.
+fn node_description(operator: &Operator) -> (&'static str, String) {
+    match &operator.node {
+        Node::SeqScan { table, filter, .. } => {
+            let columns = list(operator.schema.fields.iter().map(|f| &f.column.name));
+            let mut detail = format!("{table} ({columns})");
+            if let Some(filter) = filter {
+                detail += &format!(" WHERE {filter}");
+            }
+            ("SeqScan", detail)
+        }
+        Node::Values { result } => match result.rows.len() {
+            1 => ("Values", "1 row".to_string()),
+            rows => ("Values", format!("{rows} rows")),
+        },
+        Node::SubqueryScan => ("SubqueryScan", String::new()),
+        Node::Filter { predicate } => ("Filter", predicate.to_string()),
+        Node::Join {
+            kind,
+            constraint,
+            algorithm,
+        } => {
+            let name = match algorithm {
+                JoinAlgorithm::Hash => "HashJoin",
+                JoinAlgorithm::NestedLoop => "NestedLoopJoin",
+            };
+            let kind = match kind {
+                JoinKind::Inner => "INNER",
+                JoinKind::Left => "LEFT",
+                JoinKind::Right => "RIGHT",
+                JoinKind::Full => "FULL",
+            };
+            let detail = match constraint {
+                JoinConstraint::On(on) => format!("{kind} ON {on}"),
+                JoinConstraint::Using(columns) => format!("{kind} USING ({})", list(columns)),
+                JoinConstraint::Natural => format!("NATURAL {kind}"),
+                JoinConstraint::None => kind.to_string(),
+            };
+            (name, detail)
+        }
+        Node::HashAggregate {
+            group_by,
+            aggregates,
+        } => {
+            let mut detail = list(aggregates);
+            if !group_by.is_empty() {
+                detail += &format!(" GROUP BY {}", list(group_by));
+            }
+            ("HashAggregate", detail.trim_start().to_string())
+        }
+        Node::Window { windows } => {
+            // the window functions are the last expressions the operator computes
+            let computed = &operator.schema.computed;
+            let calls = &computed[computed.len() - windows.len()..];
+            ("Window", list(calls))
+        }
+        Node::Sort { order_by, limit } => {
+            let mut detail = format!("ORDER BY {}", list(order_by));
+            if let Some(limit) = limit {
+                detail += &format!(" keeping {limit} rows");
+            }
+            ("Sort", detail)
+        }
+        Node::Limit { offset, limit } => {
+            let mut detail = match limit {
+                Some(limit) => format!("LIMIT {limit}"),
+                None => String::new(),
+            };
+            if *offset > 0 {
+                detail += &format!(" OFFSET {offset}");
+            }
+            ("Limit", detail.trim_start().to_string())
+        }
+        Node::Project { exprs } => ("Project", list(exprs)),
+        Node::Distinct => ("Distinct", String::new()),
+        Node::SetOperation { op, quantifier } => {
+            let detail = match quantifier {
+                SetQuantifier::None => op.to_string(),
+                quantifier => format!("{op} {quantifier}"),
+            };
+            ("SetOperation", detail)
+        }
+    }
+}
+
+fn list<T: Display>(items: impl IntoIterator<Item = T>) -> String {
+    items
+        .into_iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        database::Database,
+        errors::QueryError,
+        table::{ColumnValue, QueryResult, ResultSet},
+        testing::{error, execute, int, rows, text, values, with_tables, Fixture},
+    };
+
+    const NUMBERS: Fixture = ("numbers", "n INT", &["1", "2", "3", "4"]);
+
+    fn result(database: &mut Database, sql: &str) -> ResultSet {
+        match execute(database, sql) {
+            QueryResult::Rows(result) => result,
+            other => panic!("{sql:?} returned {other:?} instead of rows"),
+        }
+    }
+
+    fn column_names(result: &ResultSet) -> Vec<&str> {
+        result.columns.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn explain_describes_each_operator_under_its_parent() {
+        with_tables(&[NUMBERS], |database| {
+            let result = result(database, "EXPLAIN SELECT n FROM numbers ORDER BY n");
+            assert_eq!(
+                column_names(&result),
+                ["id", "parent", "operator", "detail", "estimated_rows"]
+            );
+            let plan: Vec<_> = result
+                .rows
+                .into_iter()
+                .map(|row| row[..4].to_vec())
+                .collect();
+            assert_eq!(
+                plan,
+                [
+                    [int(1), ColumnValue::Null, text("Project"), text("n")],
+                    [int(2), int(1), text("Sort"), text("ORDER BY n")],
+                    [int(3), int(2), text("SeqScan"), text("numbers (n)")],
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn sort_keeps_only_the_rows_a_limit_needs() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "EXPLAIN SELECT n FROM numbers ORDER BY n DESC LIMIT 2 OFFSET 1";
+            let details: Vec<_> = rows(database, sql)
+                .into_iter()
+                .map(|row| row[2..4].to_vec())
+                .collect();
+            let sort = [text("Sort"), text("ORDER BY n DESC keeping 3 rows")];
+            assert!(details.contains(&sort.to_vec()));
+        });
+    }
+
+    #[test]
+    fn explain_query_plan_is_explain() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT count(*) FROM numbers WHERE n > 1 GROUP BY n % 2";
+            assert_eq!(
+                rows(database, &format!("EXPLAIN QUERY PLAN {sql}")),
+                rows(database, &format!("EXPLAIN {sql}"))
+            );
+        });
+    }
+
+    #[test]
+    fn explain_analyze_counts_rows() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "EXPLAIN ANALYZE SELECT n FROM numbers WHERE n > 1";
+            let analyzed = result(database, sql);
+            assert_eq!(column_names(&analyzed)[5..], ["actual_rows", "time_us"]);
+            for row in &analyzed.rows {
+                assert_eq!(row[5], int(3));
+                assert!(matches!(row[6], ColumnValue::Int(time) if time >= 0));
+            }
+            // a LIMIT stops the scan under it early
+            let limited = result(database, "EXPLAIN ANALYZE SELECT n FROM numbers LIMIT 2");
+            let scan = limited.rows.last().unwrap();
+            assert_eq!((&scan[2], &scan[5]), (&text("SeqScan"), &int(2)));
+        });
+    }
+
+    #[test]
+    fn only_queries_are_explained() {
+        with_tables(&[NUMBERS], |database| {
+            let e = error(database, "EXPLAIN INSERT INTO numbers (n) VALUES (5)");
+            assert!(matches!(e, QueryError::UnkownOperation));
+            assert_eq!(values(database, "SELECT count(*) FROM numbers"), [int(4)]);
+        });
+    }
+}
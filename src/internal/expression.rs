@@ -0,0 +1,1101 @@
+use crate::internal::{
+    aggregate::Aggregate,
+    database::Database,
+    errors,
+    function::{pattern_match, scalar_function, Functions, Pattern},
+    table::{Column, ColumnType, ColumnValue, ResultSet},
+    window::Window,
+};
+use sqlparser::ast::{
+    BinaryOperator, DataType, Expr, Function, FunctionArg, FunctionArgExpr, Ident, Query,
+    TrimWhereField, UnaryOperator, Value, WindowSpec, WindowType,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+/// A column visible to expressions, along with the table (or alias) it came from.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub relation: Option<String>,
+    pub column: Column,
+    /// Hidden fields can only be referred to by their qualified name, like the
+    /// columns a `JOIN ... USING` merged.
+    pub hidden: bool,
+}
+impl Field {
+    pub fn new(relation: Option<String>, column: Column) -> Field {
+        Field {
+            relation,
+            column,
+            hidden: false,
+        }
+    }
+    /// The fields of a table's columns, qualified by `relation`.
+    pub fn from_columns(relation: &str, columns: &[Column]) -> Vec<Field> {
+        columns
+            .iter()
+            .map(|column| Field::new(Some(relation.to_string()), column.clone()))
+            .collect()
+    }
+}
+
+/// Finds the field `name` refers to, which may be qualified by a table name or alias.
+pub fn resolve(fields: &[Field], name: &[Ident]) -> Result<usize, errors::QueryError> {
+    let (relation, column) = match name {
+        [column] => (None, &column.value),
+        [.., relation, column] => (Some(&relation.value), &column.value),
+        [] => return Err(errors::QueryError::UnknownColumn),
+    };
+    let mut matches = fields.iter().enumerate().filter(|(_, field)| {
+        field.column.name == *column
+            && match relation {
+                Some(relation) => field.relation.as_ref() == Some(relation),
+                None => !field.hidden,
+            }
+    });
+    match (matches.next(), matches.next()) {
+        (Some((index, _)), None) => Ok(index),
+        (Some(_), Some(_)) => Err(errors::QueryError::AmbiguousColumn),
+        (None, _) => Err(errors::QueryError::UnknownColumn),
+    }
+}
+
+/// What expressions of a query can reach beyond their own row: the database to
+/// run subqueries against and, inside a subquery, the row of the enclosing query.
+pub struct Context<'a> {
+    pub database: &'a Database,
+    pub outer: Option<&'a Scope<'a>>,
+    /// Set once a column had to be looked up in `outer`, meaning the query is
+    /// correlated and its results can't be reused for other outer rows.
+    pub correlated: Cell<bool>,
+    /// Results of uncorrelated subqueries, which only need to run once.
+    subqueries: RefCell<HashMap<*const Query, Rc<ResultSet>>>,
+    /// The common table expressions in scope, later ones shadowing earlier ones.
+    ctes: RefCell<Vec<CommonTable>>,
+    /// The values bound to the parameters of a prepared statement, in order.
+    parameters: &'a [ColumnValue],
+    /// Set once a parameter was read, which tells whether a plan depends on them.
+    parameters_read: Cell<bool>,
+}
+
+/// The rows a common table expression stands for.
+struct CommonTable {
+    name: String,
+    result: Rc<ResultSet>,
+    /// Whether a query read the table, which tells recursive ones apart.
+    read: Cell<bool>,
+}
+impl<'a> Context<'a> {
+    pub fn new(database: &'a Database, outer: Option<&'a Scope<'a>>) -> Context<'a> {
+        Context {
+            database,
+            outer,
+            correlated: Cell::new(false),
+            subqueries: RefCell::new(HashMap::new()),
+            ctes: RefCell::new(Vec::new()),
+            parameters: &[],
+            parameters_read: Cell::new(false),
+        }
+    }
+    pub fn with_parameters(mut self, parameters: &'a [ColumnValue]) -> Context<'a> {
+        self.parameters = parameters;
+        self
+    }
+    /// The value of the parameter numbered `number`, counting from one, which
+    /// inside a subquery is the one of the enclosing statement. Parameters
+    /// without a value are NULL, like in SQLite.
+    pub fn parameter(&self, number: usize) -> ColumnValue {
+        if let Some(context) = self.outer.and_then(|outer| outer.context) {
+            return context.parameter(number);
+        }
+        self.parameters_read.set(true);
+        match number
+            .checked_sub(1)
+            .and_then(|index| self.parameters.get(index))
+        {
+            Some(value) => value.clone(),
+            None => ColumnValue::Null,
+        }
+    }
+    pub fn parameters_read(&self) -> bool {
+        self.parameters_read.get()
+    }
+    /// Makes `name` refer to `result` until the bindings are truncated.
+    pub fn bind(&self, name: String, result: Rc<ResultSet>) {
+        self.ctes.borrow_mut().push(CommonTable {
+            name,
+            result,
+            read: Cell::new(false),
+        });
+    }
+    pub fn bindings(&self) -> usize {
+        self.ctes.borrow().len()
+    }
+    /// Forgets the bindings made after there were `len` of them.
+    pub fn truncate_bindings(&self, len: usize) {
+        self.ctes.borrow_mut().truncate(len);
+    }
+    /// Whether the most recent binding was read since it was made.
+    pub fn last_binding_read(&self) -> bool {
+        self.ctes.borrow().last().is_some_and(|cte| cte.read.get())
+    }
+    /// The rows of the common table expression `name`, which may have been
+    /// defined by an enclosing query.
+    pub fn cte(&self, name: &str) -> Option<Rc<ResultSet>> {
+        let ctes = self.ctes.borrow();
+        match ctes.iter().rev().find(|cte| cte.name == name) {
+            Some(cte) => {
+                cte.read.set(true);
+                Some(cte.result.clone())
+            }
+            None => self.outer?.context?.cte(name),
+        }
+    }
+    /// Forgets the results of subqueries, which may change once the common
+    /// tables they read do.
+    pub fn clear_subqueries(&self) {
+        self.subqueries.borrow_mut().clear();
+    }
+}
+
+/// The row an expression is evaluated against, along with the fields describing it.
+pub struct Scope<'a> {
+    pub fields: &'a [Field],
+    pub row: &'a [ColumnValue],
+    /// Expressions whose values were computed ahead of time, such as GROUP BY
+    /// keys and aggregates. Their values follow the fields' values in `row`.
+    pub computed: &'a [Expr],
+    pub context: Option<&'a Context<'a>>,
+}
+impl<'a> Scope<'a> {
+    pub fn new(fields: &'a [Field], row: &'a [ColumnValue]) -> Scope<'a> {
+        Scope {
+            fields,
+            row,
+            computed: &[],
+            context: None,
+        }
+    }
+    pub fn with_computed(
+        fields: &'a [Field],
+        row: &'a [ColumnValue],
+        computed: &'a [Expr],
+    ) -> Scope<'a> {
+        Scope {
+            fields,
+            row,
+            computed,
+            context: None,
+        }
+    }
+    pub fn within(mut self, context: Option<&'a Context<'a>>) -> Scope<'a> {
+        self.context = context;
+        self
+    }
+    fn lookup(&self, name: &[Ident]) -> Result<ColumnValue, errors::QueryError> {
+        match resolve(self.fields, name) {
+            Ok(index) => Ok(self.row[index].clone()),
+            Err(errors::QueryError::UnknownColumn) => match self.context {
+                Some(Context {
+                    outer: Some(outer),
+                    correlated,
+                    ..
+                }) => {
+                    correlated.set(true);
+                    outer.lookup(name)
+                }
+                _ => Err(errors::QueryError::UnknownColumn),
+            },
+            Err(e) => Err(e),
+        }
+    }
+    /// The value of a placeholder, which `Database` numbers as `?N` when parsing.
+    /// Outside of a running statement, placeholders have no value.
+    fn parameter(&self, placeholder: &str) -> Result<ColumnValue, errors::QueryError> {
+        let context = self.context.ok_or(errors::QueryError::UnknownParameter)?;
+        match placeholder.strip_prefix('?').map(str::parse) {
+            Some(Ok(number)) => Ok(context.parameter(number)),
+            _ => Err(errors::QueryError::UnknownParameter),
+        }
+    }
+    /// Runs `query` as a subquery of the current row.
+    fn subquery(&self, query: &Query) -> Result<Rc<ResultSet>, errors::QueryError> {
+        let context = self.context.ok_or(errors::QueryError::UnkownOperation)?;
+        let key = query as *const Query;
+        if let Some(result) = context.subqueries.borrow().get(&key) {
+            return Ok(result.clone());
+        }
+        let inner = Context::new(context.database, Some(self));
+        let result = Rc::new(context.database.select_in(query, &inner)?);
+        if !inner.correlated.get() {
+            context.subqueries.borrow_mut().insert(key, result.clone());
+        }
+        Ok(result)
+    }
+}
+
+/// Evaluates `expr` against a single row.
+///
+/// Comparisons and logical operators yield `Int(1)` or `Int(0)` like SQLite,
+/// and follow SQL's three-valued logic when either side is NULL.
+pub fn evaluate(expr: &Expr, scope: &Scope) -> Result<ColumnValue, errors::QueryError> {
+    if let Some(index) = scope.computed.iter().position(|computed| computed == expr) {
+        return Ok(scope.row[scope.fields.len() + index].clone());
+    }
+    match expr {
+        Expr::Identifier(ident) => scope.lookup(std::slice::from_ref(ident)),
+        Expr::CompoundIdentifier(idents) => scope.lookup(idents),
+        Expr::Value(Value::Placeholder(placeholder)) => scope.parameter(placeholder),
+        Expr::Value(value) => parse_value(value),
+        Expr::Nested(expr) => evaluate(expr, scope),
+        Expr::UnaryOp { op, expr } => {
+            let value = evaluate(expr, scope)?;
+            match (op, value) {
+                (_, ColumnValue::Null) => Ok(ColumnValue::Null),
+                (UnaryOperator::Plus, ColumnValue::Int(value)) => Ok(ColumnValue::Int(value)),
+                (UnaryOperator::Minus, ColumnValue::Int(value)) => value
+                    .checked_neg()
+                    .map(ColumnValue::Int)
+                    .ok_or(errors::QueryError::IntegerOverflow),
+                (UnaryOperator::Not, value) => Ok(boolean(!truthy(&value))),
+                _ => Err(errors::QueryError::TypeMismatch),
+            }
+        }
+        Expr::IsNull(expr) => Ok(boolean(evaluate(expr, scope)? == ColumnValue::Null)),
+        Expr::IsNotNull(expr) => Ok(boolean(evaluate(expr, scope)? != ColumnValue::Null)),
+        Expr::BinaryOp { left, op, right } => {
+            let left = evaluate(left, scope)?;
+            let right = evaluate(right, scope)?;
+            binary_op(&left, op, &right)
+        }
+        Expr::Like {
+            negated,
+            expr: value,
+            pattern,
+            escape_char,
+        }
+        | Expr::ILike {
+            negated,
+            expr: value,
+            pattern,
+            escape_char,
+        } => {
+            let value = evaluate(value, scope)?;
+            let pattern = Pattern::like(&evaluate(pattern, scope)?, *escape_char)?;
+            let case_insensitive = matches!(expr, Expr::ILike { .. });
+            Ok(negate(
+                pattern_match(pattern, &value, case_insensitive),
+                *negated,
+            ))
+        }
+        // what `x GLOB pattern` is parsed into
+        Expr::SimilarTo {
+            negated,
+            expr,
+            pattern,
+            escape_char: None,
+        } => {
+            let value = evaluate(expr, scope)?;
+            let pattern = Pattern::glob(&evaluate(pattern, scope)?)?;
+            Ok(negate(pattern_match(pattern, &value, false), *negated))
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let value = evaluate(expr, scope)?;
+            let low = binary_op(&value, &BinaryOperator::GtEq, &evaluate(low, scope)?)?;
+            let high = binary_op(&value, &BinaryOperator::LtEq, &evaluate(high, scope)?)?;
+            let between = binary_op(&low, &BinaryOperator::And, &high)?;
+            Ok(negate(between, *negated))
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = evaluate(expr, scope)?;
+            let values = list
+                .iter()
+                .map(|expr| evaluate(expr, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(in_list(&value, values.iter(), *negated))
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(evaluate(operand, scope)?),
+                None => None,
+            };
+            for (condition, result) in conditions.iter().zip(results) {
+                let condition = evaluate(condition, scope)?;
+                let matched = match &operand {
+                    // NULL never equals anything, so it only reaches ELSE
+                    Some(operand) => truthy(&binary_op(operand, &BinaryOperator::Eq, &condition)?),
+                    None => truthy(&condition),
+                };
+                if matched {
+                    return evaluate(result, scope);
+                }
+            }
+            match else_result {
+                Some(else_result) => evaluate(else_result, scope),
+                None => Ok(ColumnValue::Null),
+            }
+        }
+        Expr::Cast { expr, data_type } => cast(evaluate(expr, scope)?, &cast_type(data_type)?),
+        Expr::Subquery(query) => {
+            // like SQLite, a scalar subquery yields the first row, or NULL without rows
+            let result = scope.subquery(query)?;
+            if result.columns.len() != 1 {
+                return Err(errors::QueryError::SubqueryMustReturnOneColumn);
+            }
+            Ok(result
+                .rows
+                .first()
+                .map_or(ColumnValue::Null, |row| row[0].clone()))
+        }
+        Expr::Exists { subquery, negated } => {
+            let result = scope.subquery(subquery)?;
+            Ok(boolean(result.rows.is_empty() == *negated))
+        }
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => {
+            let value = evaluate(expr, scope)?;
+            let result = scope.subquery(subquery)?;
+            if result.columns.len() != 1 {
+                return Err(errors::QueryError::SubqueryMustReturnOneColumn);
+            }
+            let values = result.rows.iter().map(|row| &row[0]);
+            Ok(in_list(&value, values, *negated))
+        }
+        Expr::Function(function) if function.over.is_some() => {
+            // window functions are computed over all rows before the projection
+            Err(errors::QueryError::MisusedWindowFunction)
+        }
+        Expr::Function(_) if Aggregate::from_expr(expr, functions(scope.context))?.is_some() => {
+            // aggregates only have a value once they were computed for a group
+            Err(errors::QueryError::MisusedAggregate)
+        }
+        Expr::Function(function) => {
+            let name = function.name.to_string();
+            let arguments = function_arguments(function)?
+                .into_iter()
+                .map(|argument| evaluate(argument, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(user) = functions(scope.context).and_then(|f| f.scalar(&name)) {
+                return user.invoke(&arguments);
+            }
+            let scalar = scalar_function(&name).ok_or(errors::QueryError::UnknownFunction)?;
+            scalar.invoke(&arguments)
+        }
+        Expr::Trim {
+            expr,
+            trim_where,
+            trim_what,
+        } => {
+            let name = match trim_where {
+                Some(TrimWhereField::Leading) => "ltrim",
+                Some(TrimWhereField::Trailing) => "rtrim",
+                Some(TrimWhereField::Both) | None => "trim",
+            };
+            let mut arguments = vec![evaluate(expr, scope)?];
+            if let Some(trim_what) = trim_what {
+                arguments.push(evaluate(trim_what, scope)?);
+            }
+            scalar_function(name).unwrap().invoke(&arguments)
+        }
+        Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+        } => {
+            let mut arguments = vec![evaluate(expr, scope)?];
+            match substring_from {
+                Some(from) => arguments.push(evaluate(from, scope)?),
+                None => arguments.push(ColumnValue::Int(1)),
+            }
+            if let Some(substring_for) = substring_for {
+                arguments.push(evaluate(substring_for, scope)?);
+            }
+            scalar_function("substr").unwrap().invoke(&arguments)
+        }
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+/// Infers the type of the values `expr` produces over rows with `fields`.
+/// Expressions that can only be NULL, or whose type is only known once they
+/// run like scalar subqueries, are reported as `Int`.
+pub fn expression_type(
+    expr: &Expr,
+    fields: &[Field],
+    context: Option<&Context>,
+) -> Result<ColumnType, errors::QueryError> {
+    match expr {
+        Expr::Identifier(ident) => field_type(std::slice::from_ref(ident), fields, context),
+        Expr::CompoundIdentifier(idents) => field_type(idents, fields, context),
+        Expr::Value(Value::SingleQuotedString(_)) => Ok(ColumnType::Text),
+        Expr::Nested(expr) => expression_type(expr, fields, context),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::StringConcat,
+            right,
+        } => {
+            expression_type(left, fields, context)?;
+            expression_type(right, fields, context)?;
+            Ok(ColumnType::Text)
+        }
+        Expr::Cast { expr, data_type } => {
+            expression_type(expr, fields, context)?;
+            cast_type(data_type)
+        }
+        Expr::Case {
+            results,
+            else_result,
+            ..
+        } => {
+            let mut result_type = None;
+            for child in sub_expressions(expr) {
+                let child_type = expression_type(child, fields, context)?;
+                // the first branch that isn't a NULL literal decides the type
+                let is_result = results.contains(child) || else_result.as_deref() == Some(child);
+                if is_result && result_type.is_none() && *child != Expr::Value(Value::Null) {
+                    result_type = Some(child_type);
+                }
+            }
+            Ok(result_type.unwrap_or(ColumnType::Int))
+        }
+        Expr::Function(function) if function.over.is_some() => {
+            // the frame doesn't change the type, and a named window may not be known here
+            let mut call = function.clone();
+            call.over = Some(WindowType::WindowSpec(WindowSpec {
+                partition_by: Vec::new(),
+                order_by: Vec::new(),
+                window_frame: None,
+            }));
+            let window = Window::from_expr(&Expr::Function(call), &[], functions(context))?;
+            window.unwrap().result_type(fields, context)
+        }
+        Expr::Function(function) => match Aggregate::from_expr(expr, functions(context))? {
+            Some(aggregate) => aggregate.result_type(fields, context),
+            None => {
+                let name = function.name.to_string();
+                if let Some(user) = functions(context).and_then(|f| f.scalar(&name)) {
+                    for argument in function_arguments(function)? {
+                        expression_type(argument, fields, context)?;
+                    }
+                    if function.args.len() != user.arity {
+                        return Err(errors::QueryError::WrongNumberOfArguments);
+                    }
+                    // only known from the values the function returns
+                    return Ok(ColumnType::Int);
+                }
+                let scalar = scalar_function(&name).ok_or(errors::QueryError::UnknownFunction)?;
+                let arguments = function_arguments(function)?;
+                scalar.check_arity(arguments.len())?;
+                let mut types = Vec::new();
+                for argument in &arguments {
+                    types.push(expression_type(argument, fields, context)?);
+                }
+                match &scalar.result_type {
+                    Some(result_type) => Ok(result_type.clone()),
+                    // like CASE, the first argument that isn't a NULL literal decides
+                    None => Ok(arguments
+                        .iter()
+                        .zip(types)
+                        .find(|(argument, _)| ***argument != Expr::Value(Value::Null))
+                        .map_or(ColumnType::Int, |(_, argument_type)| argument_type)),
+                }
+            }
+        },
+        Expr::Trim { .. } | Expr::Substring { .. } => {
+            for child in sub_expressions(expr) {
+                expression_type(child, fields, context)?;
+            }
+            Ok(ColumnType::Text)
+        }
+        _ => {
+            for child in sub_expressions(expr) {
+                expression_type(child, fields, context)?;
+            }
+            Ok(ColumnType::Int)
+        }
+    }
+}
+
+/// The type of the field `name` refers to, looking through to the enclosing
+/// query's fields inside a correlated subquery.
+fn field_type(
+    name: &[Ident],
+    fields: &[Field],
+    context: Option<&Context>,
+) -> Result<ColumnType, errors::QueryError> {
+    match resolve(fields, name) {
+        Ok(index) => Ok(fields[index].column._type.clone()),
+        Err(errors::QueryError::UnknownColumn) => match context.and_then(|c| c.outer) {
+            Some(outer) => field_type(name, outer.fields, outer.context),
+            None => Err(errors::QueryError::UnknownColumn),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// The expressions directly nested in `expr`, for walking expression trees.
+pub fn sub_expressions(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Nested(expr)
+        | Expr::UnaryOp { expr, .. }
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. } => vec![expr],
+        Expr::Like { expr, pattern, .. }
+        | Expr::ILike { expr, pattern, .. }
+        | Expr::SimilarTo { expr, pattern, .. } => vec![expr, pattern],
+        Expr::Between {
+            expr, low, high, ..
+        } => vec![expr, low, high],
+        Expr::InList { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+        Expr::Trim {
+            expr, trim_what, ..
+        } => std::iter::once(&**expr)
+            .chain(trim_what.as_deref())
+            .collect(),
+        Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+        } => std::iter::once(&**expr)
+            .chain(substring_from.as_deref())
+            .chain(substring_for.as_deref())
+            .collect(),
+        Expr::BinaryOp { left, right, .. } => vec![left, right],
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => operand
+            .iter()
+            .map(|operand| &**operand)
+            .chain(conditions)
+            .chain(results)
+            .chain(else_result.iter().map(|result| &**result))
+            .collect(),
+        // the subquery itself is a scope of its own
+        Expr::InSubquery { expr, .. } => vec![expr],
+        Expr::Function(function) => {
+            let window = match &function.over {
+                Some(WindowType::WindowSpec(spec)) => Some(spec),
+                _ => None,
+            };
+            function
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+                    | FunctionArg::Named {
+                        arg: FunctionArgExpr::Expr(expr),
+                        ..
+                    } => Some(expr),
+                    _ => None,
+                })
+                .chain(window.iter().flat_map(|spec| &spec.partition_by))
+                .chain(
+                    window
+                        .iter()
+                        .flat_map(|spec| spec.order_by.iter().map(|o| &o.expr)),
+                )
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// Like `sub_expressions`, for rewriting them in place.
+pub fn sub_expressions_mut(expr: &mut Expr) -> Vec<&mut Expr> {
+    match expr {
+        Expr::Nested(expr)
+        | Expr::UnaryOp { expr, .. }
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::InSubquery { expr, .. } => vec![expr],
+        Expr::Like { expr, pattern, .. } | Expr::ILike { expr, pattern, .. } => {
+            vec![expr, pattern]
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => vec![expr, low, high],
+        Expr::InList { expr, list, .. } => std::iter::once(&mut **expr).chain(list).collect(),
+        Expr::Trim {
+            expr, trim_what, ..
+        } => std::iter::once(&mut **expr)
+            .chain(trim_what.as_deref_mut())
+            .collect(),
+        Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+        } => std::iter::once(&mut **expr)
+            .chain(substring_from.as_deref_mut())
+            .chain(substring_for.as_deref_mut())
+            .collect(),
+        Expr::BinaryOp { left, right, .. } => vec![left, right],
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => operand
+            .as_deref_mut()
+            .into_iter()
+            .chain(conditions)
+            .chain(results)
+            .chain(else_result.as_deref_mut())
+            .collect(),
+        Expr::Function(function) => {
+            let window = match &mut function.over {
+                Some(WindowType::WindowSpec(spec)) => Some(spec),
+                _ => None,
+            };
+            function
+                .args
+                .iter_mut()
+                .filter_map(|arg| match arg {
+                    FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+                    | FunctionArg::Named {
+                        arg: FunctionArgExpr::Expr(expr),
+                        ..
+                    } => Some(expr),
+                    _ => None,
+                })
+                .chain(window.into_iter().flat_map(|spec| {
+                    spec.partition_by
+                        .iter_mut()
+                        .chain(spec.order_by.iter_mut().map(|o| &mut o.expr))
+                }))
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// The functions the application registered on the database, if known.
+fn functions<'a>(context: Option<&'a Context>) -> Option<&'a Functions> {
+    context.map(|context| &context.database.functions)
+}
+
+/// The arguments of a call to a scalar function.
+fn function_arguments(function: &Function) -> Result<Vec<&Expr>, errors::QueryError> {
+    function
+        .args
+        .iter()
+        .map(|argument| match argument {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Ok(expr),
+            _ => Err(errors::QueryError::SyntaxError),
+        })
+        .collect()
+}
+
+/// Whether every column `expr` refers to is one of `fields`.
+pub fn is_bound_by(expr: &Expr, fields: &[Field]) -> bool {
+    match expr {
+        Expr::Identifier(ident) => resolve(fields, std::slice::from_ref(ident)).is_ok(),
+        Expr::CompoundIdentifier(idents) => resolve(fields, idents).is_ok(),
+        // subqueries may be correlated with anything
+        Expr::Subquery(_) | Expr::Exists { .. } | Expr::InSubquery { .. } => false,
+        _ => sub_expressions(expr)
+            .into_iter()
+            .all(|expr| is_bound_by(expr, fields)),
+    }
+}
+
+/// Whether a value counts as true in a WHERE-like position. NULL is not true.
+pub fn truthy(value: &ColumnValue) -> bool {
+    match value {
+        ColumnValue::Int(value) => *value != 0,
+        ColumnValue::Text(value) => value.parse::<i64>().map(|v| v != 0).unwrap_or(false),
+        ColumnValue::Null => false,
+    }
+}
+
+/// Inverts a predicate's result for its NOT form; NULL stays NULL.
+fn negate(value: ColumnValue, negated: bool) -> ColumnValue {
+    match value {
+        ColumnValue::Null => ColumnValue::Null,
+        value if negated => boolean(!truthy(&value)),
+        value => value,
+    }
+}
+
+/// `value [NOT] IN (values)`: NULL rather than false when nothing matched
+/// but either side had a NULL.
+fn in_list<'a>(
+    value: &ColumnValue,
+    values: impl Iterator<Item = &'a ColumnValue>,
+    negated: bool,
+) -> ColumnValue {
+    if *value == ColumnValue::Null {
+        return ColumnValue::Null;
+    }
+    let mut saw_null = false;
+    for candidate in values {
+        if candidate == value {
+            return boolean(!negated);
+        }
+        saw_null |= *candidate == ColumnValue::Null;
+    }
+    if saw_null {
+        ColumnValue::Null
+    } else {
+        boolean(negated)
+    }
+}
+
+fn boolean(value: bool) -> ColumnValue {
+    ColumnValue::Int(value as i64)
+}
+
+fn binary_op(
+    left: &ColumnValue,
+    op: &BinaryOperator,
+    right: &ColumnValue,
+) -> Result<ColumnValue, errors::QueryError> {
+    match op {
+        // NULL AND false is false, NULL OR true is true; otherwise NULL wins
+        BinaryOperator::And => match (left, right) {
+            (ColumnValue::Null, other) | (other, ColumnValue::Null) => {
+                if truthy(other) || *other == ColumnValue::Null {
+                    Ok(ColumnValue::Null)
+                } else {
+                    Ok(boolean(false))
+                }
+            }
+            _ => Ok(boolean(truthy(left) && truthy(right))),
+        },
+        BinaryOperator::Or => match (left, right) {
+            (ColumnValue::Null, other) | (other, ColumnValue::Null) => {
+                if truthy(other) {
+                    Ok(boolean(true))
+                } else {
+                    Ok(ColumnValue::Null)
+                }
+            }
+            _ => Ok(boolean(truthy(left) || truthy(right))),
+        },
+        _ if *left == ColumnValue::Null || *right == ColumnValue::Null => Ok(ColumnValue::Null),
+        BinaryOperator::Eq => Ok(boolean(left == right)),
+        BinaryOperator::NotEq => Ok(boolean(left != right)),
+        BinaryOperator::Lt => Ok(boolean(left < right)),
+        BinaryOperator::LtEq => Ok(boolean(left <= right)),
+        BinaryOperator::Gt => Ok(boolean(left > right)),
+        BinaryOperator::GtEq => Ok(boolean(left >= right)),
+        BinaryOperator::StringConcat => {
+            Ok(ColumnValue::Text(format!("{}{}", text(left), text(right))))
+        }
+        BinaryOperator::Plus
+        | BinaryOperator::Minus
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulo => {
+            let (ColumnValue::Int(left), ColumnValue::Int(right)) = (left, right) else {
+                return Err(errors::QueryError::TypeMismatch);
+            };
+            // division by zero yields NULL, like SQLite
+            let result = match op {
+                BinaryOperator::Plus => left.checked_add(*right),
+                BinaryOperator::Minus => left.checked_sub(*right),
+                BinaryOperator::Multiply => left.checked_mul(*right),
+                BinaryOperator::Divide if *right == 0 => return Ok(ColumnValue::Null),
+                BinaryOperator::Divide => left.checked_div(*right),
+                BinaryOperator::Modulo if *right == 0 => return Ok(ColumnValue::Null),
+                _ => left.checked_rem(*right),
+            };
+            result
+                .map(ColumnValue::Int)
+                .ok_or(errors::QueryError::IntegerOverflow)
+        }
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+pub fn parse_value(value: &Value) -> Result<ColumnValue, errors::QueryError> {
+    match value {
+        Value::Number(val, _) => {
+            // ! should handle floats too
+            match val.parse::<i64>() {
+                Ok(parsed) => Ok(ColumnValue::Int(parsed)),
+                Err(_) => Err(errors::QueryError::IntegerOverflow),
+            }
+        }
+        Value::SingleQuotedString(val) => Ok(ColumnValue::Text(val.to_string())),
+        // there is no boolean type, so booleans are 1 and 0 like the results of comparisons
+        Value::Boolean(val) => Ok(boolean(*val)),
+        Value::Null => Ok(ColumnValue::Null),
+        // parameters only have a value while a statement runs
+        Value::Placeholder(_) => Err(errors::QueryError::UnknownParameter),
+        // escaped, hex, national and dollar quoted strings aren't supported
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+/// The column type a CAST converts to.
+fn cast_type(data_type: &DataType) -> Result<ColumnType, errors::QueryError> {
+    match data_type {
+        DataType::Int(_) | DataType::Integer(_) | DataType::BigInt(_) => Ok(ColumnType::Int),
+        DataType::Text | DataType::Varchar(_) | DataType::Char(_) | DataType::String => {
+            Ok(ColumnType::Text)
+        }
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+/// Converts `value` to `to`. Like SQLite, text becomes the integer it starts
+/// with, or 0 when it doesn't start with one.
+fn cast(value: ColumnValue, to: &ColumnType) -> Result<ColumnValue, errors::QueryError> {
+    match (value, to) {
+        (ColumnValue::Text(value), ColumnType::Int) => {
+            let value = value.trim_start();
+            let sign = value.starts_with(['+', '-']) as usize;
+            let digits = value[sign..].len()
+                - value[sign..]
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .len();
+            if digits == 0 {
+                return Ok(ColumnValue::Int(0));
+            }
+            value[..sign + digits]
+                .parse()
+                .map(ColumnValue::Int)
+                .map_err(|_| errors::QueryError::IntegerOverflow)
+        }
+        (ColumnValue::Int(value), ColumnType::Text) => Ok(ColumnValue::Text(value.to_string())),
+        (value, _) => Ok(value),
+    }
+}
+
+/// The text form of a value that isn't NULL, as used by `||`.
+fn text(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Int(value) => value.to_string(),
+        ColumnValue::Text(value) => value.clone(),
+        ColumnValue::Null => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        table::{
+            ColumnType,
+            ColumnValue::{self, Null},
+            QueryResult,
+        },
+        testing::{error, execute, int, ints, text, values, with_tables, Fixture},
+    };
+
+    /// Orders placed by customers; one customer never ordered.
+    const SHOP: [Fixture; 2] = [
+        (
+            "customers",
+            "id INT, name TEXT",
+            &["1, 'ada'", "2, 'bob'", "3, 'cy'"],
+        ),
+        (
+            "orders",
+            "customer INT, total INT",
+            &["1, 10", "1, 30", "2, 5"],
+        ),
+    ];
+
+    #[test]
+    fn scalar_subqueries() {
+        with_tables(&SHOP, |database| {
+            let sql = "SELECT (SELECT max(total) FROM orders), (SELECT total FROM orders WHERE total > 100)";
+            assert_eq!(values(database, sql), [int(30), Null]);
+            let e = error(database, "SELECT (SELECT customer, total FROM orders)");
+            assert!(matches!(e, QueryError::SubqueryMustReturnOneColumn));
+        });
+    }
+
+    #[test]
+    fn correlated_subqueries() {
+        with_tables(&SHOP, |database| {
+            let sql = "SELECT name, (SELECT sum(total) FROM orders WHERE customer = c.id) \
+                FROM customers c ORDER BY id";
+            let expected = [text("ada"), int(40), text("bob"), int(5), text("cy"), Null];
+            assert_eq!(values(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn in_and_exists_subqueries() {
+        with_tables(&SHOP, |database| {
+            let sql =
+                "SELECT id FROM customers WHERE id IN (SELECT customer FROM orders) ORDER BY id";
+            assert_eq!(ints(database, sql), [1, 2]);
+            let sql = "SELECT id FROM customers WHERE id NOT IN (SELECT customer FROM orders)";
+            assert_eq!(ints(database, sql), [3]);
+            let sql = "SELECT id FROM customers c WHERE EXISTS \
+                (SELECT customer FROM orders WHERE customer = c.id AND total > 20)";
+            assert_eq!(ints(database, sql), [1]);
+            let sql = "SELECT count(*) FROM customers c WHERE NOT EXISTS \
+                (SELECT customer FROM orders WHERE customer = c.id)";
+            assert_eq!(ints(database, sql), [1]);
+        });
+    }
+
+    #[test]
+    fn derived_tables() {
+        with_tables(&SHOP, |database| {
+            let sql = "SELECT * FROM \
+                (SELECT customer, sum(total) FROM orders GROUP BY customer) AS t \
+                WHERE t.customer = 1";
+            assert_eq!(values(database, sql), [int(1), int(40)]);
+            let sql = "SELECT t.total FROM (SELECT total FROM orders) AS t WHERE t.total > 5";
+            assert_eq!(ints(database, sql), [10, 30]);
+        });
+    }
+
+    /// The single value `expression` evaluates to, without a table.
+    fn value(expression: &str) -> ColumnValue {
+        with_tables(&[], |database| {
+            values(database, &format!("SELECT {expression}")).remove(0)
+        })
+    }
+
+    #[test]
+    fn arithmetic_and_concatenation() {
+        assert_eq!(value("1 + 2 * 3 - 4"), int(3));
+        assert_eq!(value("(1 + 2) * 3"), int(9));
+        assert_eq!(value("7 / 2"), int(3));
+        assert_eq!(value("7 % 3"), int(1));
+        assert_eq!(value("-(2 - 5)"), int(3));
+        assert_eq!(value("'a' || 1 || 'b'"), text("a1b"));
+    }
+
+    #[test]
+    fn nulls_and_errors() {
+        assert_eq!(value("1 + NULL"), Null);
+        assert_eq!(value("'a' || NULL"), Null);
+        // like SQLite
+        assert_eq!(value("1 / 0"), Null);
+        with_tables(&[], |database| {
+            let e = error(database, "SELECT 9223372036854775807 + 1");
+            assert!(matches!(e, QueryError::IntegerOverflow));
+            let e = error(database, "SELECT 9223372036854775808");
+            assert!(matches!(e, QueryError::IntegerOverflow));
+            let e = error(database, "SELECT 1 + 'a'");
+            assert!(matches!(e, QueryError::TypeMismatch));
+        });
+    }
+
+    #[test]
+    fn case_and_cast() {
+        assert_eq!(value("CASE WHEN 1 > 2 THEN 'a' ELSE 'b' END"), text("b"));
+        assert_eq!(
+            value("CASE 2 WHEN 1 THEN 'a' WHEN 2 THEN 'b' END"),
+            text("b")
+        );
+        assert_eq!(value("CASE WHEN 0 THEN 1 END"), Null);
+        assert_eq!(value("CAST('12abc' AS INTEGER)"), int(12));
+        assert_eq!(value("CAST('abc' AS INTEGER)"), int(0));
+        assert_eq!(value("CAST(12 AS TEXT)"), text("12"));
+    }
+
+    #[test]
+    fn comparisons_are_integers() {
+        assert_eq!(value("1 < 2"), int(1));
+        assert_eq!(value("'b' < 'a'"), int(0));
+        assert_eq!(value("NULL = NULL"), Null);
+        assert_eq!(value("NULL IS NULL AND 1 IS NOT NULL"), int(1));
+        assert_eq!(value("TRUE OR NULL"), int(1));
+    }
+
+    #[test]
+    fn expression_columns_have_inferred_types() {
+        with_tables(&SHOP, |database| {
+            let sql = "SELECT total * 2, name || '!', CASE WHEN total > 5 THEN name END \
+                FROM orders JOIN customers ON customer = id";
+            let result = match execute(database, sql) {
+                QueryResult::Rows(result) => result,
+                other => panic!("{other:?}"),
+            };
+            let types: Vec<ColumnType> = result.columns.into_iter().map(|c| c._type).collect();
+            assert_eq!(types, [ColumnType::Int, ColumnType::Text, ColumnType::Text]);
+        });
+    }
+
+    #[test]
+    fn unsupported_literals_are_errors() {
+        with_tables(&[], |database| {
+            for sql in ["SELECT E'a'", "SELECT X'01'"] {
+                let e = error(database, sql);
+                assert!(matches!(e, QueryError::UnkownOperation));
+            }
+        });
+    }
+
+    #[test]
+    fn like_and_ilike() {
+        assert_eq!(value("'Hello' LIKE 'H%o'"), int(1));
+        // like PostgreSQL, only ILIKE ignores case
+        assert_eq!(value("'Hello' LIKE 'h%o'"), int(0));
+        assert_eq!(
+            value("'Hello' LIKE 'H_llo' AND 'Hello' NOT LIKE 'H_lo'"),
+            int(1)
+        );
+        assert_eq!(value("'50%' LIKE '50!%' ESCAPE '!'"), int(1));
+        assert_eq!(value("'500' LIKE '50!%' ESCAPE '!'"), int(0));
+        assert_eq!(value("'ABC' ILIKE 'a%'"), int(1));
+        assert_eq!(value("NULL LIKE 'a'"), Null);
+    }
+
+    #[test]
+    fn glob_operator_and_function() {
+        assert_eq!(value("'Hello' GLOB 'h*'"), int(0));
+        assert_eq!(value("'Hello' GLOB 'H?l*'"), int(1));
+        assert_eq!(value("'b' GLOB '[a-c]' AND 'd' NOT GLOB '[a-c]'"), int(1));
+        assert_eq!(value("NOT 'a' GLOB 'b'"), int(1));
+        assert_eq!(value("1 + 1 GLOB '2'"), int(1));
+        assert_eq!(value("glob('*.rs', 'main.rs')"), int(1));
+        with_tables(&[], |database| {
+            let e = error(database, "SELECT 'a' SIMILAR TO 'a'");
+            assert!(matches!(e, QueryError::UnkownOperation));
+        });
+    }
+
+    #[test]
+    fn glob_operator_in_where() {
+        with_tables(&SHOP, |database| {
+            let sql = "SELECT id FROM customers WHERE name GLOB '*b*' OR name GLOB 'c?'";
+            assert_eq!(ints(database, sql), [2, 3]);
+        });
+    }
+
+    #[test]
+    fn between_and_in_lists() {
+        assert_eq!(value("2 BETWEEN 1 AND 3"), int(1));
+        assert_eq!(value("'b' NOT BETWEEN 'a' AND 'c'"), int(0));
+        assert_eq!(value("5 BETWEEN 1 AND NULL"), Null);
+        assert_eq!(value("2 IN (1, 2, 3)"), int(1));
+        assert_eq!(value("4 NOT IN (1, 2, 3)"), int(1));
+        // NULL in the list makes a miss unknown, like SQL says
+        assert_eq!(value("4 IN (1, NULL)"), Null);
+        assert_eq!(value("1 IN (1, NULL)"), int(1));
+    }
+}
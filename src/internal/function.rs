@@ -0,0 +1,878 @@
+use crate::internal::{
+    errors,
+    table::{
+        ColumnType,
+        ColumnValue::{self, Int, Null, Text},
+    },
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A built-in scalar function, such as `upper(name)`.
+pub struct ScalarFunction {
+    pub name: &'static str,
+    /// The type of each parameter, `None` accepting any type. The last
+    /// parameter repeats when the function is variadic.
+    pub parameters: &'static [Option<ColumnType>],
+    /// How many of the parameters must be passed.
+    pub required: usize,
+    pub variadic: bool,
+    /// The type of the result, `None` meaning the type of the first argument.
+    pub result_type: Option<ColumnType>,
+    /// Whether the same arguments always give the same result. Calls to the
+    /// functions that read the clock aren't computed ahead of time.
+    pub deterministic: bool,
+    /// Only called with arguments that passed the arity and type checks.
+    pub call: fn(&[ColumnValue]) -> Result<ColumnValue, errors::QueryError>,
+}
+impl ScalarFunction {
+    pub fn check_arity(&self, arguments: usize) -> Result<(), errors::QueryError> {
+        let too_many = !self.variadic && arguments > self.parameters.len();
+        if arguments < self.required || too_many {
+            return Err(errors::QueryError::WrongNumberOfArguments);
+        }
+        Ok(())
+    }
+    /// Calls the function, checking the arguments against its parameters first.
+    pub fn invoke(&self, arguments: &[ColumnValue]) -> Result<ColumnValue, errors::QueryError> {
+        self.check_arity(arguments.len())?;
+        for (index, argument) in arguments.iter().enumerate() {
+            let parameter = self.parameters[index.min(self.parameters.len() - 1)].as_ref();
+            let matches = matches!(
+                (parameter, argument),
+                (None, _)
+                    | (_, Null)
+                    | (Some(ColumnType::Int), Int(_))
+                    | (Some(ColumnType::Text), Text(_))
+            );
+            if !matches {
+                return Err(errors::QueryError::TypeMismatch);
+            }
+        }
+        (self.call)(arguments)
+    }
+}
+
+const INT: Option<ColumnType> = Some(ColumnType::Int);
+const TEXT: Option<ColumnType> = Some(ColumnType::Text);
+const ANY: Option<ColumnType> = None;
+
+/// Every built-in scalar function, shared by all expression evaluation.
+pub static SCALAR_FUNCTIONS: &[ScalarFunction] = &[
+    // strings
+    ScalarFunction {
+        name: "length",
+        parameters: &[ANY],
+        required: 1,
+        variadic: false,
+        result_type: INT,
+        deterministic: true,
+        call: |args| Ok(map_null(&args[0], |v| Int(text(v).chars().count() as i64))),
+    },
+    ScalarFunction {
+        name: "substr",
+        parameters: &[TEXT, INT, INT],
+        required: 2,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: substr,
+    },
+    ScalarFunction {
+        name: "upper",
+        parameters: &[TEXT],
+        required: 1,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: |args| Ok(map_null(&args[0], |v| Text(text(v).to_uppercase()))),
+    },
+    ScalarFunction {
+        name: "lower",
+        parameters: &[TEXT],
+        required: 1,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: |args| Ok(map_null(&args[0], |v| Text(text(v).to_lowercase()))),
+    },
+    ScalarFunction {
+        name: "trim",
+        parameters: &[TEXT, TEXT],
+        required: 1,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: |args| trim(args, true, true),
+    },
+    ScalarFunction {
+        name: "ltrim",
+        parameters: &[TEXT, TEXT],
+        required: 1,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: |args| trim(args, true, false),
+    },
+    ScalarFunction {
+        name: "rtrim",
+        parameters: &[TEXT, TEXT],
+        required: 1,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: |args| trim(args, false, true),
+    },
+    ScalarFunction {
+        name: "replace",
+        parameters: &[TEXT, TEXT, TEXT],
+        required: 3,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: true,
+        call: |args| match args {
+            [Text(value), Text(from), Text(to)] if !from.is_empty() => {
+                Ok(Text(value.replace(from.as_str(), to)))
+            }
+            [Text(value), Text(_), Text(_)] => Ok(Text(value.clone())),
+            _ => Ok(Null),
+        },
+    },
+    ScalarFunction {
+        name: "instr",
+        parameters: &[TEXT, TEXT],
+        required: 2,
+        variadic: false,
+        result_type: INT,
+        deterministic: true,
+        call: |args| match args {
+            // the 1-based character position of the first match, 0 without one
+            [Text(value), Text(needle)] => Ok(Int(value
+                .find(needle.as_str())
+                .map_or(0, |index| value[..index].chars().count() as i64 + 1))),
+            _ => Ok(Null),
+        },
+    },
+    // `like(pattern, text)` and `glob(pattern, text)`, as in SQLite
+    ScalarFunction {
+        name: "like",
+        parameters: &[ANY, ANY],
+        required: 2,
+        variadic: false,
+        result_type: INT,
+        deterministic: true,
+        call: |args| {
+            Ok(pattern_match(
+                Pattern::like(&args[0], None)?,
+                &args[1],
+                false,
+            ))
+        },
+    },
+    ScalarFunction {
+        name: "glob",
+        parameters: &[ANY, ANY],
+        required: 2,
+        variadic: false,
+        result_type: INT,
+        deterministic: true,
+        call: |args| Ok(pattern_match(Pattern::glob(&args[0])?, &args[1], false)),
+    },
+    // numbers
+    ScalarFunction {
+        name: "abs",
+        parameters: &[INT],
+        required: 1,
+        variadic: false,
+        result_type: INT,
+        deterministic: true,
+        call: |args| match &args[0] {
+            Int(value) => value
+                .checked_abs()
+                .map(Int)
+                .ok_or(errors::QueryError::IntegerOverflow),
+            _ => Ok(Null),
+        },
+    },
+    ScalarFunction {
+        name: "round",
+        parameters: &[INT, INT],
+        required: 1,
+        variadic: false,
+        result_type: INT,
+        deterministic: true,
+        // integers are already rounded
+        call: |args| Ok(args[0].clone()),
+    },
+    ScalarFunction {
+        name: "min",
+        parameters: &[ANY],
+        required: 2,
+        variadic: true,
+        result_type: None,
+        deterministic: true,
+        call: |args| Ok(extreme(args, |a, b| a < b)),
+    },
+    ScalarFunction {
+        name: "max",
+        parameters: &[ANY],
+        required: 2,
+        variadic: true,
+        result_type: None,
+        deterministic: true,
+        call: |args| Ok(extreme(args, |a, b| a > b)),
+    },
+    // NULL handling
+    ScalarFunction {
+        name: "coalesce",
+        parameters: &[ANY],
+        required: 2,
+        variadic: true,
+        result_type: None,
+        deterministic: true,
+        call: |args| Ok(coalesce(args)),
+    },
+    ScalarFunction {
+        name: "ifnull",
+        parameters: &[ANY, ANY],
+        required: 2,
+        variadic: false,
+        result_type: None,
+        deterministic: true,
+        call: |args| Ok(coalesce(args)),
+    },
+    ScalarFunction {
+        name: "nullif",
+        parameters: &[ANY, ANY],
+        required: 2,
+        variadic: false,
+        result_type: None,
+        deterministic: true,
+        call: |args| match args {
+            [a, b] if a == b => Ok(Null),
+            _ => Ok(args[0].clone()),
+        },
+    },
+    // dates and times, kept as text in UTC
+    ScalarFunction {
+        name: "date",
+        parameters: &[ANY],
+        required: 1,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: false,
+        call: |args| Ok(format_time(&args[0], "%Y-%m-%d")),
+    },
+    ScalarFunction {
+        name: "strftime",
+        parameters: &[TEXT, ANY],
+        required: 2,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: false,
+        call: |args| match &args[0] {
+            Text(format) => Ok(format_time(&args[1], format)),
+            _ => Ok(Null),
+        },
+    },
+    ScalarFunction {
+        name: "now",
+        parameters: &[],
+        required: 0,
+        variadic: false,
+        result_type: TEXT,
+        deterministic: false,
+        call: |_| Ok(format_time(&Text("now".to_string()), "%Y-%m-%d %H:%M:%S")),
+    },
+];
+
+/// Finds the built-in scalar function called `name`, ignoring case.
+pub fn scalar_function(name: &str) -> Option<&'static ScalarFunction> {
+    SCALAR_FUNCTIONS
+        .iter()
+        .find(|function| function.name.eq_ignore_ascii_case(name))
+}
+
+/// Applies `f` to a value that isn't NULL; most functions return NULL for NULL.
+fn map_null(value: &ColumnValue, f: impl Fn(&ColumnValue) -> ColumnValue) -> ColumnValue {
+    match value {
+        Null => Null,
+        value => f(value),
+    }
+}
+
+fn text(value: &ColumnValue) -> String {
+    match value {
+        Int(value) => value.to_string(),
+        Text(value) => value.clone(),
+        Null => String::new(),
+    }
+}
+
+/// `substr(text, start [, length])` with SQLite's 1-based positions: a
+/// negative start counts from the end and a negative length takes the
+/// characters before the start.
+fn substr(args: &[ColumnValue]) -> Result<ColumnValue, errors::QueryError> {
+    if args.contains(&Null) {
+        return Ok(Null);
+    }
+    let (Text(value), Int(start)) = (&args[0], &args[1]) else {
+        return Ok(Null);
+    };
+    let chars: Vec<char> = value.chars().collect();
+    let count = chars.len() as i64;
+    let mut begin = match *start {
+        start if start > 0 => start - 1,
+        start if start < 0 => count.saturating_add(start),
+        // position 0 is just before the first character
+        _ => -1,
+    };
+    let mut end = count;
+    if let Some(Int(length)) = args.get(2) {
+        if *length >= 0 {
+            end = begin.saturating_add(*length);
+        } else {
+            end = begin;
+            begin = begin.saturating_add(*length);
+        }
+    }
+    let begin = begin.clamp(0, count) as usize;
+    let end = end.clamp(0, count) as usize;
+    Ok(Text(chars[begin..end.max(begin)].iter().collect()))
+}
+
+/// One element of a LIKE or GLOB pattern.
+enum Token {
+    /// `%` or `*`
+    Any,
+    /// `_` or `?`
+    One,
+    Char(char),
+    /// `[...]` in GLOB, matching any of the ranges, or none of them when negated.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// A compiled LIKE or GLOB pattern; `None` when the pattern is NULL.
+pub struct Pattern(Option<Vec<Token>>);
+impl Pattern {
+    /// `%` matches any run of characters and `_` any single one, unless
+    /// preceded by the `escape` character.
+    pub fn like(
+        pattern: &ColumnValue,
+        escape: Option<char>,
+    ) -> Result<Pattern, errors::QueryError> {
+        if *pattern == Null {
+            return Ok(Pattern(None));
+        }
+        let mut tokens = Vec::new();
+        let mut chars = text(pattern).chars().collect::<Vec<_>>().into_iter();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                c if Some(c) == escape => Token::Char(chars.next().unwrap_or(c)),
+                '%' => Token::Any,
+                '_' => Token::One,
+                c => Token::Char(c),
+            });
+        }
+        Ok(Pattern(Some(tokens)))
+    }
+    /// `*` matches any run of characters, `?` any single one and `[...]` one
+    /// of a set like `[a-z]`, or anything but it with `[^...]`.
+    pub fn glob(pattern: &ColumnValue) -> Result<Pattern, errors::QueryError> {
+        if *pattern == Null {
+            return Ok(Pattern(None));
+        }
+        let mut tokens = Vec::new();
+        let chars: Vec<char> = text(pattern).chars().collect();
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            index += 1;
+            tokens.push(match c {
+                '*' => Token::Any,
+                '?' => Token::One,
+                '[' => {
+                    let negated = chars.get(index) == Some(&'^');
+                    if negated {
+                        index += 1;
+                    }
+                    let mut ranges = Vec::new();
+                    // a `]` right after the opening bracket is part of the set
+                    let mut first = true;
+                    loop {
+                        let Some(&start) = chars.get(index) else {
+                            return Err(errors::QueryError::SyntaxError);
+                        };
+                        index += 1;
+                        if start == ']' && !first {
+                            break;
+                        }
+                        first = false;
+                        match (chars.get(index), chars.get(index + 1)) {
+                            (Some('-'), Some(&end)) if end != ']' => {
+                                ranges.push((start, end));
+                                index += 2;
+                            }
+                            _ => ranges.push((start, start)),
+                        }
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Char(c),
+            });
+        }
+        Ok(Pattern(Some(tokens)))
+    }
+}
+
+/// Whether `value` matches `pattern`, as 1 or 0, or NULL when either is NULL.
+pub fn pattern_match(pattern: Pattern, value: &ColumnValue, case_insensitive: bool) -> ColumnValue {
+    let (Some(tokens), false) = (pattern.0, *value == Null) else {
+        return Null;
+    };
+    let fold = |c: char| {
+        if case_insensitive {
+            c.to_lowercase().next().unwrap_or(c)
+        } else {
+            c
+        }
+    };
+    let matches_one = |token: &Token, c: char| match token {
+        Token::Any => false,
+        Token::One => true,
+        Token::Char(expected) => fold(*expected) == fold(c),
+        Token::Class { negated, ranges } => {
+            ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&c))
+                != *negated
+        }
+    };
+    let chars: Vec<char> = text(value).chars().collect();
+    // walk both, backtracking to the last `%` whenever the rest fails to match
+    let (mut token, mut char) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while char < chars.len() {
+        match tokens.get(token) {
+            Some(Token::Any) => {
+                backtrack = Some((token, char));
+                token += 1;
+            }
+            Some(t) if matches_one(t, chars[char]) => {
+                token += 1;
+                char += 1;
+            }
+            _ => match backtrack {
+                Some((any, start)) => {
+                    backtrack = Some((any, start + 1));
+                    token = any + 1;
+                    char = start + 1;
+                }
+                None => return Int(0),
+            },
+        }
+    }
+    let rest_matches_empty = tokens[token..].iter().all(|t| matches!(t, Token::Any));
+    Int(rest_matches_empty as i64)
+}
+
+/// Removes the characters of the second argument, or spaces, from either end.
+fn trim(
+    args: &[ColumnValue],
+    leading: bool,
+    trailing: bool,
+) -> Result<ColumnValue, errors::QueryError> {
+    let characters: Vec<char> = match args.get(1) {
+        Some(Text(characters)) => characters.chars().collect(),
+        Some(_) => return Ok(Null),
+        None => vec![' '],
+    };
+    let Text(value) = &args[0] else {
+        return Ok(Null);
+    };
+    let mut value = value.as_str();
+    if leading {
+        value = value.trim_start_matches(characters.as_slice());
+    }
+    if trailing {
+        value = value.trim_end_matches(characters.as_slice());
+    }
+    Ok(Text(value.to_string()))
+}
+
+/// The smallest or largest argument, or NULL when any of them is NULL.
+fn extreme(args: &[ColumnValue], better: fn(&ColumnValue, &ColumnValue) -> bool) -> ColumnValue {
+    if args.contains(&Null) {
+        return Null;
+    }
+    let mut result = &args[0];
+    for arg in &args[1..] {
+        if better(arg, result) {
+            result = arg;
+        }
+    }
+    result.clone()
+}
+
+fn coalesce(args: &[ColumnValue]) -> ColumnValue {
+    args.iter()
+        .find(|arg| **arg != Null)
+        .cloned()
+        .unwrap_or(Null)
+}
+
+/// Formats a time value with the strftime directives `%Y %m %d %H %M %S %s %j %w %%`.
+/// The value is `'now'`, text like `YYYY-MM-DD [HH:MM[:SS]]`, or an integer
+/// of seconds since the Unix epoch. Anything else gives NULL, like SQLite.
+fn format_time(value: &ColumnValue, format: &str) -> ColumnValue {
+    let Some(seconds) = parse_time(value) else {
+        return Null;
+    };
+    let days = seconds.div_euclid(86400);
+    let time = seconds.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let mut result = String::new();
+    let mut directives = format.chars();
+    while let Some(c) = directives.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match directives.next() {
+            Some('Y') => result.push_str(&format!("{:04}", year)),
+            Some('m') => result.push_str(&format!("{:02}", month)),
+            Some('d') => result.push_str(&format!("{:02}", day)),
+            Some('H') => result.push_str(&format!("{:02}", time / 3600)),
+            Some('M') => result.push_str(&format!("{:02}", time % 3600 / 60)),
+            Some('S') => result.push_str(&format!("{:02}", time % 60)),
+            Some('s') => result.push_str(&seconds.to_string()),
+            Some('j') => {
+                let day_of_year = days - days_from_civil(year, 1, 1) + 1;
+                result.push_str(&format!("{:03}", day_of_year));
+            }
+            // 1970-01-01 was a Thursday
+            Some('w') => result.push_str(&(days + 4).rem_euclid(7).to_string()),
+            Some('%') => result.push('%'),
+            _ => return Null,
+        }
+    }
+    Text(result)
+}
+
+/// Seconds since the Unix epoch of a time value.
+fn parse_time(value: &ColumnValue) -> Option<i64> {
+    let value = match value {
+        Int(seconds) => return Some(*seconds),
+        Text(value) => value.trim(),
+        Null => return None,
+    };
+    if value.eq_ignore_ascii_case("now") {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+        return Some(now.as_secs() as i64);
+    }
+    let number = |s: Option<&str>, len: usize| {
+        s.filter(|s| s.len() == len && s.bytes().all(|b| b.is_ascii_digit()))?
+            .parse::<i64>()
+            .ok()
+    };
+    let (date, time) = match value.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let mut date_parts = date.split('-');
+    let year = number(date_parts.next(), 4)?;
+    let month = number(date_parts.next(), 2).filter(|m| (1..=12).contains(m))?;
+    let day = number(date_parts.next(), 2).filter(|d| (1..=31).contains(d))?;
+    if date_parts.next().is_some() {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86400;
+    if let Some(time) = time {
+        let mut time_parts = time.split(':');
+        let hour = number(time_parts.next(), 2).filter(|h| *h < 24)?;
+        let minute = number(time_parts.next(), 2).filter(|m| *m < 60)?;
+        let second = match time_parts.next() {
+            Some(second) => number(Some(second), 2).filter(|s| *s < 60)?,
+            None => 0,
+        };
+        if time_parts.next().is_some() {
+            return None;
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+    }
+    Some(seconds)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+type UserCall = dyn Fn(&[ColumnValue]) -> Result<ColumnValue, errors::QueryError>;
+
+/// A scalar function the application defined with `Database::create_function`.
+pub struct UserFunction {
+    pub arity: usize,
+    call: Box<UserCall>,
+}
+impl UserFunction {
+    pub fn invoke(&self, arguments: &[ColumnValue]) -> Result<ColumnValue, errors::QueryError> {
+        if arguments.len() != self.arity {
+            return Err(errors::QueryError::WrongNumberOfArguments);
+        }
+        (self.call)(arguments)
+    }
+}
+
+type AggregateStep = dyn Fn(&mut dyn Any, &[ColumnValue]) -> Result<(), errors::QueryError>;
+type AggregateFinalize = dyn Fn(Box<dyn Any>) -> Result<ColumnValue, errors::QueryError>;
+
+/// An aggregate function the application defined with `Database::create_aggregate`.
+/// Each group gets its own state from `init`, which `step` updates with the
+/// arguments of every row and `finalize` turns into the group's value.
+pub struct UserAggregate {
+    init: Box<dyn Fn() -> Box<dyn Any>>,
+    step: Box<AggregateStep>,
+    finalize: Box<AggregateFinalize>,
+}
+impl UserAggregate {
+    pub fn init(&self) -> Box<dyn Any> {
+        (self.init)()
+    }
+    pub fn step(
+        &self,
+        state: &mut dyn Any,
+        arguments: &[ColumnValue],
+    ) -> Result<(), errors::QueryError> {
+        (self.step)(state, arguments)
+    }
+    pub fn finalize(&self, state: Box<dyn Any>) -> Result<ColumnValue, errors::QueryError> {
+        (self.finalize)(state)
+    }
+}
+impl fmt::Debug for UserAggregate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("UserAggregate")
+    }
+}
+/// Two aggregates are the same when they are the same registration.
+impl PartialEq for UserAggregate {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// The functions an application registered on a database. They take
+/// precedence over built-in functions of the same name.
+#[derive(Default)]
+pub struct Functions {
+    scalars: HashMap<String, UserFunction>,
+    aggregates: HashMap<String, Rc<UserAggregate>>,
+}
+impl Functions {
+    pub fn create_function(
+        &mut self,
+        name: &str,
+        arity: usize,
+        call: impl Fn(&[ColumnValue]) -> Result<ColumnValue, errors::QueryError> + 'static,
+    ) {
+        let name = name.to_lowercase();
+        self.aggregates.remove(&name);
+        let call = Box::new(call);
+        self.scalars.insert(name, UserFunction { arity, call });
+    }
+    pub fn create_aggregate<S: 'static>(
+        &mut self,
+        name: &str,
+        init: impl Fn() -> S + 'static,
+        step: impl Fn(&mut S, &[ColumnValue]) -> Result<(), errors::QueryError> + 'static,
+        finalize: impl Fn(S) -> Result<ColumnValue, errors::QueryError> + 'static,
+    ) {
+        let name = name.to_lowercase();
+        self.scalars.remove(&name);
+        let aggregate = UserAggregate {
+            init: Box::new(move || Box::new(init())),
+            step: Box::new(move |state, arguments| {
+                step(state.downcast_mut::<S>().unwrap(), arguments)
+            }),
+            finalize: Box::new(move |state| finalize(*state.downcast::<S>().unwrap())),
+        };
+        self.aggregates.insert(name, Rc::new(aggregate));
+    }
+    pub fn scalar(&self, name: &str) -> Option<&UserFunction> {
+        self.scalars.get(&name.to_lowercase())
+    }
+    pub fn aggregate(&self, name: &str) -> Option<Rc<UserAggregate>> {
+        self.aggregates.get(&name.to_lowercase()).cloned()
+    }
+}
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Functions")
+            .field("scalars", &self.scalars.keys().collect::<Vec<_>>())
+            .field("aggregates", &self.aggregates.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::testing::{error, rows, text, values, with_tables};
+
+    fn call(name: &str, arguments: &[ColumnValue]) -> Result<ColumnValue, errors::QueryError> {
+        scalar_function(name).unwrap().invoke(arguments)
+    }
+
+    #[test]
+    fn string_functions() {
+        assert_eq!(call("length", &[text("héllo")]).unwrap(), Int(5));
+        assert_eq!(call("UPPER", &[text("abc")]).unwrap(), text("ABC"));
+        assert_eq!(
+            call("substr", &[text("hello"), Int(2), Int(3)]).unwrap(),
+            text("ell")
+        );
+        assert_eq!(
+            call("substr", &[text("hello"), Int(-3)]).unwrap(),
+            text("llo")
+        );
+        assert_eq!(call("trim", &[text("  a  ")]).unwrap(), text("a"));
+        assert_eq!(call("ltrim", &[text("xxa"), text("x")]).unwrap(), text("a"));
+        let replaced = call("replace", &[text("a-b-c"), text("-"), text("+")]).unwrap();
+        assert_eq!(replaced, text("a+b+c"));
+        assert_eq!(call("instr", &[text("hello"), text("l")]).unwrap(), Int(3));
+        assert_eq!(call("instr", &[text("hello"), text("z")]).unwrap(), Int(0));
+    }
+
+    #[test]
+    fn numeric_and_null_functions() {
+        assert_eq!(call("abs", &[Int(-3)]).unwrap(), Int(3));
+        assert!(matches!(
+            call("abs", &[Int(i64::MIN)]),
+            Err(errors::QueryError::IntegerOverflow)
+        ));
+        assert_eq!(call("min", &[Int(3), Int(1), Int(2)]).unwrap(), Int(1));
+        assert_eq!(call("max", &[Int(3), Null]).unwrap(), Null);
+        assert_eq!(call("coalesce", &[Null, Null, Int(2)]).unwrap(), Int(2));
+        assert_eq!(call("ifnull", &[Null, text("x")]).unwrap(), text("x"));
+        assert_eq!(call("nullif", &[Int(1), Int(1)]).unwrap(), Null);
+        assert_eq!(call("nullif", &[Int(1), Int(2)]).unwrap(), Int(1));
+    }
+
+    #[test]
+    fn date_functions() {
+        let day = text("2024-02-29 13:45:10");
+        assert_eq!(
+            call("date", std::slice::from_ref(&day)).unwrap(),
+            text("2024-02-29")
+        );
+        let formatted = call("strftime", &[text("%H:%M:%S %j %w"), day]).unwrap();
+        assert_eq!(formatted, text("13:45:10 060 4"));
+        assert_eq!(call("date", &[Int(86400)]).unwrap(), text("1970-01-02"));
+        assert_eq!(call("date", &[text("not a date")]).unwrap(), Null);
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        assert!(matches!(
+            call("upper", &[]),
+            Err(errors::QueryError::WrongNumberOfArguments)
+        ));
+        assert!(matches!(
+            call("abs", &[text("a")]),
+            Err(errors::QueryError::TypeMismatch)
+        ));
+        // NULL goes with any parameter type
+        assert_eq!(call("upper", &[Null]).unwrap(), Null);
+    }
+
+    #[test]
+    fn functions_in_queries() {
+        with_tables(&[], |database| {
+            let sql = "SELECT upper(substr('litesql', 1, 4)), length(trim('  ab '))";
+            assert_eq!(values(database, sql), [text("LITE"), Int(2)]);
+            let e = error(database, "SELECT nope(1)");
+            assert!(matches!(e, errors::QueryError::UnknownFunction));
+        });
+    }
+
+    #[test]
+    fn user_defined_scalar_functions() {
+        with_tables(&[], |database| {
+            database.create_function("double", 1, |args| match &args[0] {
+                Int(value) => Ok(Int(value * 2)),
+                _ => Err(errors::QueryError::TypeMismatch),
+            });
+            let result = values(database, "SELECT double(21), DOUBLE(double(1))");
+            assert_eq!(result, [Int(42), Int(4)]);
+            let e = error(database, "SELECT double(1, 2)");
+            assert!(matches!(e, errors::QueryError::WrongNumberOfArguments));
+            // errors from the function fail the query
+            let e = error(database, "SELECT double('a')");
+            assert!(matches!(e, errors::QueryError::TypeMismatch));
+        });
+    }
+
+    #[test]
+    fn user_defined_functions_shadow_built_in_ones() {
+        with_tables(&[], |database| {
+            database.create_function("upper", 1, |_| Ok(text("shadowed")));
+            assert_eq!(values(database, "SELECT upper('a')"), [text("shadowed")]);
+        });
+    }
+
+    #[test]
+    fn user_defined_aggregates() {
+        with_tables(&[], |database| {
+            database.create_aggregate(
+                "product",
+                || 1,
+                |state: &mut i64, args| {
+                    if let Int(value) = args[0] {
+                        *state *= value;
+                    }
+                    Ok(())
+                },
+                |state| Ok(Int(state)),
+            );
+            let sql = "SELECT product(n) FROM \
+                (SELECT 2 AS n UNION ALL SELECT 3 UNION ALL SELECT 4)";
+            assert_eq!(values(database, sql), [Int(24)]);
+            let sql = "SELECT n % 2, product(n) FROM \
+                (SELECT 2 AS n UNION ALL SELECT 3 UNION ALL SELECT 4) GROUP BY n % 2 ORDER BY 1";
+            assert_eq!(rows(database, sql), [[Int(0), Int(8)], [Int(1), Int(3)]]);
+        });
+    }
+}
@@ -0,0 +1,390 @@
+use crate::internal::{
+    errors,
+    expression::{evaluate, is_bound_by, resolve, truthy, Context, Field, Scope},
+    table::ColumnValue,
+};
+use sqlparser::ast::{BinaryOperator, Expr, Ident, JoinConstraint};
+use std::collections::HashMap;
+
+/// Rows that were read into memory, along with the fields describing them.
+#[derive(Debug)]
+pub struct Relation {
+    pub fields: Vec<Field>,
+    pub rows: Vec<Vec<ColumnValue>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+/// A join condition split into equalities between the two sides, which can be
+/// used as hash keys, and whatever else has to be checked on the joined row.
+struct Condition {
+    left_keys: Vec<Expr>,
+    right_keys: Vec<Expr>,
+    residual: Vec<Expr>,
+}
+impl Condition {
+    fn new(on: Option<&Expr>, left: &[Field], right: &[Field]) -> Condition {
+        let mut condition = Condition {
+            left_keys: Vec::new(),
+            right_keys: Vec::new(),
+            residual: Vec::new(),
+        };
+        let mut conjuncts = Vec::new();
+        if let Some(on) = on {
+            split_conjuncts(on, &mut conjuncts);
+        }
+        for conjunct in conjuncts {
+            if let Expr::BinaryOp {
+                left: a,
+                op: BinaryOperator::Eq,
+                right: b,
+            } = conjunct
+            {
+                if is_bound_by(a, left) && is_bound_by(b, right) {
+                    condition.left_keys.push(*a.clone());
+                    condition.right_keys.push(*b.clone());
+                    continue;
+                }
+                if is_bound_by(b, left) && is_bound_by(a, right) {
+                    condition.left_keys.push(*b.clone());
+                    condition.right_keys.push(*a.clone());
+                    continue;
+                }
+            }
+            condition.residual.push(conjunct.clone());
+        }
+        condition
+    }
+}
+
+/// Splits `expr` into the conditions that are ANDed together.
+pub fn split_conjuncts<'a>(expr: &'a Expr, conjuncts: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            split_conjuncts(left, conjuncts);
+            split_conjuncts(right, conjuncts);
+        }
+        Expr::Nested(expr) => split_conjuncts(expr, conjuncts),
+        _ => conjuncts.push(expr),
+    }
+}
+
+/// Evaluates join keys; `None` when one of them is NULL, since NULL never equals anything.
+fn keys(
+    exprs: &[Expr],
+    fields: &[Field],
+    row: &[ColumnValue],
+    context: &Context,
+) -> Result<Option<Vec<ColumnValue>>, errors::QueryError> {
+    let scope = Scope::new(fields, row).within(Some(context));
+    let mut values = Vec::new();
+    for expr in exprs {
+        match evaluate(expr, &scope)? {
+            ColumnValue::Null => return Ok(None),
+            value => values.push(value),
+        }
+    }
+    Ok(Some(values))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinAlgorithm {
+    /// Builds a hash table over the right relation's join keys.
+    Hash,
+    /// Compares every pair of rows.
+    NestedLoop,
+}
+
+/// The columns a `USING` or `NATURAL` join merges.
+pub fn using_columns(left: &[Field], right: &[Field], constraint: &JoinConstraint) -> Vec<Ident> {
+    match constraint {
+        JoinConstraint::Using(columns) => columns.clone(),
+        JoinConstraint::Natural => left
+            .iter()
+            .filter(|field| !field.hidden)
+            .filter(|field| {
+                let name = Ident::new(&field.column.name);
+                resolve(right, std::slice::from_ref(&name)).is_ok()
+            })
+            .map(|field| Ident::new(&field.column.name))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the join has equality conditions between its sides, which a hash join can use.
+pub fn is_equi_join(left: &[Field], right: &[Field], constraint: &JoinConstraint) -> bool {
+    let on = match constraint {
+        JoinConstraint::On(on) => Some(on),
+        _ => None,
+    };
+    !using_columns(left, right, constraint).is_empty()
+        || !Condition::new(on, left, right).left_keys.is_empty()
+}
+
+/// The fields of the rows joining `left` and `right` produces. Columns joined
+/// with USING appear once, first, and the original ones are hidden.
+pub fn join_fields(
+    left: &[Field],
+    right: &[Field],
+    constraint: &JoinConstraint,
+) -> Result<Vec<Field>, errors::QueryError> {
+    let mut fields = left.to_vec();
+    fields.extend(right.iter().cloned());
+    let mut merged = Vec::new();
+    for name in using_columns(left, right, constraint) {
+        let name = std::slice::from_ref(&name);
+        let (left_index, right_index) = (resolve(left, name)?, resolve(right, name)?);
+        let mut field = left[left_index].clone();
+        field.relation = None;
+        merged.push(field);
+        fields[left_index].hidden = true;
+        fields[left.len() + right_index].hidden = true;
+    }
+    merged.extend(fields);
+    Ok(merged)
+}
+
+/// Joins two relations, using `algorithm` for the equality conditions between
+/// the two sides. Anything else is checked on each pair of rows.
+pub fn join(
+    left: Relation,
+    right: Relation,
+    kind: JoinKind,
+    constraint: &JoinConstraint,
+    algorithm: JoinAlgorithm,
+    context: &Context,
+) -> Result<Relation, errors::QueryError> {
+    let using = using_columns(&left.fields, &right.fields, constraint);
+    let mut condition = match constraint {
+        JoinConstraint::On(on) => Condition::new(Some(on), &left.fields, &right.fields),
+        _ => Condition::new(None, &left.fields, &right.fields),
+    };
+    let mut using_indexes = Vec::new();
+    for name in &using {
+        let name = std::slice::from_ref(name);
+        using_indexes.push((resolve(&left.fields, name)?, resolve(&right.fields, name)?));
+        condition.left_keys.push(Expr::Identifier(name[0].clone()));
+        condition.right_keys.push(Expr::Identifier(name[0].clone()));
+    }
+
+    let mut fields = left.fields.clone();
+    fields.extend(right.fields.iter().cloned());
+    let left_nulls = vec![ColumnValue::Null; left.fields.len()];
+    let right_nulls = vec![ColumnValue::Null; right.fields.len()];
+    let mut right_matched = vec![false; right.rows.len()];
+    let mut rows = Vec::new();
+
+    let matches = |left_row: &[ColumnValue], right_row: &[ColumnValue]| {
+        let row = [left_row, right_row].concat();
+        let scope = Scope::new(&fields, &row).within(Some(context));
+        for expr in &condition.residual {
+            if !truthy(&evaluate(expr, &scope)?) {
+                return Ok(None);
+            }
+        }
+        Ok::<_, errors::QueryError>(Some(row))
+    };
+
+    if algorithm == JoinAlgorithm::NestedLoop {
+        let right_keys = right
+            .rows
+            .iter()
+            .map(|row| keys(&condition.right_keys, &right.fields, row, context))
+            .collect::<Result<Vec<_>, _>>()?;
+        for left_row in &left.rows {
+            let left_keys = keys(&condition.left_keys, &left.fields, left_row, context)?;
+            let mut matched = false;
+            for (index, right_row) in right.rows.iter().enumerate() {
+                if left_keys.is_none() || left_keys != right_keys[index] {
+                    continue;
+                }
+                if let Some(row) = matches(left_row, right_row)? {
+                    matched = true;
+                    right_matched[index] = true;
+                    rows.push(row);
+                }
+            }
+            if !matched && matches!(kind, JoinKind::Left | JoinKind::Full) {
+                rows.push([&left_row[..], &right_nulls].concat());
+            }
+        }
+    } else {
+        // build the hash table over the right relation
+        let mut table: HashMap<Vec<ColumnValue>, Vec<usize>> = HashMap::new();
+        for (index, right_row) in right.rows.iter().enumerate() {
+            if let Some(keys) = keys(&condition.right_keys, &right.fields, right_row, context)? {
+                table.entry(keys).or_default().push(index);
+            }
+        }
+        for left_row in &left.rows {
+            let mut matched = false;
+            if let Some(keys) = keys(&condition.left_keys, &left.fields, left_row, context)? {
+                for index in table.get(&keys).into_iter().flatten() {
+                    if let Some(row) = matches(left_row, &right.rows[*index])? {
+                        matched = true;
+                        right_matched[*index] = true;
+                        rows.push(row);
+                    }
+                }
+            }
+            if !matched && matches!(kind, JoinKind::Left | JoinKind::Full) {
+                rows.push([&left_row[..], &right_nulls].concat());
+            }
+        }
+    }
+    if matches!(kind, JoinKind::Right | JoinKind::Full) {
+        for (right_row, matched) in right.rows.iter().zip(right_matched) {
+            if !matched {
+                rows.push([&left_nulls[..], right_row].concat());
+            }
+        }
+    }
+
+    if using_indexes.is_empty() {
+        return Ok(Relation { fields, rows });
+    }
+    // merged columns hold whichever side isn't NULL
+    let rows = rows
+        .into_iter()
+        .map(|row| {
+            let mut merged_row: Vec<ColumnValue> = using_indexes
+                .iter()
+                .map(|(left_index, right_index)| match &row[*left_index] {
+                    ColumnValue::Null => row[left.fields.len() + *right_index].clone(),
+                    value => value.clone(),
+                })
+                .collect();
+            merged_row.extend(row);
+            merged_row
+        })
+        .collect();
+    Ok(Relation {
+        fields: join_fields(&left.fields, &right.fields, constraint)?,
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        table::ColumnValue::{self, Null},
+        testing::{error, int, text, values, with_tables, Fixture},
+    };
+
+    /// Employees and departments, each with one that has no match on the other side.
+    const COMPANY: [Fixture; 2] = [
+        (
+            "emp",
+            "name TEXT, dept INT",
+            &["'ada', 1", "'bob', 2", "'cy', 9"],
+        ),
+        (
+            "dept",
+            "id INT, title TEXT",
+            &["1, 'eng'", "2, 'ops'", "3, 'hr'"],
+        ),
+    ];
+
+    fn pairs(pairs: &[(Option<&str>, Option<&str>)]) -> Vec<ColumnValue> {
+        pairs
+            .iter()
+            .flat_map(|(name, title)| [name.map_or(Null, text), title.map_or(Null, text)])
+            .collect()
+    }
+
+    #[test]
+    fn inner_join() {
+        with_tables(&COMPANY, |database| {
+            let sql = "SELECT e.name, d.title FROM emp e JOIN dept d ON e.dept = d.id ORDER BY 1";
+            let expected = pairs(&[(Some("ada"), Some("eng")), (Some("bob"), Some("ops"))]);
+            assert_eq!(values(database, sql), expected);
+            // the same rows come out of a nested loop when the condition isn't an equality
+            let sql =
+                "SELECT name, title FROM emp, dept WHERE dept <= id AND dept >= id ORDER BY 1";
+            assert_eq!(values(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn left_join() {
+        with_tables(&COMPANY, |database| {
+            let sql = "SELECT name, title FROM emp LEFT JOIN dept ON dept = id ORDER BY 1";
+            let expected = pairs(&[
+                (Some("ada"), Some("eng")),
+                (Some("bob"), Some("ops")),
+                (Some("cy"), None),
+            ]);
+            assert_eq!(values(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn right_join() {
+        with_tables(&COMPANY, |database| {
+            let sql = "SELECT name, title FROM emp RIGHT JOIN dept ON dept = id ORDER BY 2";
+            let expected = pairs(&[
+                (Some("ada"), Some("eng")),
+                (None, Some("hr")),
+                (Some("bob"), Some("ops")),
+            ]);
+            assert_eq!(values(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn full_outer_join() {
+        with_tables(&COMPANY, |database| {
+            let sql = "SELECT name, title FROM emp FULL OUTER JOIN dept ON dept = id \
+                ORDER BY 1 NULLS LAST, 2";
+            let expected = pairs(&[
+                (Some("ada"), Some("eng")),
+                (Some("bob"), Some("ops")),
+                (Some("cy"), None),
+                (None, Some("hr")),
+            ]);
+            assert_eq!(values(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn cross_join() {
+        with_tables(&COMPANY, |database| {
+            let result = values(database, "SELECT count(*) FROM emp CROSS JOIN dept");
+            assert_eq!(result, [int(9)]);
+        });
+    }
+
+    #[test]
+    fn using_and_natural_joins() {
+        let boss = ("boss", "dept INT, boss TEXT", &["1, 'eve'", "2, 'fay'"][..]);
+        with_tables(&[COMPANY[0], boss], |database| {
+            let sql = "SELECT name, boss FROM emp JOIN boss USING (dept) ORDER BY 1";
+            let expected = [text("ada"), text("eve"), text("bob"), text("fay")];
+            assert_eq!(values(database, sql), expected);
+            let sql = "SELECT name, boss FROM emp NATURAL JOIN boss ORDER BY 1";
+            assert_eq!(values(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn ambiguous_columns() {
+        with_tables(&[COMPANY[0], ("other", "dept INT", &["1"])], |database| {
+            let e = error(database, "SELECT dept FROM emp, other");
+            assert!(matches!(e, QueryError::AmbiguousColumn));
+            let result = values(database, "SELECT other.dept FROM emp, other LIMIT 1");
+            assert_eq!(result, [int(1)]);
+        });
+    }
+}
@@ -0,0 +1,249 @@
+use crate::internal::table::Table;
+use std::{fs, io, path::Path};
+
+static JOURNAL_DIR: &str = "database.journal";
+/// Written last by `begin`, so that a journal cut short while it was being
+/// copied, before anything changed, isn't mistaken for a complete one.
+static COMPLETE_FILE: &str = "complete";
+
+/// Copies of the catalog and of every table's files, taken when a transaction
+/// starts so it can be undone. Like SQLite's rollback journal, but copying
+/// whole tables rather than the pages about to change.
+///
+/// A journal still there when a database is loaded is hot, as in SQLite: the
+/// process running the transaction stopped before ending it, and `recover`
+/// undoes what it did.
+pub struct Journal;
+impl Journal {
+    /// Fails if there is a journal already, since it may be the only copy of
+    /// what a transaction that never ended changed.
+    pub fn begin(catalog_file: &str, tables: &[Table]) -> Result<Journal, io::Error> {
+        let journal = Path::new(JOURNAL_DIR);
+        fs::create_dir(journal)?;
+        if Path::new(catalog_file).exists() {
+            fs::copy(catalog_file, journal.join(catalog_file))?;
+        }
+        for table in tables {
+            let copy = journal.join(&table.name);
+            fs::create_dir(&copy)?;
+            for entry in fs::read_dir(&table.name)? {
+                let entry = entry?;
+                fs::copy(entry.path(), copy.join(entry.file_name()))?;
+            }
+        }
+        fs::File::create(journal.join(COMPLETE_FILE))?;
+        Ok(Journal)
+    }
+    /// Keeps what the transaction did.
+    pub fn commit(self) -> Result<(), io::Error> {
+        fs::remove_dir_all(JOURNAL_DIR)
+    }
+    /// Puts the catalog and the tables back the way they were when the
+    /// transaction started. `tables` are the ones there are now, which may
+    /// have been created or renamed since.
+    pub fn rollback(self, catalog_file: &str, tables: &[Table]) -> Result<(), io::Error> {
+        restore(catalog_file, tables)
+    }
+    /// Whether a transaction left its journal behind.
+    pub fn is_hot() -> bool {
+        Path::new(JOURNAL_DIR).exists()
+    }
+    /// Undoes the transaction whose journal was left behind, if there is one.
+    /// `tables` are the ones in the catalog now.
+    pub fn recover(catalog_file: &str, tables: &[Table]) -> Result<(), io::Error> {
+        let journal = Path::new(JOURNAL_DIR);
+        if !journal.exists() {
+            return Ok(());
+        }
+        if journal.join(COMPLETE_FILE).exists() {
+            restore(catalog_file, tables)
+        } else {
+            // nothing changed while the journal was being copied
+            fs::remove_dir_all(journal)
+        }
+    }
+}
+
+/// Copies the catalog and the tables back from the journal, then deletes it.
+/// The journal is only deleted once everything is back, so a restore cut
+/// short is done again from the start by `recover`.
+fn restore(catalog_file: &str, tables: &[Table]) -> Result<(), io::Error> {
+    let journal = Path::new(JOURNAL_DIR);
+    // the tables created since it started, before the catalog naming them is
+    // put back
+    for table in tables {
+        if !journal.join(&table.name).exists() && Path::new(&table.name).exists() {
+            fs::remove_dir_all(&table.name)?;
+        }
+    }
+    for entry in fs::read_dir(journal)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        let table = Path::new(&name);
+        if table.exists() {
+            fs::remove_dir_all(table)?;
+        }
+        fs::create_dir(table)?;
+        for file in fs::read_dir(entry.path())? {
+            let file = file?;
+            fs::copy(file.path(), table.join(file.file_name()))?;
+        }
+    }
+    let catalog = journal.join(catalog_file);
+    if catalog.exists() {
+        fs::copy(catalog, catalog_file)?;
+    } else if Path::new(catalog_file).exists() {
+        fs::remove_file(catalog_file)?;
+    }
+    fs::remove_dir_all(journal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{COMPLETE_FILE, JOURNAL_DIR};
+    use crate::internal::{
+        database::Database,
+        errors::QueryError,
+        table::QueryResult,
+        testing::{error, execute, ints, rows, text, with_tables, Fixture},
+    };
+    use std::{fs, path::Path};
+
+    const NUMBERS: Fixture = ("numbers", "n INT", &["1", "2"]);
+
+    #[test]
+    fn a_transaction_that_succeeds_is_kept() {
+        with_tables(&[NUMBERS], |database| {
+            let results = database
+                .execute_batch_in_transaction(
+                    "INSERT INTO numbers (n) VALUES (3);
+                    CREATE TABLE words (w TEXT);
+                    INSERT INTO words (w) VALUES ('a');",
+                )
+                .unwrap();
+            assert_eq!(results.len(), 3);
+            assert!(!Path::new(JOURNAL_DIR).exists());
+            let mut database = Database::load().unwrap();
+            assert_eq!(ints(&mut database, "SELECT n FROM numbers"), [1, 2, 3]);
+            assert_eq!(rows(&mut database, "SELECT w FROM words"), [[text("a")]]);
+        });
+    }
+
+    #[test]
+    fn a_transaction_that_fails_is_undone() {
+        with_tables(&[NUMBERS], |database| {
+            let e = database
+                .execute_batch_in_transaction(
+                    "INSERT INTO numbers (n) VALUES (3);
+                    CREATE TABLE words (w TEXT);
+                    ALTER TABLE numbers RENAME TO digits;
+                    SELECT * FROM numbers;",
+                )
+                .unwrap_err();
+            assert_eq!(e.index, 3);
+            assert!(matches!(e.error, QueryError::UnknownTable));
+            assert!(!Path::new(JOURNAL_DIR).exists());
+            for name in ["words", "digits"] {
+                assert!(!Path::new(name).exists(), "{name}");
+            }
+            for database in [database, &mut Database::load().unwrap()] {
+                assert_eq!(ints(database, "SELECT n FROM numbers"), [1, 2]);
+                let e = error(database, "SELECT * FROM words");
+                assert!(matches!(e, QueryError::UnknownTable));
+            }
+        });
+    }
+
+    #[test]
+    fn a_transaction_that_fails_to_parse_changes_nothing() {
+        with_tables(&[NUMBERS], |database| {
+            let e = database
+                .execute_batch_in_transaction("INSERT INTO numbers (n) VALUES (3); SELEC")
+                .unwrap_err();
+            assert_eq!(e.index, 1);
+            assert!(matches!(e.error, QueryError::SyntaxError));
+            assert_eq!(ints(database, "SELECT n FROM numbers"), [1, 2]);
+        });
+    }
+
+    #[test]
+    fn begin_and_commit_in_a_transaction_do_nothing() {
+        with_tables(&[NUMBERS], |database| {
+            let e = database
+                .execute_batch_in_transaction(
+                    "BEGIN;
+                    INSERT INTO numbers (n) VALUES (3);
+                    COMMIT;
+                    SELECT * FROM nope;",
+                )
+                .unwrap_err();
+            assert_eq!(e.index, 3);
+            // the insert was undone along with the rest of the script
+            assert_eq!(ints(database, "SELECT n FROM numbers"), [1, 2]);
+            let results = database
+                .execute_batch_in_transaction("BEGIN TRANSACTION; COMMIT;")
+                .unwrap();
+            assert!(matches!(
+                results[..],
+                [
+                    QueryResult::TransactionSucceeded,
+                    QueryResult::TransactionSucceeded
+                ]
+            ));
+        });
+    }
+
+    /// Leaves the journal of a transaction that inserted a row into `numbers`,
+    /// created `words` and was never ended, as a process stopped in the
+    /// middle of it would.
+    fn stop_in_a_transaction(database: &mut Database) {
+        let copy = Path::new(JOURNAL_DIR).join("numbers");
+        fs::create_dir_all(&copy).unwrap();
+        for entry in fs::read_dir("numbers").unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(entry.path(), copy.join(entry.file_name())).unwrap();
+        }
+        fs::copy(
+            "database.json",
+            Path::new(JOURNAL_DIR).join("database.json"),
+        )
+        .unwrap();
+        fs::File::create(Path::new(JOURNAL_DIR).join(COMPLETE_FILE)).unwrap();
+        execute(database, "INSERT INTO numbers (n) VALUES (3)");
+        execute(database, "CREATE TABLE words (w TEXT)");
+    }
+
+    #[test]
+    fn a_transaction_left_unended_is_undone_on_load() {
+        with_tables(&[NUMBERS], |database| {
+            stop_in_a_transaction(database);
+            // the journal isn't lost to the next transaction before it's read
+            let e = database
+                .execute_batch_in_transaction("INSERT INTO numbers (n) VALUES (4)")
+                .unwrap_err();
+            assert!(matches!(e.error, QueryError::IOError(_)));
+            assert!(Path::new(JOURNAL_DIR).exists());
+
+            let mut database = Database::load().unwrap();
+            assert!(!Path::new(JOURNAL_DIR).exists());
+            assert!(!Path::new("words").exists());
+            assert_eq!(ints(&mut database, "SELECT n FROM numbers"), [1, 2]);
+            let e = error(&mut database, "SELECT * FROM words");
+            assert!(matches!(e, QueryError::UnknownTable));
+        });
+    }
+
+    #[test]
+    fn a_journal_cut_short_is_discarded_on_load() {
+        with_tables(&[NUMBERS], |database| {
+            fs::create_dir_all(Path::new(JOURNAL_DIR).join("numbers")).unwrap();
+            execute(database, "INSERT INTO numbers (n) VALUES (3)");
+            let mut database = Database::load().unwrap();
+            assert!(!Path::new(JOURNAL_DIR).exists());
+            assert_eq!(ints(&mut database, "SELECT n FROM numbers"), [1, 2, 3]);
+        });
+    }
+}
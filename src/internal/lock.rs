@@ -0,0 +1,260 @@
+use crate::internal::errors::LockError;
+use std::{
+    fs::{File, OpenOptions},
+    io, thread,
+    time::{Duration, Instant},
+};
+
+static SHARED_LOCK_FILE: &str = "database.lock";
+static RESERVED_LOCK_FILE: &str = "database.lock.reserved";
+static PENDING_LOCK_FILE: &str = "database.lock.pending";
+static RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Lock levels a database handle can hold, following SQLite's escalation:
+/// readers hold `Shared`, a writer goes `Shared -> Reserved -> Pending -> Exclusive`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    #[default]
+    Unlocked,
+    /// Reading is allowed, any number of processes can hold it.
+    Shared,
+    /// This process intends to write; other readers may still come and go.
+    Reserved,
+    /// This process is waiting for readers to finish; no new readers are let in.
+    Pending,
+    /// This process is the only one reading or writing.
+    Exclusive,
+}
+
+/// Advisory locks on the current database directory.
+///
+/// Each level is backed by an OS file lock on its own file, because a process
+/// can only hold one kind of lock per file.
+#[derive(Debug, Default)]
+pub struct Lock {
+    level: LockLevel,
+    shared: Option<File>,
+    reserved: Option<File>,
+    pending: Option<File>,
+}
+impl Lock {
+    pub fn level(&self) -> LockLevel {
+        self.level
+    }
+    /// Escalates the lock one level at a time until `level` is held,
+    /// retrying each step until `timeout` expires.
+    pub fn acquire(&mut self, level: LockLevel, timeout: Duration) -> Result<(), LockError> {
+        let deadline = Instant::now() + timeout;
+        while self.level < level {
+            match self.step_up() {
+                Ok(()) => continue,
+                Err(LockError::Busy) => {
+                    // Waiting for `Reserved` while holding `Shared` could deadlock
+                    // with the writer waiting for us to leave, so back off completely.
+                    if self.level == LockLevel::Shared {
+                        self.release()?;
+                    }
+                    if Instant::now() >= deadline {
+                        self.release()?;
+                        return Err(LockError::Busy);
+                    }
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(e) => {
+                    self.release()?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Drops every lock held by this handle.
+    pub fn release(&mut self) -> Result<(), LockError> {
+        for file in [&self.shared, &self.reserved, &self.pending]
+            .into_iter()
+            .flatten()
+        {
+            file.unlock()?;
+        }
+        self.level = LockLevel::Unlocked;
+        Ok(())
+    }
+    fn step_up(&mut self) -> Result<(), LockError> {
+        match self.level {
+            LockLevel::Unlocked => {
+                // A writer holding `Pending` keeps new readers out.
+                let pending = open(&mut self.pending, PENDING_LOCK_FILE)?;
+                try_lock(pending.try_lock_shared())?;
+                let shared = open(&mut self.shared, SHARED_LOCK_FILE)?;
+                let result = try_lock(shared.try_lock_shared());
+                pending.unlock()?;
+                result?;
+                self.level = LockLevel::Shared;
+            }
+            LockLevel::Shared => {
+                let reserved = open(&mut self.reserved, RESERVED_LOCK_FILE)?;
+                try_lock(reserved.try_lock())?;
+                self.level = LockLevel::Reserved;
+            }
+            LockLevel::Reserved => {
+                let pending = open(&mut self.pending, PENDING_LOCK_FILE)?;
+                try_lock(pending.try_lock())?;
+                self.level = LockLevel::Pending;
+            }
+            LockLevel::Pending => {
+                // A handle can't portably trade its shared lock for an exclusive
+                // one, so give it up first; `Pending` keeps new readers out and
+                // `Reserved` other writers, so nothing changes in between.
+                let shared = open(&mut self.shared, SHARED_LOCK_FILE)?;
+                shared.unlock()?;
+                try_lock(shared.try_lock())?;
+                self.level = LockLevel::Exclusive;
+            }
+            LockLevel::Exclusive => {}
+        }
+        Ok(())
+    }
+}
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+fn open<'a>(file: &'a mut Option<File>, path: &str) -> Result<&'a File, io::Error> {
+    if file.is_none() {
+        let opened = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        *file = Some(opened);
+    }
+    Ok(file.as_ref().unwrap())
+}
+
+fn try_lock(result: Result<(), std::fs::TryLockError>) -> Result<(), LockError> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(std::fs::TryLockError::WouldBlock) => Err(LockError::Busy),
+        Err(std::fs::TryLockError::Error(e)) => Err(LockError::IOError(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{database::Database, errors::QueryError, testing::in_temp_dir};
+
+    static NO_WAIT: Duration = Duration::ZERO;
+
+    #[test]
+    fn readers_share_the_database() {
+        in_temp_dir(|| {
+            let (mut a, mut b) = (Lock::default(), Lock::default());
+            a.acquire(LockLevel::Shared, NO_WAIT).unwrap();
+            b.acquire(LockLevel::Shared, NO_WAIT).unwrap();
+            assert_eq!(a.level(), LockLevel::Shared);
+            assert_eq!(b.level(), LockLevel::Shared);
+        });
+    }
+
+    #[test]
+    fn writer_escalates_to_exclusive() {
+        in_temp_dir(|| {
+            let mut lock = Lock::default();
+            lock.acquire(LockLevel::Exclusive, NO_WAIT).unwrap();
+            assert_eq!(lock.level(), LockLevel::Exclusive);
+            lock.release().unwrap();
+            assert_eq!(lock.level(), LockLevel::Unlocked);
+            // released locks can be taken again
+            lock.acquire(LockLevel::Exclusive, NO_WAIT).unwrap();
+        });
+    }
+
+    #[test]
+    fn only_one_writer_reserves() {
+        in_temp_dir(|| {
+            let (mut a, mut b) = (Lock::default(), Lock::default());
+            a.acquire(LockLevel::Reserved, NO_WAIT).unwrap();
+            assert!(matches!(
+                b.acquire(LockLevel::Reserved, NO_WAIT),
+                Err(LockError::Busy)
+            ));
+            // readers still come and go
+            b.acquire(LockLevel::Shared, NO_WAIT).unwrap();
+        });
+    }
+
+    #[test]
+    fn exclusive_waits_for_readers() {
+        in_temp_dir(|| {
+            let (mut reader, mut writer) = (Lock::default(), Lock::default());
+            reader.acquire(LockLevel::Shared, NO_WAIT).unwrap();
+            let result = writer.acquire(LockLevel::Exclusive, Duration::from_millis(50));
+            assert!(matches!(result, Err(LockError::Busy)));
+            // giving up releases everything the writer had taken
+            assert_eq!(writer.level(), LockLevel::Unlocked);
+            reader.release().unwrap();
+            writer.acquire(LockLevel::Exclusive, NO_WAIT).unwrap();
+        });
+    }
+
+    #[test]
+    fn pending_keeps_new_readers_out() {
+        in_temp_dir(|| {
+            let (mut writer, mut reader) = (Lock::default(), Lock::default());
+            writer.acquire(LockLevel::Pending, NO_WAIT).unwrap();
+            assert!(matches!(
+                reader.acquire(LockLevel::Shared, NO_WAIT),
+                Err(LockError::Busy)
+            ));
+        });
+    }
+
+    #[test]
+    fn busy_timeout_waits_then_fails() {
+        in_temp_dir(|| {
+            let mut writer = Lock::default();
+            writer.acquire(LockLevel::Exclusive, NO_WAIT).unwrap();
+            let mut database = Database::new();
+            database.set_busy_timeout(Duration::from_millis(100));
+            let start = Instant::now();
+            let result = database.query("CREATE TABLE t (a INT)".to_string());
+            assert!(matches!(result, Err(QueryError::DatabaseBusy)));
+            assert!(start.elapsed() >= Duration::from_millis(100));
+        });
+    }
+
+    #[test]
+    fn busy_timeout_succeeds_once_the_writer_is_done() {
+        in_temp_dir(|| {
+            let mut writer = Lock::default();
+            writer.acquire(LockLevel::Exclusive, NO_WAIT).unwrap();
+            let done = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                writer.release().unwrap();
+            });
+            let mut database = Database::new();
+            database.set_busy_timeout(Duration::from_secs(5));
+            let result = database.query("CREATE TABLE t (a INT)".to_string());
+            assert!(result.is_ok());
+            done.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn loading_waits_for_a_writer() {
+        in_temp_dir(|| {
+            let mut writer = Lock::default();
+            writer.acquire(LockLevel::Pending, NO_WAIT).unwrap();
+            let done = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                drop(writer);
+            });
+            assert!(Database::load().is_ok());
+            done.join().unwrap();
+        });
+    }
+}
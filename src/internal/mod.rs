@@ -1,4 +1,24 @@
+pub mod aggregate;
+pub mod copy;
+pub mod cost;
 pub mod database;
 pub mod errors;
+pub mod explain;
+pub mod expression;
+pub mod function;
+pub mod join;
+pub mod journal;
+pub mod lock;
+pub mod operator;
+pub mod optimizer;
 pub mod page;
+pub mod plan;
+pub mod schema;
+pub mod set_operation;
+pub mod sort;
+pub mod statement;
+pub mod statistics;
 pub mod table;
+#[cfg(test)]
+mod testing;
+pub mod window;
@@ -0,0 +1,465 @@
+use crate::internal::{
+    aggregate::HashAggregate,
+    cost::{estimate_rows, join_algorithm},
+    errors,
+    expression::{evaluate, truthy, Context, Field, Scope},
+    join::{join, JoinAlgorithm, JoinKind, Relation},
+    plan::{Plan, Schema},
+    set_operation::combine,
+    sort::{SortKey, SortedRows, Sorter},
+    statistics::TableStatistics,
+    table::{Column, ColumnType, ColumnValue, ResultSet},
+    window::{evaluate_windows, Window},
+};
+use sqlparser::ast::{Expr, JoinConstraint, OrderByExpr, SetOperator, SetQuantifier};
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<Vec<ColumnValue>, errors::QueryError>> + 'a>;
+
+/// How one step of a plan runs.
+#[derive(Debug)]
+pub enum Node {
+    /// Reads a table lazily, one page at a time, decoding only `columns`
+    /// and keeping the rows `filter` holds for.
+    SeqScan {
+        table: String,
+        columns: Vec<usize>,
+        filter: Option<Expr>,
+    },
+    Values {
+        result: Rc<ResultSet>,
+    },
+    /// Passes the rows of a subquery in FROM through.
+    SubqueryScan,
+    Filter {
+        predicate: Expr,
+    },
+    Join {
+        kind: JoinKind,
+        constraint: JoinConstraint,
+        algorithm: JoinAlgorithm,
+    },
+    HashAggregate {
+        group_by: Vec<Expr>,
+        aggregates: Vec<Expr>,
+    },
+    Window {
+        windows: Vec<Window>,
+    },
+    /// Sorts the rows, keeping only the first `limit` when known.
+    Sort {
+        order_by: Vec<OrderByExpr>,
+        limit: Option<usize>,
+    },
+    Limit {
+        offset: usize,
+        limit: Option<usize>,
+    },
+    Project {
+        exprs: Vec<Expr>,
+    },
+    Distinct,
+    SetOperation {
+        op: SetOperator,
+        quantifier: SetQuantifier,
+    },
+}
+
+/// A physical operator: a step of the plan, the rows it produces and the
+/// operators it reads from.
+#[derive(Debug)]
+pub struct Operator {
+    pub node: Node,
+    pub schema: Schema,
+    pub inputs: Vec<Operator>,
+    /// How many rows the cost model expects the operator to produce.
+    pub estimated_rows: f64,
+    /// What the operator did while running, once profiling is enabled.
+    pub profile: Option<Profile>,
+}
+
+/// What an operator did while running, as `EXPLAIN ANALYZE` reports it.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub rows: Cell<usize>,
+    /// The time spent producing the rows, including the time the operators
+    /// it reads from took to produce theirs.
+    pub time: Cell<Duration>,
+}
+impl Operator {
+    /// Chooses how to run each step of `plan`, using `statistics` to estimate
+    /// the cost of the choices.
+    pub fn new(plan: Plan, statistics: &[TableStatistics]) -> Result<Operator, errors::QueryError> {
+        let schema = plan.schema()?;
+        let estimated_rows = estimate_rows(&plan, statistics);
+        let (node, inputs) = match plan {
+            Plan::Scan {
+                table,
+                columns,
+                filter,
+                ..
+            } => (
+                Node::SeqScan {
+                    table,
+                    columns,
+                    filter,
+                },
+                vec![],
+            ),
+            Plan::Values { result, .. } => (Node::Values { result }, vec![]),
+            Plan::Derived { input, .. } => (Node::SubqueryScan, vec![*input]),
+            Plan::Filter { input, predicate } => (Node::Filter { predicate }, vec![*input]),
+            Plan::Join {
+                left,
+                right,
+                kind,
+                constraint,
+            } => {
+                let algorithm = join_algorithm(&left, &right, &constraint, statistics)?;
+                let node = Node::Join {
+                    kind,
+                    constraint,
+                    algorithm,
+                };
+                (node, vec![*left, *right])
+            }
+            Plan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => (
+                Node::HashAggregate {
+                    group_by,
+                    aggregates,
+                },
+                vec![*input],
+            ),
+            Plan::Window { input, windows, .. } => (Node::Window { windows }, vec![*input]),
+            // a sort followed by a limit only has to keep the top rows
+            Plan::Limit {
+                input,
+                offset,
+                limit: Some(limit),
+            } if matches!(*input, Plan::Sort { .. }) => {
+                let kept = limit.saturating_add(offset);
+                let sorted_rows = estimate_rows(&input, statistics).min(kept as f64);
+                let Plan::Sort { input, order_by } = *input else {
+                    unreachable!()
+                };
+                let sort = Operator {
+                    schema: schema.clone(),
+                    node: Node::Sort {
+                        order_by,
+                        limit: Some(kept),
+                    },
+                    inputs: vec![Operator::new(*input, statistics)?],
+                    estimated_rows: sorted_rows,
+                    profile: None,
+                };
+                return Ok(Operator {
+                    node: Node::Limit {
+                        offset,
+                        limit: Some(limit),
+                    },
+                    schema,
+                    inputs: vec![sort],
+                    estimated_rows,
+                    profile: None,
+                });
+            }
+            Plan::Sort { input, order_by } => (
+                Node::Sort {
+                    order_by,
+                    limit: None,
+                },
+                vec![*input],
+            ),
+            Plan::Limit {
+                input,
+                offset,
+                limit,
+            } => (Node::Limit { offset, limit }, vec![*input]),
+            Plan::Project { input, exprs, .. } => (Node::Project { exprs }, vec![*input]),
+            Plan::Distinct { input } => (Node::Distinct, vec![*input]),
+            Plan::SetOperation {
+                op,
+                quantifier,
+                left,
+                right,
+            } => (Node::SetOperation { op, quantifier }, vec![*left, *right]),
+        };
+        Ok(Operator {
+            node,
+            schema,
+            inputs: inputs
+                .into_iter()
+                .map(|input| Operator::new(input, statistics))
+                .collect::<Result<_, _>>()?,
+            estimated_rows,
+            profile: None,
+        })
+    }
+    /// Makes this operator and the ones below it record what they do while running.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::default());
+        for input in &mut self.inputs {
+            input.enable_profiling();
+        }
+    }
+    /// Runs the operator to completion. Columns whose type is only known from
+    /// their values, like scalar subqueries, get the type of the first one
+    /// that isn't NULL.
+    pub fn result(&self, context: &Context) -> Result<ResultSet, errors::QueryError> {
+        let rows = self.execute(context)?.collect::<Result<Vec<_>, _>>()?;
+        let mut columns: Vec<Column> = self
+            .schema
+            .fields
+            .iter()
+            .map(|field| field.column.clone())
+            .collect();
+        for (index, column) in columns.iter_mut().enumerate() {
+            match rows
+                .iter()
+                .map(|row| &row[index])
+                .find(|v| **v != ColumnValue::Null)
+            {
+                Some(ColumnValue::Int(_)) => column._type = ColumnType::Int,
+                Some(ColumnValue::Text(_)) => column._type = ColumnType::Text,
+                _ => {}
+            }
+        }
+        Ok(ResultSet { columns, rows })
+    }
+    /// Produces the operator's rows. Operators that need all of their input,
+    /// such as sorts and joins, read it before returning.
+    pub fn execute<'a>(&'a self, context: &'a Context<'a>) -> Result<Rows<'a>, errors::QueryError> {
+        let Some(profile) = &self.profile else {
+            return self.rows(context);
+        };
+        let timed = |time: Duration| profile.time.set(profile.time.get() + time);
+        let start = Instant::now();
+        let mut rows = self.rows(context)?;
+        timed(start.elapsed());
+        Ok(Box::new(std::iter::from_fn(move || {
+            let start = Instant::now();
+            let row = rows.next();
+            timed(start.elapsed());
+            if let Some(Ok(_)) = row {
+                profile.rows.set(profile.rows.get() + 1);
+            }
+            row
+        })))
+    }
+    fn rows<'a>(&'a self, context: &'a Context<'a>) -> Result<Rows<'a>, errors::QueryError> {
+        let input = || self.inputs[0].execute(context);
+        let input_schema = || {
+            let schema = &self.inputs[0].schema;
+            (&schema.fields[..], &schema.computed[..])
+        };
+        let materialize =
+            |operator: &'a Operator| operator.execute(context)?.collect::<Result<Vec<_>, _>>();
+        Ok(match &self.node {
+            Node::SeqScan {
+                table,
+                columns,
+                filter,
+            } => {
+                let table = context
+                    .database
+                    .table(table)
+                    .ok_or(errors::QueryError::UnknownTable)?;
+                let rows = table.scan_columns(columns).map(Ok);
+                match filter {
+                    Some(filter) => {
+                        let scope = (&self.schema.fields[..], &[][..]);
+                        Box::new(filter_rows(rows, filter, scope, context))
+                    }
+                    None => Box::new(rows),
+                }
+            }
+            Node::Values { result } => Box::new(result.rows.iter().cloned().map(Ok)),
+            Node::SubqueryScan => input()?,
+            Node::Filter { predicate } => {
+                Box::new(filter_rows(input()?, predicate, input_schema(), context))
+            }
+            Node::Join {
+                kind,
+                constraint,
+                algorithm,
+            } => {
+                let relation = |operator: &'a Operator| {
+                    Ok::<_, errors::QueryError>(Relation {
+                        fields: operator.schema.fields.clone(),
+                        rows: materialize(operator)?,
+                    })
+                };
+                let (left, right) = (relation(&self.inputs[0])?, relation(&self.inputs[1])?);
+                let joined = join(left, right, *kind, constraint, *algorithm, context)?;
+                Box::new(joined.rows.into_iter().map(Ok))
+            }
+            Node::HashAggregate {
+                group_by,
+                aggregates,
+            } => {
+                let fields = &self.inputs[0].schema.fields;
+                let functions = &context.database.functions;
+                let mut aggregate = HashAggregate::new(group_by.clone(), aggregates, functions)?;
+                for row in input()? {
+                    aggregate.push(fields, row?, context)?;
+                }
+                Box::new(aggregate.finish(fields)?.into_iter().map(Ok))
+            }
+            Node::Window { windows } => {
+                let rows = materialize(&self.inputs[0])?;
+                let rows = evaluate_windows(windows, input_schema(), rows, context)?;
+                Box::new(rows.into_iter().map(Ok))
+            }
+            Node::Sort { order_by, limit } => {
+                // the sorted rows are read one at a time, from memory or from
+                // the runs spilled to disk
+                let rows = order_rows(order_by, input_schema(), input()?, *limit, context)?;
+                Box::new(rows.map(|row| row.map_err(errors::QueryError::IOError)))
+            }
+            Node::Limit { offset, limit } => {
+                // stops reading the input as soon as enough rows were read
+                let mut rows = input()?;
+                // an error in a skipped row still fails the query
+                for row in rows.by_ref().take(*offset) {
+                    row?;
+                }
+                Box::new(rows.take(limit.unwrap_or(usize::MAX)))
+            }
+            Node::Project { exprs } => {
+                let (fields, computed) = input_schema();
+                Box::new(input()?.map(move |row| {
+                    let row = row?;
+                    let scope = Scope::with_computed(fields, &row, computed).within(Some(context));
+                    exprs
+                        .iter()
+                        .map(|expr| evaluate(expr, &scope))
+                        .collect::<Result<Vec<_>, _>>()
+                }))
+            }
+            Node::Distinct => {
+                let mut seen = HashSet::new();
+                Box::new(
+                    input()?
+                        .filter(move |row| !matches!(row, Ok(row) if !seen.insert(row.clone()))),
+                )
+            }
+            Node::SetOperation { op, quantifier } => {
+                let left = self.inputs[0].result(context)?;
+                let right = self.inputs[1].result(context)?;
+                let result = combine(op, quantifier, left, right)?;
+                Box::new(result.rows.into_iter().map(Ok))
+            }
+        })
+    }
+}
+
+/// Keeps the rows for which `predicate` is true, as in WHERE and HAVING. The
+/// rows are described by the fields and computed expressions of `scope`.
+fn filter_rows<'a>(
+    rows: impl Iterator<Item = Result<Vec<ColumnValue>, errors::QueryError>> + 'a,
+    predicate: &'a Expr,
+    scope: (&'a [Field], &'a [Expr]),
+    context: &'a Context<'a>,
+) -> impl Iterator<Item = Result<Vec<ColumnValue>, errors::QueryError>> + 'a {
+    let (fields, computed) = scope;
+    rows.filter_map(move |row| {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        let scope = Scope::with_computed(fields, &row, computed).within(Some(context));
+        match evaluate(predicate, &scope) {
+            Ok(value) if truthy(&value) => Some(Ok(row)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Sorts `rows` by the ORDER BY keys, keeping at most `limit` of them. The rows
+/// are described by the fields and computed expressions of `scope`. All of the
+/// input is read before the first sorted row is returned.
+fn order_rows(
+    order_by: &[OrderByExpr],
+    scope: (&[Field], &[Expr]),
+    rows: impl Iterator<Item = Result<Vec<ColumnValue>, errors::QueryError>>,
+    limit: Option<usize>,
+    context: &Context,
+) -> Result<SortedRows, errors::QueryError> {
+    let (fields, computed) = scope;
+    let keys = order_by
+        .iter()
+        .map(|o| SortKey::new(o.asc, o.nulls_first))
+        .collect();
+    let mut sorter = match limit {
+        Some(limit) => Sorter::with_limit(keys, limit),
+        None => Sorter::new(keys),
+    };
+    for row in rows {
+        let row = row?;
+        let scope = Scope::with_computed(fields, &row, computed).within(Some(context));
+        let values = order_by
+            .iter()
+            .map(|order| evaluate(&order.expr, &scope))
+            .collect::<Result<Vec<_>, _>>()?;
+        sorter
+            .push(values, row)
+            .map_err(errors::QueryError::IOError)?;
+    }
+    sorter.finish().map_err(errors::QueryError::IOError)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        testing::{error, ints, with_tables, Fixture},
+    };
+
+    const NUMBERS: Fixture = ("n", "i INT", &["3", "1", "2", "5", "4"]);
+
+    /// Overflows for 5, the fourth row read, and for no other.
+    static OVERFLOWS_ON_5: &str = "9223372036854775807 + i / 5 > 0";
+
+    #[test]
+    fn limit_stops_reading_its_input() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = format!("SELECT i FROM n WHERE {OVERFLOWS_ON_5} LIMIT 3");
+            assert_eq!(ints(database, &sql), [3, 1, 2]);
+        });
+    }
+
+    #[test]
+    fn errors_in_skipped_rows_fail_the_query() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = format!("SELECT i FROM n WHERE {OVERFLOWS_ON_5} LIMIT 1 OFFSET 4");
+            let e = error(database, &sql);
+            assert!(matches!(e, QueryError::IntegerOverflow));
+        });
+    }
+
+    #[test]
+    fn sort_with_limit_keeps_only_the_first_rows() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT i FROM n ORDER BY i DESC LIMIT 2 OFFSET 1";
+            assert_eq!(ints(database, sql), [4, 3]);
+        });
+    }
+
+    #[test]
+    fn operators_run_in_a_pipeline() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT DISTINCT i % 2 FROM n WHERE i > 1 ORDER BY 1";
+            assert_eq!(ints(database, sql), [0, 1]);
+        });
+    }
+}
@@ -0,0 +1,579 @@
+use crate::internal::{
+    aggregate::Aggregate,
+    cost::estimate_rows,
+    errors,
+    expression::{evaluate, is_bound_by, sub_expressions, sub_expressions_mut, Field, Scope},
+    function::{scalar_function, Functions},
+    join::{join_fields, split_conjuncts, JoinKind},
+    plan::Plan,
+    statistics::TableStatistics,
+    table::{ColumnValue, ResultSet},
+};
+use sqlparser::ast::{BinaryOperator, Expr, Ident, JoinConstraint, Value};
+use std::rc::Rc;
+
+/// Rewrites `plan` into an equivalent one that is cheaper to run: constant
+/// expressions are computed once, WHERE conditions move down to the scans and
+/// joins they concern, inner joins run in the order `statistics` suggest and
+/// scans only decode the columns the query reads.
+pub fn optimize(
+    plan: Plan,
+    functions: &Functions,
+    statistics: &[TableStatistics],
+) -> Result<Plan, errors::QueryError> {
+    let plan = fold_plan(plan, functions)?;
+    let plan = push_down_filters(plan)?;
+    let plan = reorder_joins(plan, statistics)?;
+    Ok(prune_columns(plan))
+}
+
+fn fold_plan(plan: Plan, functions: &Functions) -> Result<Plan, errors::QueryError> {
+    let mut plan = plan.map_inputs(|input| fold_plan(input, functions))?;
+    match &mut plan {
+        Plan::Scan {
+            filter: Some(expr), ..
+        }
+        | Plan::Join {
+            constraint: JoinConstraint::On(expr),
+            ..
+        } => fold(expr, functions),
+        Plan::Filter { input, predicate } => {
+            fold(predicate, functions);
+            // a condition that always holds filters nothing
+            if matches!(predicate, Expr::Value(Value::Number(n, _)) if n != "0") {
+                return Ok(*std::mem::replace(input, empty()));
+            }
+        }
+        Plan::Aggregate { group_by, .. } => {
+            group_by.iter_mut().for_each(|expr| fold(expr, functions));
+        }
+        Plan::Sort { order_by, .. } => {
+            for order in order_by {
+                fold(&mut order.expr, functions);
+            }
+        }
+        Plan::Project { exprs, .. } => exprs.iter_mut().for_each(|expr| fold(expr, functions)),
+        _ => {}
+    }
+    Ok(plan)
+}
+
+/// Replaces the parts of `expr` that don't depend on the row with their value.
+/// Aggregates and window functions are left alone, since they are matched
+/// against the values computed for them, and so are user-defined functions and
+/// built-in ones like `now()`, which may not return the same value every time.
+fn fold(expr: &mut Expr, functions: &Functions) {
+    let is_aggregate = matches!(Aggregate::from_expr(expr, Some(functions)), Ok(Some(_)));
+    if let Expr::Function(function) = expr {
+        if function.over.is_some() || is_aggregate {
+            return;
+        }
+    }
+    if matches!(expr, Expr::Value(_)) {
+        return;
+    }
+    if !deterministic(expr, functions) {
+        sub_expressions_mut(expr)
+            .into_iter()
+            .for_each(|expr| fold(expr, functions));
+        return;
+    }
+    // without a row or a database, anything reading either fails to evaluate
+    match evaluate(expr, &Scope::new(&[], &[])) {
+        Ok(value) => *expr = literal(value),
+        Err(_) => sub_expressions_mut(expr)
+            .into_iter()
+            .for_each(|expr| fold(expr, functions)),
+    }
+}
+
+/// Whether `expr` only calls functions that give the same result every time.
+fn deterministic(expr: &Expr, functions: &Functions) -> bool {
+    if let Expr::Function(function) = expr {
+        let name = function.name.to_string();
+        if functions.scalar(&name).is_some()
+            || scalar_function(&name).is_some_and(|function| !function.deterministic)
+        {
+            return false;
+        }
+    }
+    sub_expressions(expr)
+        .into_iter()
+        .all(|expr| deterministic(expr, functions))
+}
+
+fn literal(value: ColumnValue) -> Expr {
+    Expr::Value(match value {
+        ColumnValue::Int(value) => Value::Number(value.to_string(), false),
+        ColumnValue::Text(value) => Value::SingleQuotedString(value),
+        ColumnValue::Null => Value::Null,
+    })
+}
+
+/// A plan without rows, standing in for one that is being moved.
+fn empty() -> Box<Plan> {
+    Box::new(Plan::Values {
+        fields: Vec::new(),
+        result: Rc::new(ResultSet {
+            columns: Vec::new(),
+            rows: Vec::new(),
+        }),
+    })
+}
+
+fn push_down_filters(plan: Plan) -> Result<Plan, errors::QueryError> {
+    match plan {
+        Plan::Filter { input, predicate } => {
+            let mut conjuncts = Vec::new();
+            split_conjuncts(&predicate, &mut conjuncts);
+            let conjuncts = conjuncts.into_iter().cloned().collect();
+            push_filter(push_down_filters(*input)?, conjuncts)
+        }
+        plan => plan.map_inputs(push_down_filters),
+    }
+}
+
+/// Filters the rows of `plan` by `conjuncts`, moving each one as close to the
+/// rows it reads as possible.
+fn push_filter(plan: Plan, mut conjuncts: Vec<Expr>) -> Result<Plan, errors::QueryError> {
+    if conjuncts.is_empty() {
+        return Ok(plan);
+    }
+    match plan {
+        Plan::Scan {
+            table,
+            fields,
+            columns,
+            filter,
+        } => Ok(Plan::Scan {
+            table,
+            fields,
+            columns,
+            filter: conjunction(filter.into_iter().chain(conjuncts)),
+        }),
+        Plan::Filter { input, predicate } => {
+            let mut lower = Vec::new();
+            split_conjuncts(&predicate, &mut lower);
+            let mut lower: Vec<Expr> = lower.into_iter().cloned().collect();
+            lower.append(&mut conjuncts);
+            push_filter(*input, lower)
+        }
+        Plan::Join {
+            left,
+            right,
+            kind,
+            mut constraint,
+        } => {
+            let (left_fields, right_fields) = (left.schema()?.fields, right.schema()?.fields);
+            let fields = join_fields(&left_fields, &right_fields, &constraint)?;
+            let (mut to_left, mut to_right, mut to_join, mut rest) =
+                (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+            for conjunct in conjuncts {
+                // conditions that don't resolve here must keep failing the same way
+                if !is_bound_by(&conjunct, &fields) {
+                    rest.push(conjunct);
+                } else if matches!(kind, JoinKind::Inner | JoinKind::Left)
+                    && is_bound_by(&conjunct, &left_fields)
+                {
+                    to_left.push(conjunct);
+                } else if matches!(kind, JoinKind::Inner | JoinKind::Right)
+                    && is_bound_by(&conjunct, &right_fields)
+                {
+                    to_right.push(conjunct);
+                } else if kind == JoinKind::Inner
+                    && matches!(constraint, JoinConstraint::On(_) | JoinConstraint::None)
+                {
+                    // conditions between the two sides become part of the join,
+                    // where equalities can be used as hash keys
+                    to_join.push(conjunct);
+                } else {
+                    rest.push(conjunct);
+                }
+            }
+            if !to_join.is_empty() {
+                let on = match constraint {
+                    JoinConstraint::On(on) => Some(on),
+                    _ => None,
+                };
+                constraint =
+                    JoinConstraint::On(conjunction(on.into_iter().chain(to_join)).unwrap());
+            }
+            let plan = Plan::Join {
+                left: Box::new(push_filter(*left, to_left)?),
+                right: Box::new(push_filter(*right, to_right)?),
+                kind,
+                constraint,
+            };
+            Ok(filter(plan, rest))
+        }
+        plan => Ok(filter(plan, conjuncts)),
+    }
+}
+
+fn filter(plan: Plan, conjuncts: Vec<Expr>) -> Plan {
+    match conjunction(conjuncts) {
+        Some(predicate) => Plan::Filter {
+            input: Box::new(plan),
+            predicate,
+        },
+        None => plan,
+    }
+}
+
+/// ANDs `conjuncts` together, in order.
+fn conjunction(conjuncts: impl IntoIterator<Item = Expr>) -> Option<Expr> {
+    conjuncts.into_iter().reduce(|left, right| Expr::BinaryOp {
+        left: Box::new(left),
+        op: BinaryOperator::And,
+        right: Box::new(right),
+    })
+}
+
+/// Rewrites trees of inner joins so that the joins estimated to produce the
+/// fewest rows run first. Each join gets the conditions between the relations
+/// it joins, and its smaller side goes to the right, which is the side a hash
+/// join builds its table from.
+fn reorder_joins(plan: Plan, statistics: &[TableStatistics]) -> Result<Plan, errors::QueryError> {
+    if !is_inner_join(&plan) {
+        return plan.map_inputs(|input| reorder_joins(input, statistics));
+    }
+    let (mut relations, mut conditions) = (Vec::new(), Vec::new());
+    flatten_joins(&plan, &mut relations, &mut conditions);
+    // conditions that don't resolve against the joined rows, such as ones
+    // with subqueries, can only be checked where they were written
+    let fields = plan.schema()?.fields;
+    if !conditions
+        .iter()
+        .all(|condition| is_bound_by(condition, &fields))
+    {
+        return plan.map_inputs(|input| reorder_joins(input, statistics));
+    }
+    let mut conditions: Vec<Expr> = conditions.into_iter().cloned().collect();
+    let mut relations = relations
+        .into_iter()
+        .map(|relation| {
+            let relation = reorder_joins(relation.clone(), statistics)?;
+            let rows = estimate_rows(&relation, statistics);
+            Ok((relation, rows))
+        })
+        .collect::<Result<Vec<_>, errors::QueryError>>()?;
+
+    // start from the smallest relation, then keep joining whichever relation
+    // makes for the fewest rows
+    let mut first = 0;
+    for (index, (_, rows)) in relations.iter().enumerate() {
+        if *rows < relations[first].1 {
+            first = index;
+        }
+    }
+    let mut joined = relations.remove(first);
+    while !relations.is_empty() {
+        let mut best: Option<(usize, (Plan, f64), Vec<Expr>)> = None;
+        for (index, relation) in relations.iter().enumerate() {
+            let mut remaining = conditions.clone();
+            let join = inner_join(joined.clone(), relation.clone(), &mut remaining)?;
+            let rows = estimate_rows(&join, statistics);
+            if best
+                .as_ref()
+                .is_none_or(|(_, (_, best_rows), _)| rows < *best_rows)
+            {
+                best = Some((index, (join, rows), remaining));
+            }
+        }
+        let (index, join, remaining) = best.unwrap();
+        relations.remove(index);
+        (joined, conditions) = (join, remaining);
+    }
+    Ok(joined.0)
+}
+
+fn is_inner_join(plan: &Plan) -> bool {
+    matches!(
+        plan,
+        Plan::Join {
+            kind: JoinKind::Inner,
+            constraint: JoinConstraint::On(_) | JoinConstraint::None,
+            ..
+        }
+    )
+}
+
+/// Collects the relations a tree of inner joins joins and the conditions it
+/// joins them on.
+fn flatten_joins<'a>(
+    plan: &'a Plan,
+    relations: &mut Vec<&'a Plan>,
+    conditions: &mut Vec<&'a Expr>,
+) {
+    match plan {
+        Plan::Join {
+            left,
+            right,
+            constraint,
+            ..
+        } if is_inner_join(plan) => {
+            flatten_joins(left, relations, conditions);
+            flatten_joins(right, relations, conditions);
+            if let JoinConstraint::On(on) = constraint {
+                split_conjuncts(on, conditions);
+            }
+        }
+        _ => relations.push(plan),
+    }
+}
+
+/// Joins two relations, given along with their estimated rows, on the
+/// `conditions` that only read their columns, which are taken out of
+/// `conditions`.
+fn inner_join(
+    (left, left_rows): (Plan, f64),
+    (right, right_rows): (Plan, f64),
+    conditions: &mut Vec<Expr>,
+) -> Result<Plan, errors::QueryError> {
+    let (left, right) = if right_rows > left_rows {
+        (right, left)
+    } else {
+        (left, right)
+    };
+    let constraint = JoinConstraint::None;
+    let fields = join_fields(&left.schema()?.fields, &right.schema()?.fields, &constraint)?;
+    let (on, rest): (Vec<Expr>, Vec<Expr>) = conditions
+        .drain(..)
+        .partition(|condition| is_bound_by(condition, &fields));
+    *conditions = rest;
+    Ok(Plan::Join {
+        left: Box::new(left),
+        right: Box::new(right),
+        kind: JoinKind::Inner,
+        constraint: match conjunction(on) {
+            Some(on) => JoinConstraint::On(on),
+            None => constraint,
+        },
+    })
+}
+
+/// Makes scans only decode the columns some expression of the plan refers to.
+/// Subqueries may refer to any column of the query, so plans with them are
+/// left alone, as are NATURAL joins, which join on whatever columns both
+/// sides have.
+fn prune_columns(plan: Plan) -> Plan {
+    let mut references = Vec::new();
+    if !collect_references(&plan, &mut references) {
+        return plan;
+    }
+    prune(plan, &references)
+}
+
+fn prune(plan: Plan, references: &[Vec<Ident>]) -> Plan {
+    match plan {
+        Plan::Scan {
+            table,
+            fields,
+            columns,
+            filter,
+        } => {
+            let (fields, columns) = fields
+                .into_iter()
+                .zip(columns)
+                .filter(|(field, _)| references.iter().any(|name| may_refer_to(name, field)))
+                .unzip();
+            Plan::Scan {
+                table,
+                fields,
+                columns,
+                filter,
+            }
+        }
+        plan => plan
+            .map_inputs(|input| Ok(prune(input, references)))
+            .unwrap(),
+    }
+}
+
+/// Whether the column reference `name` could resolve to `field`.
+fn may_refer_to(name: &[Ident], field: &Field) -> bool {
+    match name {
+        [.., relation, column] => {
+            field.relation.as_ref() == Some(&relation.value) && field.column.name == column.value
+        }
+        [column] => field.column.name == column.value,
+        [] => false,
+    }
+}
+
+/// Collects the column references of every expression in `plan`; false when
+/// the columns it reads can't be told from them.
+fn collect_references(plan: &Plan, references: &mut Vec<Vec<Ident>>) -> bool {
+    let mut exprs: Vec<&Expr> = Vec::new();
+    match plan {
+        Plan::Scan { filter, .. } => exprs.extend(filter),
+        Plan::Filter { predicate, .. } => exprs.push(predicate),
+        Plan::Join { constraint, .. } => match constraint {
+            JoinConstraint::On(on) => exprs.push(on),
+            JoinConstraint::Using(columns) => {
+                references.extend(columns.iter().map(|column| vec![column.clone()]))
+            }
+            JoinConstraint::Natural => return false,
+            JoinConstraint::None => {}
+        },
+        Plan::Aggregate {
+            group_by,
+            aggregates,
+            ..
+        } => exprs.extend(group_by.iter().chain(aggregates)),
+        Plan::Window {
+            exprs: calls,
+            windows,
+            ..
+        } => {
+            exprs.extend(calls);
+            // named windows are only spelled out here
+            for window in windows {
+                exprs.extend(&window.arguments);
+                exprs.extend(&window.spec.partition_by);
+                exprs.extend(window.spec.order_by.iter().map(|o| &o.expr));
+            }
+        }
+        Plan::Sort { order_by, .. } => exprs.extend(order_by.iter().map(|o| &o.expr)),
+        Plan::Project {
+            exprs: projection, ..
+        } => exprs.extend(projection),
+        _ => {}
+    }
+    exprs
+        .into_iter()
+        .all(|expr| expr_references(expr, references))
+        && plan
+            .inputs()
+            .into_iter()
+            .all(|input| collect_references(input, references))
+}
+
+fn expr_references(expr: &Expr, references: &mut Vec<Vec<Ident>>) -> bool {
+    match expr {
+        Expr::Identifier(ident) => references.push(vec![ident.clone()]),
+        Expr::CompoundIdentifier(idents) => references.push(idents.clone()),
+        Expr::Subquery(_) | Expr::Exists { .. } | Expr::InSubquery { .. } => return false,
+        _ => {}
+    }
+    sub_expressions(expr)
+        .into_iter()
+        .all(|expr| expr_references(expr, references))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::{
+        database::Database,
+        expression::Context,
+        plan::plan,
+        testing::{text, values, with_tables, Fixture},
+    };
+    use sqlparser::{ast::Statement, dialect::GenericDialect, parser::Parser};
+
+    const TABLES: [Fixture; 2] = [
+        ("a", "x INT, y TEXT", &["1, 'one'", "2, 'two'"]),
+        ("b", "x INT, z TEXT", &["2, 'q'"]),
+    ];
+
+    /// The plan of the query `sql` once optimized.
+    fn optimized(database: &Database, sql: &str) -> Plan {
+        let statement = Parser::parse_sql(&GenericDialect {}, sql)
+            .unwrap()
+            .remove(0);
+        let Statement::Query(query) = statement else {
+            panic!("{sql:?} isn't a query");
+        };
+        let context = Context::new(database, None);
+        optimize(plan(&query, &context).unwrap(), &database.functions, &[]).unwrap()
+    }
+
+    /// The expressions the topmost projection of `plan` computes.
+    fn projection(plan: &Plan) -> Vec<String> {
+        match plan {
+            Plan::Project { exprs, .. } => exprs.iter().map(|expr| expr.to_string()).collect(),
+            other => projection(other.inputs()[0]),
+        }
+    }
+
+    /// Each scan of `plan`, as the table, the columns it decodes and its filter.
+    fn scans(plan: &Plan) -> Vec<String> {
+        let mut scans = Vec::new();
+        if let Plan::Scan {
+            table,
+            fields,
+            filter,
+            ..
+        } = plan
+        {
+            let columns: Vec<&str> = fields.iter().map(|f| f.column.name.as_str()).collect();
+            let mut scan = format!("{table} ({})", columns.join(", "));
+            if let Some(filter) = filter {
+                scan += &format!(" WHERE {filter}");
+            }
+            scans.push(scan);
+        }
+        for input in plan.inputs() {
+            scans.extend(self::scans(input));
+        }
+        scans
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        with_tables(&TABLES, |database| {
+            let sql = "SELECT x + (2 * 3), upper('a') FROM a";
+            let plan = optimized(database, sql);
+            assert_eq!(projection(&plan), ["x + 6", "'A'"]);
+        });
+    }
+
+    #[test]
+    fn non_deterministic_functions_are_not_folded() {
+        with_tables(&TABLES, |database| {
+            database.create_function("answer", 0, |_| Ok(ColumnValue::Int(42)));
+            let sql = "SELECT now(), date('now'), length(strftime('%s', 'now')), answer()";
+            let plan = optimized(database, sql);
+            let expected = [
+                "now()",
+                "date('now')",
+                "length(strftime('%s', 'now'))",
+                "answer()",
+            ];
+            assert_eq!(projection(&plan), expected);
+        });
+    }
+
+    #[test]
+    fn filters_move_down_to_the_scans() {
+        with_tables(&TABLES, |database| {
+            let sql = "SELECT a.y FROM a JOIN b ON a.x = b.x WHERE b.z = 'q' AND a.x > 1";
+            let plan = optimized(database, sql);
+            let scans = scans(&plan);
+            assert!(scans.contains(&"a (x, y) WHERE a.x > 1".to_string()));
+            assert!(scans.contains(&"b (x, z) WHERE b.z = 'q'".to_string()));
+            assert!(!matches!(plan.inputs()[0], Plan::Filter { .. }));
+            assert_eq!(values(database, sql), [text("two")]);
+        });
+    }
+
+    #[test]
+    fn conditions_that_always_hold_are_dropped() {
+        with_tables(&TABLES, |database| {
+            let plan = optimized(database, "SELECT y FROM a WHERE 1 = 1");
+            assert!(matches!(plan.inputs()[0], Plan::Scan { filter: None, .. }));
+            assert_eq!(scans(&plan), ["a (y)"]);
+        });
+    }
+
+    #[test]
+    fn scans_only_read_the_columns_used() {
+        with_tables(&TABLES, |database| {
+            let plan = optimized(database, "SELECT y FROM a ORDER BY x");
+            assert_eq!(scans(&plan), ["a (x, y)"]);
+            let plan = optimized(database, "SELECT count(*) FROM a");
+            assert_eq!(scans(&plan), ["a ()"]);
+        });
+    }
+}
@@ -25,20 +25,20 @@ impl Page {
             .create(true)
             .open(format!("{}/page_{}", &table.name, page_num))?;
         let content = format!("{}\r\n", bytes);
-        file.write(content.as_bytes())?;
+        file.write_all(content.as_bytes())?;
 
         Ok(())
     }
-    pub fn write_bytes(table: &Table, page_num: i64, bytes: &Vec<u8>) -> Result<(), io::Error> {
+    pub fn write_bytes(table: &Table, page_num: i64, bytes: &[u8]) -> Result<(), io::Error> {
         let mut file = fs::OpenOptions::new()
             .append(true)
             .create(true)
             .open(format!("{}/page_{}", &table.name, page_num))?;
-        file.write(bytes)?;
+        file.write_all(bytes)?;
 
         Ok(())
     }
     pub fn read(table: &Table, page_num: i64) -> Result<Vec<u8>, io::Error> {
-        Ok(fs::read(format!("{}/page_{}", &table.name, page_num))?)
+        fs::read(format!("{}/page_{}", &table.name, page_num))
     }
 }
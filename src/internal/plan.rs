@@ -0,0 +1,824 @@
+use crate::internal::{
+    aggregate::collect_aggregates,
+    errors,
+    expression::{evaluate, expression_type, resolve, Context, Field, Scope},
+    join::{join_fields, JoinKind},
+    schema::system_table,
+    table::{Column, ColumnValue, ResultSet},
+    window::{collect_windows, Window},
+};
+use sqlparser::ast::{
+    Distinct, Expr, Ident, JoinConstraint, JoinOperator, OrderByExpr, Query, Select, SelectItem,
+    SetExpr, SetOperator, SetQuantifier, TableAlias, TableFactor, TableWithJoins, Value,
+};
+use std::rc::Rc;
+
+/// The fields of the rows a plan produces, followed in each row by the values
+/// of expressions computed ahead of time, such as GROUP BY keys, aggregates
+/// and window functions.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    pub fields: Vec<Field>,
+    pub computed: Vec<Expr>,
+}
+
+/// What a query computes, as a tree of relational steps. The operators in
+/// `operator` decide how each step runs.
+#[derive(Debug, Clone)]
+pub enum Plan {
+    /// Reads the rows of a stored table for which `filter` holds, decoding
+    /// only the table's `columns`, which `fields` describe.
+    Scan {
+        table: String,
+        fields: Vec<Field>,
+        columns: Vec<usize>,
+        filter: Option<Expr>,
+    },
+    /// Rows that are already known, such as a common table, or the single
+    /// row without columns a SELECT without FROM reads.
+    Values {
+        fields: Vec<Field>,
+        result: Rc<ResultSet>,
+    },
+    /// A subquery in FROM, whose columns go by the subquery's alias.
+    Derived {
+        input: Box<Plan>,
+        fields: Vec<Field>,
+    },
+    Filter {
+        input: Box<Plan>,
+        predicate: Expr,
+    },
+    Join {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        kind: JoinKind,
+        constraint: JoinConstraint,
+    },
+    /// Groups the rows; each group's first row is followed by its keys and aggregates.
+    Aggregate {
+        input: Box<Plan>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<Expr>,
+    },
+    /// Appends the value of each window function to every row.
+    Window {
+        input: Box<Plan>,
+        exprs: Vec<Expr>,
+        windows: Vec<Window>,
+    },
+    Sort {
+        input: Box<Plan>,
+        order_by: Vec<OrderByExpr>,
+    },
+    Limit {
+        input: Box<Plan>,
+        offset: usize,
+        limit: Option<usize>,
+    },
+    Project {
+        input: Box<Plan>,
+        exprs: Vec<Expr>,
+        columns: Vec<Column>,
+    },
+    Distinct {
+        input: Box<Plan>,
+    },
+    SetOperation {
+        op: SetOperator,
+        quantifier: SetQuantifier,
+        left: Box<Plan>,
+        right: Box<Plan>,
+    },
+}
+impl Plan {
+    pub fn schema(&self) -> Result<Schema, errors::QueryError> {
+        Ok(match self {
+            Plan::Scan { fields, .. }
+            | Plan::Values { fields, .. }
+            | Plan::Derived { fields, .. } => Schema {
+                fields: fields.clone(),
+                computed: Vec::new(),
+            },
+            Plan::Join {
+                left,
+                right,
+                constraint,
+                ..
+            } => Schema {
+                fields: join_fields(&left.schema()?.fields, &right.schema()?.fields, constraint)?,
+                computed: Vec::new(),
+            },
+            Plan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => Schema {
+                fields: input.schema()?.fields,
+                computed: group_by.iter().chain(aggregates).cloned().collect(),
+            },
+            Plan::Window { input, exprs, .. } => {
+                let mut schema = input.schema()?;
+                schema.computed.extend(exprs.iter().cloned());
+                schema
+            }
+            Plan::Filter { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::Distinct { input } => input.schema()?,
+            Plan::Project { columns, .. } => Schema {
+                fields: columns
+                    .iter()
+                    .map(|column| Field::new(None, column.clone()))
+                    .collect(),
+                computed: Vec::new(),
+            },
+            Plan::SetOperation { left, .. } => left.schema()?,
+        })
+    }
+    /// Rewrites the plans this one reads from with `f`.
+    pub fn map_inputs(
+        self,
+        mut f: impl FnMut(Plan) -> Result<Plan, errors::QueryError>,
+    ) -> Result<Plan, errors::QueryError> {
+        let mut f = |input: Box<Plan>| f(*input).map(Box::new);
+        Ok(match self {
+            Plan::Scan { .. } | Plan::Values { .. } => self,
+            Plan::Derived { input, fields } => Plan::Derived {
+                input: f(input)?,
+                fields,
+            },
+            Plan::Filter { input, predicate } => Plan::Filter {
+                input: f(input)?,
+                predicate,
+            },
+            Plan::Join {
+                left,
+                right,
+                kind,
+                constraint,
+            } => Plan::Join {
+                left: f(left)?,
+                right: f(right)?,
+                kind,
+                constraint,
+            },
+            Plan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => Plan::Aggregate {
+                input: f(input)?,
+                group_by,
+                aggregates,
+            },
+            Plan::Window {
+                input,
+                exprs,
+                windows,
+            } => Plan::Window {
+                input: f(input)?,
+                exprs,
+                windows,
+            },
+            Plan::Sort { input, order_by } => Plan::Sort {
+                input: f(input)?,
+                order_by,
+            },
+            Plan::Limit {
+                input,
+                offset,
+                limit,
+            } => Plan::Limit {
+                input: f(input)?,
+                offset,
+                limit,
+            },
+            Plan::Project {
+                input,
+                exprs,
+                columns,
+            } => Plan::Project {
+                input: f(input)?,
+                exprs,
+                columns,
+            },
+            Plan::Distinct { input } => Plan::Distinct { input: f(input)? },
+            Plan::SetOperation {
+                op,
+                quantifier,
+                left,
+                right,
+            } => Plan::SetOperation {
+                op,
+                quantifier,
+                left: f(left)?,
+                right: f(right)?,
+            },
+        })
+    }
+    /// The plans this one reads from.
+    pub fn inputs(&self) -> Vec<&Plan> {
+        match self {
+            Plan::Scan { .. } | Plan::Values { .. } => vec![],
+            Plan::Join { left, right, .. } | Plan::SetOperation { left, right, .. } => {
+                vec![left, right]
+            }
+            Plan::Derived { input, .. }
+            | Plan::Filter { input, .. }
+            | Plan::Aggregate { input, .. }
+            | Plan::Window { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::Distinct { input } => vec![input],
+        }
+    }
+    /// The columns of the query's result.
+    pub fn columns(&self) -> Result<Vec<Column>, errors::QueryError> {
+        Ok(self
+            .schema()?
+            .fields
+            .into_iter()
+            .map(|field| field.column)
+            .collect())
+    }
+}
+
+/// Plans a query. Its common tables are evaluated and bound to `context`
+/// first, since the plan reads their rows.
+pub fn plan(query: &Query, context: &Context) -> Result<Plan, errors::QueryError> {
+    context.database.bind_ctes(query, context)?;
+    match &*query.body {
+        SetExpr::Select(select) => plan_select(query, select, context),
+        body => {
+            let plan = set_expr(body, context)?;
+            order_and_limit(plan, query, &query.order_by, context)
+        }
+    }
+}
+
+fn set_expr(body: &SetExpr, context: &Context) -> Result<Plan, errors::QueryError> {
+    match body {
+        SetExpr::Select(_) => plan(&subquery(body), context),
+        SetExpr::Query(query) => plan(query, context),
+        SetExpr::SetOperation {
+            op,
+            set_quantifier,
+            left,
+            right,
+        } => Ok(Plan::SetOperation {
+            op: *op,
+            quantifier: *set_quantifier,
+            left: Box::new(set_expr(left, context)?),
+            right: Box::new(set_expr(right, context)?),
+        }),
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+fn plan_select(
+    query: &Query,
+    select: &Select,
+    context: &Context,
+) -> Result<Plan, errors::QueryError> {
+    let functions = &context.database.functions;
+    let mut plan = from(&select.from, context)?;
+    let fields = plan.schema()?.fields;
+    let mut aggregates = Vec::new();
+    for projection in &select.projection {
+        if let SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } = projection {
+            collect_aggregates(expr, functions, &mut aggregates)?;
+        }
+    }
+    if let Some(having) = &select.having {
+        collect_aggregates(having, functions, &mut aggregates)?;
+    }
+    for order in &query.order_by {
+        collect_aggregates(&order.expr, functions, &mut aggregates)?;
+    }
+    let is_aggregate =
+        !aggregates.is_empty() || !select.group_by.is_empty() || select.having.is_some();
+
+    let distinct = match &select.distinct {
+        None => false,
+        Some(Distinct::Distinct) => true,
+        Some(Distinct::On(_)) => return Err(errors::QueryError::UnkownOperation),
+    };
+    let mut projection = Vec::new();
+    let mut aliases = Vec::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::UnnamedExpr(expr) => {
+                projection.push(expr.clone());
+                aliases.push(None);
+            }
+            SelectItem::ExprWithAlias { expr, alias } => {
+                projection.push(expr.clone());
+                aliases.push(Some(alias.value.clone()));
+            }
+            SelectItem::Wildcard(_) => {
+                let len = projection.len();
+                projection.extend(
+                    fields
+                        .iter()
+                        .filter(|field| !field.hidden)
+                        .map(field_reference),
+                );
+                aliases.resize(aliases.len() + projection.len() - len, None);
+            }
+            SelectItem::QualifiedWildcard(name, _) => {
+                let relation = name.0.last().map(|ident| ident.value.clone());
+                let len = projection.len();
+                projection.extend(
+                    fields
+                        .iter()
+                        .filter(|field| field.relation == relation)
+                        .map(field_reference),
+                );
+                if projection.len() == len {
+                    return Err(errors::QueryError::UnknownTable);
+                }
+                aliases.resize(aliases.len() + projection.len() - len, None);
+            }
+        }
+    }
+    let columns = projection
+        .iter()
+        .zip(&aliases)
+        .map(|(expr, alias)| {
+            Ok(Column {
+                name: match (alias, expr) {
+                    (Some(alias), _) => alias.clone(),
+                    (None, Expr::CompoundIdentifier(idents)) => {
+                        idents.last().unwrap().value.clone()
+                    }
+                    (None, expr) => expr.to_string(),
+                },
+                _type: expression_type(expr, &fields, Some(context))?,
+            })
+        })
+        .collect::<Result<Vec<_>, errors::QueryError>>()?;
+    let order_by = output_order_by(&query.order_by, &projection, &aliases, &fields);
+
+    if let Some(selection) = &select.selection {
+        plan = Plan::Filter {
+            input: Box::new(plan),
+            predicate: selection.clone(),
+        };
+    }
+    if is_aggregate {
+        plan = Plan::Aggregate {
+            input: Box::new(plan),
+            group_by: select.group_by.clone(),
+            aggregates,
+        };
+        if let Some(having) = &select.having {
+            plan = Plan::Filter {
+                input: Box::new(plan),
+                predicate: having.clone(),
+            };
+        }
+    }
+    // window functions see every row that made it through WHERE and HAVING
+    let mut exprs = Vec::new();
+    for expr in projection
+        .iter()
+        .chain(query.order_by.iter().map(|o| &o.expr))
+    {
+        collect_windows(expr, &mut exprs);
+    }
+    if !exprs.is_empty() {
+        let windows = exprs
+            .iter()
+            .map(|expr| {
+                Window::from_expr(expr, &select.named_window, Some(functions)).map(Option::unwrap)
+            })
+            .collect::<Result<_, _>>()?;
+        plan = Plan::Window {
+            input: Box::new(plan),
+            exprs,
+            windows,
+        };
+    }
+
+    if distinct {
+        // duplicates are only known once the projection ran, so sort and
+        // limit the projected rows
+        plan = Plan::Distinct {
+            input: Box::new(Plan::Project {
+                input: Box::new(plan),
+                exprs: projection,
+                columns,
+            }),
+        };
+        return order_and_limit(plan, query, &order_by, context);
+    }
+    // sort before projecting, so keys can use columns that aren't selected
+    let order_by = order_by
+        .into_iter()
+        .map(|mut order| {
+            if let Some(position) = position(&order.expr)? {
+                let expr = projection.get(position);
+                order.expr = expr.ok_or(errors::QueryError::UnknownColumn)?.clone();
+            }
+            Ok(order)
+        })
+        .collect::<Result<Vec<_>, errors::QueryError>>()?;
+    let plan = sort_and_limit(plan, query, order_by, context)?;
+    Ok(Plan::Project {
+        input: Box::new(plan),
+        exprs: projection,
+        columns,
+    })
+}
+
+/// Plans a FROM clause. Several comma separated tables are cross joined, and
+/// without FROM there is a single row without columns.
+fn from(from: &[TableWithJoins], context: &Context) -> Result<Plan, errors::QueryError> {
+    let mut plan: Option<Plan> = None;
+    for table_with_joins in from {
+        let mut relation = relation(&table_with_joins.relation, context)?;
+        for joined in &table_with_joins.joins {
+            let right = self::relation(&joined.relation, context)?;
+            let (kind, constraint) = match &joined.join_operator {
+                JoinOperator::Inner(constraint) => (JoinKind::Inner, constraint),
+                JoinOperator::LeftOuter(constraint) => (JoinKind::Left, constraint),
+                JoinOperator::RightOuter(constraint) => (JoinKind::Right, constraint),
+                JoinOperator::FullOuter(constraint) => (JoinKind::Full, constraint),
+                JoinOperator::CrossJoin => (JoinKind::Inner, &JoinConstraint::None),
+                _ => return Err(errors::QueryError::UnkownOperation),
+            };
+            relation = join(relation, right, kind, constraint.clone())?;
+        }
+        plan = Some(match plan {
+            None => relation,
+            Some(left) => join(left, relation, JoinKind::Inner, JoinConstraint::None)?,
+        });
+    }
+    Ok(plan.unwrap_or_else(|| Plan::Values {
+        fields: Vec::new(),
+        result: Rc::new(ResultSet {
+            columns: Vec::new(),
+            rows: vec![Vec::new()],
+        }),
+    }))
+}
+
+fn join(
+    left: Plan,
+    right: Plan,
+    kind: JoinKind,
+    constraint: JoinConstraint,
+) -> Result<Plan, errors::QueryError> {
+    // checks the USING columns exist on both sides
+    join_fields(&left.schema()?.fields, &right.schema()?.fields, &constraint)?;
+    Ok(Plan::Join {
+        left: Box::new(left),
+        right: Box::new(right),
+        kind,
+        constraint,
+    })
+}
+
+fn relation(factor: &TableFactor, context: &Context) -> Result<Plan, errors::QueryError> {
+    match factor {
+        TableFactor::Derived { lateral: true, .. } => Err(errors::QueryError::UnkownOperation),
+        TableFactor::Derived {
+            subquery, alias, ..
+        } => {
+            let input = plan(subquery, context)?;
+            let mut columns = input.columns()?;
+            let relation = alias.as_ref().map(|alias| alias.name.value.clone());
+            if let Some(alias) = alias {
+                rename_columns(&mut columns, alias)?;
+            }
+            Ok(Plan::Derived {
+                input: Box::new(input),
+                fields: columns
+                    .into_iter()
+                    .map(|column| Field::new(relation.clone(), column))
+                    .collect(),
+            })
+        }
+        TableFactor::Table { name, alias, .. } => {
+            let table_name = name.to_string();
+            let relation = match alias {
+                Some(alias) => alias.name.value.clone(),
+                None => table_name.clone(),
+            };
+            if let Some(result) = context.cte(&table_name) {
+                let mut columns = result.columns.clone();
+                if let Some(alias) = alias {
+                    rename_columns(&mut columns, alias)?;
+                }
+                return Ok(Plan::Values {
+                    fields: Field::from_columns(&relation, &columns),
+                    result,
+                });
+            }
+            if let Some(result) = system_table(context.database, name) {
+                let mut columns = result.columns.clone();
+                let relation = match alias {
+                    Some(alias) => {
+                        rename_columns(&mut columns, alias)?;
+                        alias.name.value.clone()
+                    }
+                    // `information_schema.tables` is read as `tables`
+                    None => name.0.last().unwrap().value.clone(),
+                };
+                return Ok(Plan::Values {
+                    fields: Field::from_columns(&relation, &columns),
+                    result: Rc::new(result),
+                });
+            }
+            let table = context
+                .database
+                .table(&table_name)
+                .ok_or(errors::QueryError::UnknownTable)?;
+            Ok(Plan::Scan {
+                table: table_name,
+                fields: Field::from_columns(&relation, &table.columns),
+                columns: (0..table.columns.len()).collect(),
+                filter: None,
+            })
+        }
+        _ => Err(errors::QueryError::UnkownOperation),
+    }
+}
+
+/// Sorts and limits rows that were already projected; `order_by` refers to
+/// the columns of `plan`'s result.
+fn order_and_limit(
+    plan: Plan,
+    query: &Query,
+    order_by: &[OrderByExpr],
+    context: &Context,
+) -> Result<Plan, errors::QueryError> {
+    let fields = plan.schema()?.fields;
+    let order_by = order_by
+        .iter()
+        .map(|order| {
+            let mut order = order.clone();
+            if let Some(position) = position(&order.expr)? {
+                let field = fields
+                    .get(position)
+                    .ok_or(errors::QueryError::UnknownColumn)?;
+                order.expr = field_reference(field);
+            }
+            Ok(order)
+        })
+        .collect::<Result<Vec<_>, errors::QueryError>>()?;
+    sort_and_limit(plan, query, order_by, context)
+}
+
+fn sort_and_limit(
+    mut plan: Plan,
+    query: &Query,
+    order_by: Vec<OrderByExpr>,
+    context: &Context,
+) -> Result<Plan, errors::QueryError> {
+    if !order_by.is_empty() {
+        plan = Plan::Sort {
+            input: Box::new(plan),
+            order_by,
+        };
+    }
+    let (offset, limit) = limit_and_offset(query, context)?;
+    if offset > 0 || limit.is_some() {
+        plan = Plan::Limit {
+            input: Box::new(plan),
+            offset,
+            limit,
+        };
+    }
+    Ok(plan)
+}
+
+/// The column an ORDER BY key such as `ORDER BY 2` refers to, counting from zero.
+fn position(expr: &Expr) -> Result<Option<usize>, errors::QueryError> {
+    match expr {
+        Expr::Value(Value::Number(position, _)) => position
+            .parse::<usize>()
+            .ok()
+            .and_then(|p| p.checked_sub(1))
+            .map(Some)
+            .ok_or(errors::QueryError::UnknownColumn),
+        _ => Ok(None),
+    }
+}
+
+/// Rewrites ORDER BY keys that name a column alias, or repeat an expression
+/// of the projection, into the position of that result column.
+fn output_order_by(
+    order_by: &[OrderByExpr],
+    projection: &[Expr],
+    aliases: &[Option<String>],
+    fields: &[Field],
+) -> Vec<OrderByExpr> {
+    // columns match however they are qualified
+    let field = |expr: &Expr| match expr {
+        Expr::Identifier(ident) => resolve(fields, std::slice::from_ref(ident)).ok(),
+        Expr::CompoundIdentifier(idents) => resolve(fields, idents).ok(),
+        _ => None,
+    };
+    order_by
+        .iter()
+        .map(|order| {
+            let position = match &order.expr {
+                Expr::Identifier(ident) => aliases
+                    .iter()
+                    .position(|alias| alias.as_ref() == Some(&ident.value)),
+                _ => None,
+            }
+            .or_else(|| {
+                projection.iter().position(|expr| {
+                    *expr == order.expr
+                        || field(expr).is_some_and(|f| field(&order.expr) == Some(f))
+                })
+            });
+            let mut order = order.clone();
+            if let Some(position) = position {
+                order.expr = Expr::Value(Value::Number((position + 1).to_string(), false));
+            }
+            order
+        })
+        .collect()
+}
+
+/// Renames `columns` to the ones listed by `alias`, if any.
+pub fn rename_columns(
+    columns: &mut [Column],
+    alias: &TableAlias,
+) -> Result<(), errors::QueryError> {
+    if alias.columns.is_empty() {
+        return Ok(());
+    }
+    if alias.columns.len() != columns.len() {
+        return Err(errors::QueryError::WrongNumberOfColumns);
+    }
+    for (column, name) in columns.iter_mut().zip(&alias.columns) {
+        column.name = name.value.clone();
+    }
+    Ok(())
+}
+
+/// A query that only consists of `body`.
+pub fn subquery(body: &SetExpr) -> Query {
+    match body {
+        SetExpr::Query(query) => *query.clone(),
+        body => Query {
+            with: None,
+            body: Box::new(body.clone()),
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            fetch: None,
+            locks: Vec::new(),
+        },
+    }
+}
+
+/// A qualified reference to `field`, or a plain one when it has no relation.
+fn field_reference(field: &Field) -> Expr {
+    let column = Ident::new(&field.column.name);
+    match &field.relation {
+        Some(relation) => Expr::CompoundIdentifier(vec![Ident::new(relation), column]),
+        None => Expr::Identifier(column),
+    }
+}
+
+/// Reads `OFFSET` and `LIMIT`/`FETCH FIRST` of a query. A negative limit means
+/// no limit and a negative offset means no offset, like SQLite.
+pub fn limit_and_offset(
+    query: &Query,
+    context: &Context,
+) -> Result<(usize, Option<usize>), errors::QueryError> {
+    let count = |expr: &Expr| match evaluate(expr, &Scope::new(&[], &[]).within(Some(context)))? {
+        ColumnValue::Int(value) => Ok(usize::try_from(value).ok()),
+        _ => Err(errors::QueryError::TypeMismatch),
+    };
+    let offset = match &query.offset {
+        Some(offset) => count(&offset.value)?.unwrap_or(0),
+        None => 0,
+    };
+    let limit = match (&query.limit, &query.fetch) {
+        (Some(_), Some(_)) => return Err(errors::QueryError::SyntaxError),
+        (Some(limit), None) => count(limit)?,
+        (None, Some(fetch)) => {
+            if fetch.percent || fetch.with_ties {
+                return Err(errors::QueryError::UnkownOperation);
+            }
+            match &fetch.quantity {
+                Some(quantity) => count(quantity)?,
+                None => Some(1),
+            }
+        }
+        (None, None) => None,
+    };
+    Ok((offset, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        database::Database,
+        errors::QueryError,
+        table::QueryResult,
+        testing::{error, execute, int, ints, rows, text, values, with_tables, Fixture},
+    };
+
+    const NUMBERS: Fixture = ("n", "i INT", &["1", "2", "3", "4", "5"]);
+
+    #[test]
+    fn limit_and_offset() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT i FROM n ORDER BY i LIMIT 2";
+            assert_eq!(ints(database, sql), [1, 2]);
+            let sql = "SELECT i FROM n ORDER BY i LIMIT 2 OFFSET 2";
+            assert_eq!(ints(database, sql), [3, 4]);
+            let sql = "SELECT i FROM n ORDER BY i DESC OFFSET 4";
+            assert_eq!(ints(database, sql), [1]);
+            let sql = "SELECT i FROM n ORDER BY i LIMIT 0";
+            assert!(ints(database, sql).is_empty());
+            let sql = "SELECT i FROM n ORDER BY i LIMIT 10 OFFSET 10";
+            assert!(ints(database, sql).is_empty());
+        });
+    }
+
+    #[test]
+    fn negative_limit_and_offset_mean_none() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT i FROM n ORDER BY i LIMIT -1 OFFSET 3";
+            assert_eq!(ints(database, sql), [4, 5]);
+            let sql = "SELECT i FROM n ORDER BY i LIMIT 1 OFFSET -2";
+            assert_eq!(ints(database, sql), [1]);
+        });
+    }
+
+    #[test]
+    fn limit_expressions_and_fetch_first() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT i FROM n ORDER BY i LIMIT 1 + 1 OFFSET 1";
+            assert_eq!(ints(database, sql), [2, 3]);
+            let sql = "SELECT i FROM n ORDER BY i OFFSET 1 ROWS FETCH FIRST 2 ROWS ONLY";
+            assert_eq!(ints(database, sql), [2, 3]);
+            let e = error(database, "SELECT i FROM n LIMIT 'a'");
+            assert!(matches!(e, QueryError::TypeMismatch));
+        });
+    }
+
+    /// The names of the columns `sql` returns.
+    fn column_names(database: &mut Database, sql: &str) -> Vec<String> {
+        match execute(database, sql) {
+            QueryResult::Rows(result) => result
+                .columns
+                .into_iter()
+                .map(|column| column.name)
+                .collect(),
+            other => panic!("{sql:?} returned {other:?}"),
+        }
+    }
+
+    #[test]
+    fn select_distinct() {
+        let t = (
+            "t",
+            "a INT, b TEXT",
+            &["1, 'x'", "1, 'x'", "1, 'y'", "NULL, 'x'", "NULL, 'x'"][..],
+        );
+        with_tables(&[t], |database| {
+            let sql = "SELECT DISTINCT a FROM t ORDER BY a";
+            assert_eq!(rows(database, sql).len(), 2);
+            let sql = "SELECT DISTINCT a, b FROM t ORDER BY a, b";
+            assert_eq!(rows(database, sql).len(), 3);
+            // the first row of each set of duplicates is kept, in order
+            let result = rows(database, "SELECT DISTINCT b FROM t");
+            assert_eq!(result, [[text("x")], [text("y")]]);
+            let sql = "SELECT DISTINCT a FROM t ORDER BY a LIMIT 1 OFFSET 1";
+            assert_eq!(ints(database, sql), [1]);
+        });
+    }
+
+    #[test]
+    fn column_aliases() {
+        with_tables(&[NUMBERS], |database| {
+            let sql = "SELECT i AS number, i * 2 double, i + 1 FROM n";
+            assert_eq!(column_names(database, sql), ["number", "double", "i + 1"]);
+            // aliases can be used in ORDER BY
+            let sql = "SELECT i * -1 AS negated FROM n ORDER BY negated LIMIT 2";
+            assert_eq!(ints(database, sql), [-5, -4]);
+            let sql = "SELECT t.i AS x FROM n AS t WHERE t.i = 3";
+            assert_eq!(ints(database, sql), [3]);
+        });
+    }
+
+    #[test]
+    fn limit_applies_after_aggregation_and_distinct() {
+        with_tables(&[NUMBERS], |database| {
+            assert_eq!(values(database, "SELECT count(*) FROM n LIMIT 1"), [int(5)]);
+            let sql = "SELECT DISTINCT i % 2 FROM n ORDER BY 1 LIMIT 1 OFFSET 1";
+            assert_eq!(ints(database, sql), [1]);
+        });
+    }
+}
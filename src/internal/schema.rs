@@ -0,0 +1,256 @@
+use crate::internal::{
+    database::Database,
+    errors,
+    expression::{evaluate, truthy, Field, Scope},
+    table::{Column, ColumnType, ColumnValue, ResultSet, Table},
+};
+use sqlparser::ast::{Expr, Ident, ObjectName, ShowStatementFilter, Value};
+
+/// The schema holding the system tables.
+static SCHEMA: &str = "information_schema";
+
+/// The rows of the read-only system table called `name`, if it is one:
+/// `information_schema.tables` has a row for every table of the database and
+/// `information_schema.columns` one for every column of every table.
+pub fn system_table(database: &Database, name: &ObjectName) -> Option<ResultSet> {
+    let [schema, table] = name.0.as_slice() else {
+        return None;
+    };
+    if !schema.value.eq_ignore_ascii_case(SCHEMA) {
+        return None;
+    }
+    match table.value.to_lowercase().as_str() {
+        "tables" => Some(tables(database.tables())),
+        "columns" => Some(columns(database.tables())),
+        _ => None,
+    }
+}
+
+fn tables(tables: &[Table]) -> ResultSet {
+    ResultSet {
+        columns: vec![
+            column("table_name", ColumnType::Text),
+            column("table_type", ColumnType::Text),
+        ],
+        rows: tables
+            .iter()
+            .map(|table| {
+                vec![
+                    ColumnValue::Text(table.name.clone()),
+                    ColumnValue::Text("BASE TABLE".to_string()),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn columns(tables: &[Table]) -> ResultSet {
+    let rows = tables.iter().flat_map(|table| {
+        table.columns().iter().enumerate().map(|(index, column)| {
+            vec![
+                ColumnValue::Text(table.name.clone()),
+                ColumnValue::Text(column.name.clone()),
+                ColumnValue::Int(index as i64 + 1),
+                ColumnValue::Text(column._type.name().to_string()),
+            ]
+        })
+    });
+    ResultSet {
+        columns: vec![
+            column("table_name", ColumnType::Text),
+            column("column_name", ColumnType::Text),
+            column("ordinal_position", ColumnType::Int),
+            column("data_type", ColumnType::Text),
+        ],
+        rows: rows.collect(),
+    }
+}
+
+/// The result of `SHOW TABLES`: the name of every table.
+pub fn show_tables(
+    database: &Database,
+    filter: &Option<ShowStatementFilter>,
+) -> Result<ResultSet, errors::QueryError> {
+    let result = ResultSet {
+        columns: vec![column("name", ColumnType::Text)],
+        rows: database
+            .tables()
+            .iter()
+            .map(|table| vec![ColumnValue::Text(table.name.clone())])
+            .collect(),
+    };
+    filter_rows(result, filter)
+}
+
+/// The result of `DESCRIBE table` or `SHOW COLUMNS FROM table`: the name and
+/// type of each of the table's columns, in order.
+pub fn describe(
+    table: &Table,
+    filter: &Option<ShowStatementFilter>,
+) -> Result<ResultSet, errors::QueryError> {
+    let result = ResultSet {
+        columns: vec![
+            column("name", ColumnType::Text),
+            column("type", ColumnType::Text),
+        ],
+        rows: table
+            .columns()
+            .iter()
+            .map(|column| {
+                vec![
+                    ColumnValue::Text(column.name.clone()),
+                    ColumnValue::Text(column._type.name().to_string()),
+                ]
+            })
+            .collect(),
+    };
+    filter_rows(result, filter)
+}
+
+/// Keeps the rows whose first column matches the pattern of a `LIKE` or
+/// `ILIKE` filter, or for which a `WHERE` filter is true.
+fn filter_rows(
+    mut result: ResultSet,
+    filter: &Option<ShowStatementFilter>,
+) -> Result<ResultSet, errors::QueryError> {
+    let first = || Box::new(Expr::Identifier(Ident::new(&result.columns[0].name)));
+    let pattern =
+        |pattern: &String| Box::new(Expr::Value(Value::SingleQuotedString(pattern.clone())));
+    let predicate = match filter {
+        None => return Ok(result),
+        Some(ShowStatementFilter::Like(like)) => Expr::Like {
+            negated: false,
+            expr: first(),
+            pattern: pattern(like),
+            escape_char: None,
+        },
+        Some(ShowStatementFilter::ILike(like)) => Expr::ILike {
+            negated: false,
+            expr: first(),
+            pattern: pattern(like),
+            escape_char: None,
+        },
+        Some(ShowStatementFilter::Where(predicate)) => predicate.clone(),
+    };
+    let fields: Vec<Field> = result
+        .columns
+        .iter()
+        .map(|column| Field::new(None, column.clone()))
+        .collect();
+    let mut rows = Vec::new();
+    for row in result.rows {
+        if truthy(&evaluate(&predicate, &Scope::new(&fields, &row))?) {
+            rows.push(row);
+        }
+    }
+    result.rows = rows;
+    Ok(result)
+}
+
+fn column(name: &str, _type: ColumnType) -> Column {
+    Column {
+        name: name.to_string(),
+        _type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        testing::{error, int, rows, text, with_tables, Fixture},
+    };
+
+    const SHOP: [Fixture; 2] = [
+        ("customers", "id INT, name TEXT", &[]),
+        ("orders", "id INT, customer INT, item TEXT", &[]),
+    ];
+
+    #[test]
+    fn information_schema_lists_tables_and_columns() {
+        with_tables(&SHOP, |database| {
+            assert_eq!(
+                rows(database, "SELECT * FROM information_schema.tables"),
+                [
+                    [text("customers"), text("BASE TABLE")],
+                    [text("orders"), text("BASE TABLE")]
+                ]
+            );
+            let sql = "SELECT column_name, ordinal_position, data_type \
+                       FROM INFORMATION_SCHEMA.COLUMNS WHERE table_name = 'orders'";
+            assert_eq!(
+                rows(database, sql),
+                [
+                    [text("id"), int(1), text("INT")],
+                    [text("customer"), int(2), text("INT")],
+                    [text("item"), int(3), text("TEXT")]
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn information_schema_follows_the_catalog() {
+        with_tables(&SHOP, |database| {
+            database
+                .execute_batch("DROP TABLE orders; ALTER TABLE customers ADD COLUMN email TEXT")
+                .unwrap();
+            let sql = "SELECT t.table_name, count(*) FROM information_schema.tables t \
+                       JOIN information_schema.columns c ON c.table_name = t.table_name \
+                       GROUP BY t.table_name";
+            assert_eq!(rows(database, sql), [[text("customers"), int(3)]]);
+        });
+    }
+
+    #[test]
+    fn information_schema_is_read_only() {
+        with_tables(&SHOP, |database| {
+            let sql = "INSERT INTO information_schema.tables (table_name) VALUES ('x')";
+            assert!(matches!(error(database, sql), QueryError::UnknownTable));
+            let e = error(database, "SELECT * FROM information_schema.views");
+            assert!(matches!(e, QueryError::UnknownTable));
+        });
+    }
+
+    #[test]
+    fn show_tables() {
+        with_tables(&SHOP, |database| {
+            let names = [[text("customers")], [text("orders")]];
+            assert_eq!(rows(database, "SHOW TABLES"), names);
+            assert_eq!(rows(database, "SHOW TABLES LIKE 'o%'"), names[1..]);
+            let sql = "SHOW TABLES WHERE name <> 'orders'";
+            assert_eq!(rows(database, sql), names[..1]);
+        });
+    }
+
+    #[test]
+    fn describe_lists_columns_in_order() {
+        with_tables(&SHOP, |database| {
+            let columns = [
+                [text("id"), text("INT")],
+                [text("customer"), text("INT")],
+                [text("item"), text("TEXT")],
+            ];
+            assert_eq!(rows(database, "DESCRIBE orders"), columns);
+            assert_eq!(rows(database, "SHOW COLUMNS FROM orders"), columns);
+            let sql = "SHOW COLUMNS FROM orders LIKE 'i%'";
+            assert_eq!(rows(database, sql), [&columns[0], &columns[2]]);
+            let e = error(database, "DESCRIBE nope");
+            assert!(matches!(e, QueryError::UnknownTable));
+        });
+    }
+
+    #[test]
+    fn catalog_from_rust() {
+        with_tables(&SHOP, |database| {
+            let tables = database.tables();
+            assert_eq!(tables.len(), 2);
+            let names: Vec<&str> = tables[1]
+                .columns()
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect();
+            assert_eq!(names, ["id", "customer", "item"]);
+        });
+    }
+}
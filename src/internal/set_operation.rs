@@ -0,0 +1,132 @@
+use crate::internal::{
+    errors,
+    table::{ColumnValue, ResultSet},
+};
+use sqlparser::ast::{SetOperator, SetQuantifier};
+use std::collections::{HashMap, HashSet};
+
+/// Combines the results of the two sides of a UNION, INTERSECT or EXCEPT.
+/// The forms without ALL return distinct rows, in the order they first appear.
+pub fn combine(
+    op: &SetOperator,
+    quantifier: &SetQuantifier,
+    left: ResultSet,
+    right: ResultSet,
+) -> Result<ResultSet, errors::QueryError> {
+    if left.columns.len() != right.columns.len() {
+        return Err(errors::QueryError::WrongNumberOfColumns);
+    }
+    for index in 0..left.columns.len() {
+        if !compatible(&left, &right, index) {
+            return Err(errors::QueryError::TypeMismatch);
+        }
+    }
+    let all = *quantifier == SetQuantifier::All;
+    let ResultSet { columns, rows } = left;
+    let rows = match op {
+        SetOperator::Union => {
+            let mut rows = rows;
+            rows.extend(right.rows);
+            if all {
+                rows
+            } else {
+                distinct(rows)
+            }
+        }
+        SetOperator::Intersect | SetOperator::Except => {
+            // with ALL, each row on the right cancels out one equal row on the left
+            let mut counts: HashMap<Vec<ColumnValue>, usize> = HashMap::new();
+            for row in right.rows {
+                *counts.entry(row).or_default() += 1;
+            }
+            let intersect = *op == SetOperator::Intersect;
+            let rows = if all { rows } else { distinct(rows) };
+            rows.into_iter()
+                .filter(|row| {
+                    let found = match counts.get_mut(row) {
+                        Some(count) if *count > 0 => {
+                            if all {
+                                *count -= 1;
+                            }
+                            true
+                        }
+                        _ => false,
+                    };
+                    found == intersect
+                })
+                .collect()
+        }
+    };
+    Ok(ResultSet { columns, rows })
+}
+
+/// Columns of both sides must have the same type, unless one of them only holds NULLs.
+fn compatible(left: &ResultSet, right: &ResultSet, index: usize) -> bool {
+    let only_nulls = |result: &ResultSet| {
+        result
+            .rows
+            .iter()
+            .all(|row| row[index] == ColumnValue::Null)
+    };
+    left.columns[index]._type == right.columns[index]._type || only_nulls(left) || only_nulls(right)
+}
+
+fn distinct(rows: Vec<Vec<ColumnValue>>) -> Vec<Vec<ColumnValue>> {
+    let mut seen = HashSet::new();
+    rows.into_iter()
+        .filter(|row| seen.insert(row.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        table::ColumnValue::Null,
+        testing::{error, ints, text, values, with_tables},
+    };
+
+    /// The values 1, 1, 2 and 3 as a subquery, to combine with others.
+    static LEFT: &str = "(SELECT 1 UNION ALL SELECT 1 UNION ALL SELECT 2 UNION ALL SELECT 3)";
+
+    fn combined(sql: &str) -> Vec<i64> {
+        with_tables(&[], |database| ints(database, &sql.replace("LEFT", LEFT)))
+    }
+
+    #[test]
+    fn union() {
+        assert_eq!(combined("SELECT * FROM LEFT UNION SELECT 4"), [1, 2, 3, 4]);
+        let all = combined("SELECT * FROM LEFT UNION ALL SELECT 1");
+        assert_eq!(all, [1, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn intersect() {
+        let sql = "SELECT * FROM LEFT INTERSECT SELECT 1 UNION ALL SELECT 3";
+        assert_eq!(combined(sql), [1, 3]);
+        // each row on the right keeps one equal row on the left
+        let sql = "SELECT * FROM LEFT INTERSECT ALL (SELECT 1 UNION ALL SELECT 2)";
+        assert_eq!(combined(sql), [1, 2]);
+    }
+
+    #[test]
+    fn except() {
+        assert_eq!(combined("SELECT * FROM LEFT EXCEPT SELECT 2"), [1, 3]);
+        // each row on the right removes one equal row on the left
+        let sql = "SELECT * FROM LEFT EXCEPT ALL SELECT 1";
+        assert_eq!(combined(sql), [1, 2, 3]);
+    }
+
+    #[test]
+    fn sides_must_match() {
+        with_tables(&[], |database| {
+            let e = error(database, "SELECT 1 UNION SELECT 1, 2");
+            assert!(matches!(e, QueryError::WrongNumberOfColumns));
+            let e = error(database, "SELECT 1 UNION SELECT 'a'");
+            assert!(matches!(e, QueryError::TypeMismatch));
+            // a column of NULLs goes with any type
+            let result = values(database, "SELECT 'a' UNION ALL SELECT NULL");
+            assert_eq!(result, [text("a"), Null]);
+        });
+    }
+}
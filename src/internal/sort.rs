@@ -0,0 +1,327 @@
+use crate::internal::table::ColumnValue;
+use std::{
+    cmp::Ordering,
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+};
+
+/// How many bytes of rows are sorted in memory before a run is spilled to disk.
+static SORT_BUFFER_SIZE: usize = 1024 * 1024;
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Direction and NULL placement of one ORDER BY key.
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+impl SortKey {
+    /// NULLs sort as the smallest value unless told otherwise, like SQLite.
+    pub fn new(asc: Option<bool>, nulls_first: Option<bool>) -> SortKey {
+        let descending = asc == Some(false);
+        SortKey {
+            descending,
+            nulls_first: nulls_first.unwrap_or(!descending),
+        }
+    }
+}
+
+/// Compares two lists of key values according to `keys`.
+pub fn compare(keys: &[SortKey], a: &[ColumnValue], b: &[ColumnValue]) -> Ordering {
+    for (key, (a, b)) in keys.iter().zip(a.iter().zip(b.iter())) {
+        let ordering = match (a, b) {
+            (ColumnValue::Null, ColumnValue::Null) => Ordering::Equal,
+            (ColumnValue::Null, _) if key.nulls_first => Ordering::Less,
+            (ColumnValue::Null, _) => Ordering::Greater,
+            (_, ColumnValue::Null) if key.nulls_first => Ordering::Greater,
+            (_, ColumnValue::Null) => Ordering::Less,
+            (a, b) if key.descending => b.cmp(a),
+            (a, b) => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// A row waiting to be sorted, paired with the values of its ORDER BY keys.
+pub type SortEntry = (Vec<ColumnValue>, Vec<ColumnValue>);
+
+/// External merge sort: rows are sorted in memory until `SORT_BUFFER_SIZE` is
+/// exceeded, then the sorted run is written to a temporary file. Runs are
+/// merged back together when the sorted rows are read.
+pub struct Sorter {
+    keys: Vec<SortKey>,
+    /// Only this many rows are ever read back, so the rest can be dropped early.
+    limit: Option<usize>,
+    buffer: Vec<SortEntry>,
+    buffer_size: usize,
+    runs: Vec<PathBuf>,
+}
+impl Sorter {
+    pub fn new(keys: Vec<SortKey>) -> Sorter {
+        Sorter {
+            keys,
+            limit: None,
+            buffer: Vec::new(),
+            buffer_size: 0,
+            runs: Vec::new(),
+        }
+    }
+    /// Keeps only the first `limit` rows, turning the sort into a top-N selection.
+    pub fn with_limit(keys: Vec<SortKey>, limit: usize) -> Sorter {
+        let mut sorter = Sorter::new(keys);
+        sorter.limit = Some(limit);
+        sorter
+    }
+    pub fn push(&mut self, keys: Vec<ColumnValue>, row: Vec<ColumnValue>) -> Result<(), io::Error> {
+        self.buffer_size += size_of_values(&keys) + size_of_values(&row);
+        self.buffer.push((keys, row));
+        if let Some(limit) = self.limit {
+            if self.buffer.len() >= limit.saturating_mul(2).max(1) {
+                self.sort_buffer();
+                self.buffer_size = size_of_entries(&self.buffer);
+            }
+        }
+        if self.buffer_size >= SORT_BUFFER_SIZE {
+            self.spill()?;
+        }
+        Ok(())
+    }
+    /// Finishes sorting and returns the rows in order.
+    pub fn finish(mut self) -> Result<SortedRows, io::Error> {
+        self.sort_buffer();
+        if self.runs.is_empty() {
+            let rows = std::mem::take(&mut self.buffer);
+            return Ok(SortedRows::Memory(rows.into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut runs = Vec::new();
+        for path in std::mem::take(&mut self.runs) {
+            let mut run = Run {
+                reader: BufReader::new(fs::File::open(&path)?),
+                path,
+                head: None,
+            };
+            run.advance()?;
+            runs.push(run);
+        }
+        Ok(SortedRows::Merge {
+            keys: std::mem::take(&mut self.keys),
+            runs,
+        })
+    }
+    fn sort_buffer(&mut self) {
+        let keys = &self.keys;
+        // a stable sort keeps rows with equal keys in scan order
+        self.buffer.sort_by(|a, b| compare(keys, &a.0, &b.0));
+        if let Some(limit) = self.limit {
+            self.buffer.truncate(limit);
+        }
+    }
+    fn spill(&mut self) -> Result<(), io::Error> {
+        self.sort_buffer();
+        let path = std::env::temp_dir().join(format!(
+            "litesql-sort-{}-{}",
+            process::id(),
+            SPILL_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let mut writer = BufWriter::new(fs::File::create(&path)?);
+        for entry in self.buffer.drain(..) {
+            serde_json::to_writer(&mut writer, &entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        self.buffer_size = 0;
+        self.runs.push(path);
+        Ok(())
+    }
+}
+impl Drop for Sorter {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// A sorted run spilled to disk, read back one row at a time.
+pub struct Run {
+    path: PathBuf,
+    reader: BufReader<fs::File>,
+    head: Option<SortEntry>,
+}
+impl Run {
+    fn advance(&mut self) -> Result<(), io::Error> {
+        let mut line = String::new();
+        self.head = if self.reader.read_line(&mut line)? == 0 {
+            None
+        } else {
+            Some(serde_json::from_str(&line)?)
+        };
+        Ok(())
+    }
+}
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub enum SortedRows {
+    Memory(std::vec::IntoIter<SortEntry>),
+    Merge { keys: Vec<SortKey>, runs: Vec<Run> },
+}
+impl Iterator for SortedRows {
+    type Item = Result<Vec<ColumnValue>, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedRows::Memory(rows) => rows.next().map(|(_, row)| Ok(row)),
+            SortedRows::Merge { keys, runs } => {
+                // ties go to the earlier run, which keeps the merge stable
+                let mut smallest: Option<usize> = None;
+                for (index, run) in runs.iter().enumerate() {
+                    let Some((run_keys, _)) = &run.head else {
+                        continue;
+                    };
+                    let is_smaller = match smallest {
+                        None => true,
+                        Some(current) => {
+                            let (current_keys, _) = runs[current].head.as_ref().unwrap();
+                            compare(keys, run_keys, current_keys) == Ordering::Less
+                        }
+                    };
+                    if is_smaller {
+                        smallest = Some(index);
+                    }
+                }
+                let run = &mut runs[smallest?];
+                let (_, row) = run.head.take().unwrap();
+                Some(run.advance().map(|_| row))
+            }
+        }
+    }
+}
+
+fn size_of_entries(entries: &[SortEntry]) -> usize {
+    entries
+        .iter()
+        .map(|(keys, row)| size_of_values(keys) + size_of_values(row))
+        .sum()
+}
+
+fn size_of_values(values: &[ColumnValue]) -> usize {
+    values
+        .iter()
+        .map(|value| match value {
+            ColumnValue::Int(_) => std::mem::size_of::<i64>(),
+            ColumnValue::Text(value) => value.len(),
+            ColumnValue::Null => 1,
+        } + std::mem::size_of::<ColumnValue>())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::testing::{int, text, values, with_tables};
+    use ColumnValue::Null;
+
+    /// Sorts `rows` by themselves and returns their first column.
+    fn sorted(mut sorter: Sorter, rows: Vec<Vec<ColumnValue>>) -> Vec<i64> {
+        for row in rows {
+            sorter.push(row.clone(), row).unwrap();
+        }
+        sorter
+            .finish()
+            .unwrap()
+            .map(|row| match row.unwrap()[0] {
+                ColumnValue::Int(value) => value,
+                _ => panic!("not an integer"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nulls_are_smallest_by_default() {
+        let keys = [SortKey::new(None, None)];
+        assert_eq!(compare(&keys, &[Null], &[int(1)]), Ordering::Less);
+        let keys = [SortKey::new(Some(false), None)];
+        assert_eq!(compare(&keys, &[Null], &[int(1)]), Ordering::Greater);
+        let keys = [SortKey::new(Some(true), Some(false))];
+        assert_eq!(compare(&keys, &[Null], &[int(1)]), Ordering::Greater);
+    }
+
+    #[test]
+    fn later_keys_break_ties() {
+        let keys = [SortKey::new(None, None), SortKey::new(Some(false), None)];
+        assert_eq!(
+            compare(&keys, &[int(1), int(2)], &[int(1), int(3)]),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare(&keys, &[int(1), int(2)], &[int(2), int(3)]),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn equal_keys_keep_their_order() {
+        let sorter = Sorter::new(vec![SortKey::new(None, None)]);
+        let rows = [(2, 0), (1, 1), (2, 2), (1, 3)].map(|(key, value)| vec![int(key), int(value)]);
+        let mut sorter = sorter;
+        for row in rows {
+            sorter.push(vec![row[0].clone()], row).unwrap();
+        }
+        let order: Vec<ColumnValue> = sorter
+            .finish()
+            .unwrap()
+            .map(|row| row.unwrap()[1].clone())
+            .collect();
+        assert_eq!(order, [int(1), int(3), int(0), int(2)]);
+    }
+
+    #[test]
+    fn spills_runs_to_disk_and_merges_them() {
+        let mut sorter = Sorter::new(vec![SortKey::new(Some(false), None)]);
+        let padding = "x".repeat(10_000);
+        let count = 300;
+        for value in 0..count {
+            // every value appears once, out of order
+            let value = (value * 7) % count;
+            let row = vec![int(value), text(&padding)];
+            sorter.push(vec![int(value)], row).unwrap();
+        }
+        assert!(sorter.runs.len() > 1);
+        let runs = sorter.runs.clone();
+        let values = sorted(sorter, Vec::new());
+        assert_eq!(values, (0..count).rev().collect::<Vec<_>>());
+        // the runs are removed once read
+        assert!(runs.iter().all(|run| !run.exists()));
+    }
+
+    #[test]
+    fn limit_keeps_the_first_rows() {
+        let sorter = Sorter::with_limit(vec![SortKey::new(None, None)], 3);
+        let rows = [5, 1, 4, 2, 3, 0].map(|value| vec![int(value)]).to_vec();
+        assert_eq!(sorted(sorter, rows), [0, 1, 2]);
+    }
+
+    #[test]
+    fn order_by_several_keys() {
+        let rows: &[&str] = &["1, 'x'", "2, 'y'", "NULL, 'z'", "1, 'w'"];
+        with_tables(&[("t", "a INT, b TEXT", rows)], |database| {
+            let result = values(database, "SELECT b FROM t ORDER BY a DESC, b");
+            assert_eq!(result, [text("y"), text("w"), text("x"), text("z")]);
+            let result = values(database, "SELECT b FROM t ORDER BY a NULLS LAST, 1 DESC");
+            assert_eq!(result, [text("x"), text("w"), text("y"), text("z")]);
+        });
+    }
+}
@@ -0,0 +1,262 @@
+use crate::internal::{
+    database::{returns_rows, Database},
+    errors,
+    operator::Operator,
+    table::{ColumnValue, QueryResult, ResultSet},
+};
+use sqlparser::ast;
+
+/// A statement parsed once by `Database::prepare`, to be run any number of
+/// times with different values bound to its parameters.
+///
+/// Parameters are numbered from one like in SQLite: `?` takes the number after
+/// the largest one before it, `?N` and `$N` take N, and named parameters like
+/// `:name` take the next number the first time they appear.
+pub struct Statement {
+    pub(crate) statement: ast::Statement,
+    /// The names of the parameters by number, for the ones that have a name.
+    names: Vec<Option<String>>,
+    /// The values bound to the parameters by number. Unbound ones are NULL.
+    pub(crate) parameters: Vec<ColumnValue>,
+    /// The operators a query was planned into by its last run, along with the
+    /// version of the catalog they were planned against.
+    pub(crate) operator: Option<(u64, Operator)>,
+}
+
+impl Statement {
+    pub(crate) fn new(statement: ast::Statement, names: Vec<Option<String>>) -> Statement {
+        Statement {
+            statement,
+            parameters: vec![ColumnValue::Null; names.len()],
+            names,
+            operator: None,
+        }
+    }
+    /// The largest parameter number of the statement.
+    pub fn parameter_count(&self) -> usize {
+        self.names.len()
+    }
+    /// The number of the parameter called `name`, prefix included, as in `:id`.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        let index = self.names.iter().position(|n| n.as_deref() == Some(name))?;
+        Some(index + 1)
+    }
+    /// The name of the parameter numbered `index`, unless it's a numbered one.
+    pub fn parameter_name(&self, index: usize) -> Option<&str> {
+        self.names.get(index.checked_sub(1)?)?.as_deref()
+    }
+    /// Binds `value` to the parameter numbered `index`, counting from one, for
+    /// every run until it's bound again.
+    pub fn bind(&mut self, index: usize, value: ColumnValue) -> Result<(), errors::QueryError> {
+        let parameter = index
+            .checked_sub(1)
+            .and_then(|index| self.parameters.get_mut(index))
+            .ok_or(errors::QueryError::UnknownParameter)?;
+        *parameter = value;
+        Ok(())
+    }
+    pub fn bind_int(&mut self, index: usize, value: i64) -> Result<(), errors::QueryError> {
+        self.bind(index, ColumnValue::Int(value))
+    }
+    pub fn bind_text(&mut self, index: usize, value: &str) -> Result<(), errors::QueryError> {
+        self.bind(index, ColumnValue::Text(value.to_string()))
+    }
+    pub fn bind_null(&mut self, index: usize) -> Result<(), errors::QueryError> {
+        self.bind(index, ColumnValue::Null)
+    }
+    /// Binds `value` to the parameter called `name`, prefix included.
+    pub fn bind_named(&mut self, name: &str, value: ColumnValue) -> Result<(), errors::QueryError> {
+        let index = self
+            .parameter_index(name)
+            .ok_or(errors::QueryError::UnknownParameter)?;
+        self.bind(index, value)
+    }
+    /// Makes every parameter NULL again.
+    pub fn clear_bindings(&mut self) {
+        self.parameters.fill(ColumnValue::Null);
+    }
+    /// Runs the statement with the values bound to its parameters.
+    pub fn execute(&mut self, database: &mut Database) -> Result<QueryResult, errors::QueryError> {
+        database.execute_prepared(self)
+    }
+    /// Runs a statement that returns rows, such as a SELECT, an EXPLAIN or a SHOW.
+    pub fn query(&mut self, database: &mut Database) -> Result<ResultSet, errors::QueryError> {
+        if !returns_rows(&self.statement) {
+            return Err(errors::QueryError::UnkownOperation);
+        }
+        match self.execute(database)? {
+            QueryResult::Rows(result) => Ok(result),
+            _ => Err(errors::QueryError::UnkownOperation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        table::{ColumnValue, QueryResult},
+        testing::{execute, int, rows, text, with_tables, Fixture},
+    };
+    use std::{thread, time::Duration};
+
+    const PEOPLE: Fixture = (
+        "people",
+        "id INT, name TEXT",
+        &["1, 'ann'", "2, 'bob'", "3, 'cid'"],
+    );
+
+    #[test]
+    fn parameters_are_numbered_like_in_sqlite() {
+        with_tables(&[PEOPLE], |database| {
+            let sql = "SELECT ?, ?3, ?, :id, @name, $id, :id, $2";
+            let statement = database.prepare(sql).unwrap();
+            assert_eq!(statement.parameter_count(), 7);
+            assert_eq!(statement.parameter_index(":id"), Some(5));
+            assert_eq!(statement.parameter_index("@name"), Some(6));
+            assert_eq!(statement.parameter_index("id"), None);
+            assert_eq!(statement.parameter_name(5), Some(":id"));
+            assert_eq!(statement.parameter_name(4), None);
+            assert_eq!(statement.parameter_name(0), None);
+            assert_eq!(statement.parameter_index("$id"), Some(7));
+        });
+    }
+
+    #[test]
+    fn bound_values_are_used_until_rebound() {
+        with_tables(&[PEOPLE], |database| {
+            let sql = "SELECT name FROM people WHERE id = ?1 OR name = :name";
+            let mut statement = database.prepare(sql).unwrap();
+            let mut names =
+                |statement: &mut super::Statement| statement.query(database).unwrap().rows;
+            assert!(names(&mut statement).is_empty());
+            statement.bind_int(1, 2).unwrap();
+            assert_eq!(names(&mut statement), [[text("bob")]]);
+            statement.bind_named(":name", text("cid")).unwrap();
+            assert_eq!(names(&mut statement), [[text("bob")], [text("cid")]]);
+            statement.bind_text(2, "ann").unwrap();
+            statement.bind_null(1).unwrap();
+            assert_eq!(names(&mut statement), [[text("ann")]]);
+            statement.clear_bindings();
+            assert!(names(&mut statement).is_empty());
+        });
+    }
+
+    #[test]
+    fn unknown_parameters() {
+        with_tables(&[PEOPLE], |database| {
+            let mut statement = database.prepare("SELECT ?, :a").unwrap();
+            for e in [
+                statement.bind_int(0, 1),
+                statement.bind_int(3, 1),
+                statement.bind_named(":b", int(1)),
+            ] {
+                assert!(matches!(e, Err(QueryError::UnknownParameter)));
+            }
+            for sql in ["SELECT ?0", "SELECT ?40000", "SELECT $99999999999999999999"] {
+                let e = database.prepare(sql).err();
+                assert!(matches!(e, Some(QueryError::UnknownParameter)), "{sql}");
+            }
+        });
+    }
+
+    #[test]
+    fn a_single_statement_is_prepared() {
+        with_tables(&[PEOPLE], |database| {
+            for sql in ["SELECT 1; SELECT 2", "", "-- nothing"] {
+                let e = database.prepare(sql).err();
+                assert!(matches!(e, Some(QueryError::SyntaxError)), "{sql:?}");
+            }
+            assert!(database.prepare("SELECT 1;").is_ok());
+        });
+    }
+
+    #[test]
+    fn statements_other_than_queries_run_but_return_no_rows() {
+        with_tables(&[PEOPLE], |database| {
+            let sql = "INSERT INTO people (id, name) VALUES (?, ?)";
+            let mut insert = database.prepare(sql).unwrap();
+            for (id, name) in [(4, "dan"), (5, "eve")] {
+                insert.bind_int(1, id).unwrap();
+                insert.bind_text(2, name).unwrap();
+                let result = insert.execute(database).unwrap();
+                assert!(matches!(result, QueryResult::InsertRowSucceeded));
+            }
+            let e = insert.query(database).err();
+            assert!(matches!(e, Some(QueryError::UnkownOperation)));
+            let sql = "SELECT name FROM people WHERE id > 3";
+            assert_eq!(rows(database, sql), [[text("dan")], [text("eve")]]);
+        });
+    }
+
+    #[test]
+    fn inserted_values_can_use_parameters() {
+        with_tables(&[PEOPLE], |database| {
+            let sql =
+                "INSERT INTO people (id, name) VALUES (-1, ?), (1 + 1, :name), (abs(?), 'fay')";
+            let mut insert = database.prepare(sql).unwrap();
+            insert.bind_text(1, "dan").unwrap();
+            insert.bind_named(":name", text("eve")).unwrap();
+            insert.bind_int(3, -6).unwrap();
+            insert.execute(database).unwrap();
+            let sql = "SELECT id, name FROM people WHERE name > 'cid'";
+            assert_eq!(
+                rows(database, sql),
+                [
+                    [int(-1), text("dan")],
+                    [int(2), text("eve")],
+                    [int(6), text("fay")]
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn plans_are_reused_until_the_catalog_changes() {
+        with_tables(&[PEOPLE], |database| {
+            let mut statement = database
+                .prepare("SELECT * FROM people WHERE id = ?")
+                .unwrap();
+            statement.bind_int(1, 1).unwrap();
+            let result = statement.query(database).unwrap();
+            assert_eq!(result.rows, [[int(1), text("ann")]]);
+            assert!(statement.operator.is_some());
+
+            // rows inserted since are still seen
+            execute(database, "INSERT INTO people (id, name) VALUES (1, 'amy')");
+            assert_eq!(statement.query(database).unwrap().rows.len(), 2);
+
+            execute(database, "ALTER TABLE people ADD COLUMN age INT");
+            let result = statement.query(database).unwrap();
+            assert_eq!(result.columns.len(), 3);
+            assert_eq!(result.rows[0], [int(1), text("ann"), ColumnValue::Null]);
+
+            execute(database, "DROP TABLE people");
+            let e = statement.query(database).err();
+            assert!(matches!(e, Some(QueryError::UnknownTable)));
+        });
+    }
+
+    #[test]
+    fn plans_depending_on_parameters_are_not_reused() {
+        with_tables(&[PEOPLE], |database| {
+            let mut statement = database.prepare("SELECT id FROM people LIMIT ?").unwrap();
+            statement.bind_int(1, 1).unwrap();
+            assert_eq!(statement.query(database).unwrap().rows, [[int(1)]]);
+            assert!(statement.operator.is_none());
+            statement.bind_int(1, 2).unwrap();
+            assert_eq!(statement.query(database).unwrap().rows.len(), 2);
+        });
+    }
+
+    #[test]
+    fn reused_plans_read_the_clock_on_every_run() {
+        with_tables(&[], |database| {
+            let mut statement = database.prepare("SELECT strftime('%s', 'now')").unwrap();
+            let first = statement.query(database).unwrap().rows;
+            thread::sleep(Duration::from_millis(1100));
+            let second = statement.query(database).unwrap().rows;
+            assert_ne!(first, second);
+        });
+    }
+}
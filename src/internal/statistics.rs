@@ -0,0 +1,157 @@
+use crate::internal::table::{ColumnValue, Table};
+use serde::{Deserialize, Serialize};
+
+/// How many buckets a column's histogram splits its values into.
+static HISTOGRAM_BUCKETS: usize = 10;
+
+/// What `ANALYZE` found in a table. The optimizer estimates how many rows each
+/// step of a plan produces from them. They aren't kept up to date as rows are
+/// inserted, only replaced by the next `ANALYZE`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableStatistics {
+    pub table: String,
+    pub rows: usize,
+    pub columns: Vec<ColumnStatistics>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnStatistics {
+    pub name: String,
+    /// How many different values other than NULL the column holds.
+    pub distinct: usize,
+    pub nulls: usize,
+    /// Bounds of buckets holding about as many of the column's values each,
+    /// from the smallest value to the largest. NULLs aren't counted.
+    pub histogram: Vec<ColumnValue>,
+}
+
+impl TableStatistics {
+    /// Reads every row of `table` to gather its statistics.
+    pub fn compute(table: &Table) -> TableStatistics {
+        let rows = table.rows();
+        let columns = table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let mut values: Vec<&ColumnValue> = rows
+                    .iter()
+                    .map(|row| &row[index])
+                    .filter(|value| **value != ColumnValue::Null)
+                    .collect();
+                values.sort();
+                let distinct = match values.len() {
+                    0 => 0,
+                    _ => values.windows(2).filter(|pair| pair[0] != pair[1]).count() + 1,
+                };
+                // with few values, each one gets a bucket of its own
+                let buckets = HISTOGRAM_BUCKETS.min(values.len().saturating_sub(1)).max(1);
+                let histogram = match values.len() {
+                    0 => Vec::new(),
+                    len => (0..=buckets)
+                        .map(|bucket| values[bucket * (len - 1) / buckets].clone())
+                        .collect(),
+                };
+                ColumnStatistics {
+                    name: column.name.clone(),
+                    distinct,
+                    nulls: rows.len() - values.len(),
+                    histogram,
+                }
+            })
+            .collect();
+        TableStatistics {
+            table: table.name.clone(),
+            rows: rows.len(),
+            columns,
+        }
+    }
+    pub fn column(&self, name: &str) -> Option<&ColumnStatistics> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+    /// The fraction of the table's rows for which `column` is NULL.
+    pub fn null_fraction(&self, column: &ColumnStatistics) -> f64 {
+        match self.rows {
+            0 => 0.0,
+            rows => column.nulls as f64 / rows as f64,
+        }
+    }
+}
+
+impl ColumnStatistics {
+    /// Estimates the fraction of the column's values other than NULL that are
+    /// smaller than `value`. Integers are assumed to be spread evenly within a
+    /// bucket; for text, half of the bucket `value` falls in is counted.
+    pub fn fraction_below(&self, value: &ColumnValue) -> f64 {
+        let buckets = self.histogram.len().saturating_sub(1);
+        if buckets == 0 {
+            return match self.histogram.first() {
+                Some(only) if only < value => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+        }
+        let below: f64 = self
+            .histogram
+            .windows(2)
+            .map(|bounds| match (&bounds[0], &bounds[1]) {
+                (_, high) if high < value => 1.0,
+                (low, _) if low >= value => 0.0,
+                (ColumnValue::Int(low), ColumnValue::Int(high)) => match value {
+                    // subtracting as i64 overflows for bounds far apart
+                    ColumnValue::Int(value) => {
+                        (*value as f64 - *low as f64) / (*high as f64 - *low as f64)
+                    }
+                    _ => 0.5,
+                },
+                _ => 0.5,
+            })
+            .sum();
+        below / buckets as f64
+    }
+    /// Whether `value` lies between the smallest and the largest value of the column.
+    pub fn covers(&self, value: &ColumnValue) -> bool {
+        match (self.histogram.first(), self.histogram.last()) {
+            (Some(low), Some(high)) => low <= value && value <= high,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColumnStatistics;
+    use crate::internal::table::ColumnValue;
+
+    fn ints(name: &str, histogram: &[i64]) -> ColumnStatistics {
+        ColumnStatistics {
+            name: name.to_string(),
+            distinct: histogram.len(),
+            nulls: 0,
+            histogram: histogram
+                .iter()
+                .map(|value| ColumnValue::Int(*value))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn fraction_below_interpolates_within_buckets() {
+        let column = ints("x", &[0, 10, 20]);
+        assert_eq!(column.fraction_below(&ColumnValue::Int(-5)), 0.0);
+        assert_eq!(column.fraction_below(&ColumnValue::Int(5)), 0.25);
+        assert_eq!(column.fraction_below(&ColumnValue::Int(15)), 0.75);
+        assert_eq!(column.fraction_below(&ColumnValue::Int(25)), 1.0);
+        assert_eq!(ints("x", &[7]).fraction_below(&ColumnValue::Int(8)), 1.0);
+        assert_eq!(ints("x", &[]).fraction_below(&ColumnValue::Int(8)), 0.5);
+    }
+
+    #[test]
+    fn fraction_below_with_extreme_bounds() {
+        let column = ints("x", &[i64::MIN, i64::MAX]);
+        let half = column.fraction_below(&ColumnValue::Int(0));
+        assert!((half - 0.5).abs() < 1e-9, "{half}");
+        let all = column.fraction_below(&ColumnValue::Int(i64::MAX - 1));
+        assert!((0.0..=1.0).contains(&all), "{all}");
+    }
+}
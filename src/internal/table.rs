@@ -2,9 +2,13 @@ use crate::internal::{errors, page::Page};
 use csv;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::{fs, io};
+use std::{cmp::Ordering, fs, io, path::Path};
 
 static PAGE_SIZE: i32 = 4096;
+/// Appended to a table's name for the directory `rewrite` fills, and for the
+/// one the table's old pages are moved to once it's full.
+static REWRITE_SUFFIX: &str = "-rewrite";
+static REPLACED_SUFFIX: &str = "-replaced";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Table {
@@ -17,6 +21,9 @@ impl Table {
     pub fn new(name: String, columns: Vec<Column>) -> Table {
         Table { name, columns }
     }
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
 
     pub fn load(name: &String) -> Table {
         let metadata = fs::read_to_string(format!("{}/table.json", &name)).unwrap();
@@ -25,12 +32,123 @@ impl Table {
     }
     pub fn save(&self) -> Result<(), io::Error> {
         fs::create_dir(&self.name)?;
+        self.save_metadata()
+    }
+    /// Rewrites `table.json` of a table whose directory already exists.
+    pub fn save_metadata(&self) -> Result<(), io::Error> {
         let serialized = serde_json::to_string(&self).unwrap();
         fs::write(format!("{}/table.json", &self.name), serialized)?;
         Ok(())
     }
+    /// Deletes the table's directory along with all of its pages.
+    pub fn drop(&self) -> Result<(), io::Error> {
+        fs::remove_dir_all(&self.name)
+    }
+    /// Moves the table's directory and metadata to `new_name`.
+    pub fn rename(&mut self, new_name: &str) -> Result<(), io::Error> {
+        fs::rename(&self.name, new_name)?;
+        self.name = new_name.to_string();
+        self.save_metadata()
+    }
+    /// Replaces the table's columns with `columns` and its rows with `rows`.
+    ///
+    /// The new pages are written to a directory of their own, which then takes
+    /// the place of the table's, so a row that doesn't fit `columns` or a page
+    /// leaves the table as it was.
+    pub fn rewrite(
+        &mut self,
+        columns: Vec<Column>,
+        rows: Vec<Vec<ColumnValue>>,
+    ) -> Result<(), errors::InsertRowError> {
+        let rewritten = Table::new(format!("{}{REWRITE_SUFFIX}", self.name), columns);
+        for row in &rows {
+            rewritten.row_size(row)?;
+        }
+        // left behind by a rewrite that was cut short
+        if Path::new(&rewritten.name).exists() {
+            fs::remove_dir_all(&rewritten.name).map_err(errors::InsertRowError::IOError)?;
+        }
+        rewritten.save().map_err(errors::InsertRowError::IOError)?;
+        rewritten.insert_rows(rows)?;
+        let table = Table::new(self.name.clone(), rewritten.columns);
+        let replaced = format!("{}{REPLACED_SUFFIX}", self.name);
+        let swap = || {
+            let metadata = serde_json::to_string(&table).unwrap();
+            fs::write(format!("{}/table.json", rewritten.name), metadata)?;
+            fs::rename(&self.name, &replaced)?;
+            fs::rename(&rewritten.name, &self.name)?;
+            fs::remove_dir_all(&replaced)
+        };
+        swap().map_err(errors::InsertRowError::IOError)?;
+        self.columns = table.columns;
+        Ok(())
+    }
+    fn pages(&self) -> Vec<fs::DirEntry> {
+        fs::read_dir(&self.name)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_name().to_str().unwrap().contains("page_"))
+            .collect::<Vec<_>>()
+    }
     pub fn insert_row(&self, row: Vec<ColumnValue>) -> Result<(), errors::InsertRowError> {
-        if &row.len() != &self.columns.len() {
+        let row_size = self.row_size(&row)?;
+        let pages = self.pages();
+        let last_page = pages.last();
+        let bytes = encode_row(&row);
+
+        if let Some(last_page) = last_page {
+            // Walk over the directory and get the file size of each file
+            let metadata = fs::metadata(last_page.path()).unwrap();
+            let file_size = metadata.len();
+            let row_size: u64 = row_size.try_into().unwrap();
+            // println!("filesize = {}, rowsize = {}", file_size, row_size);
+            if file_size + row_size < PAGE_SIZE as u64 {
+                Page::write_bytes(self, (pages.len() - 1) as i64, &bytes).unwrap();
+            } else {
+                // println!("Page {} is too not empty enough", pages.len() - 1);
+                Page::write_bytes(self, (pages.len()) as i64, &bytes).unwrap();
+            }
+        } else {
+            Page::write_bytes(self, 0, &bytes).unwrap();
+        }
+        Ok(())
+    }
+    /// Appends `rows` to the table, filling pages like `insert_row` does but
+    /// writing each page once, rather than once per row.
+    pub fn insert_rows(&self, rows: Vec<Vec<ColumnValue>>) -> Result<(), errors::InsertRowError> {
+        let pages = self.pages();
+        let (mut page, mut page_size) = match pages.last() {
+            Some(last_page) => {
+                let metadata =
+                    fs::metadata(last_page.path()).map_err(errors::InsertRowError::IOError)?;
+                (pages.len() as i64 - 1, metadata.len() as usize)
+            }
+            None => (0, 0),
+        };
+        let mut buffer = Vec::new();
+        for row in rows {
+            let row_size = self.row_size(&row)?;
+            if page_size > 0 && page_size + row_size >= PAGE_SIZE as usize {
+                if !buffer.is_empty() {
+                    Page::write_bytes(self, page, &buffer)
+                        .map_err(errors::InsertRowError::IOError)?;
+                    buffer.clear();
+                }
+                page += 1;
+                page_size = 0;
+            }
+            let bytes = encode_row(&row);
+            page_size += bytes.len();
+            buffer.extend(bytes);
+        }
+        if !buffer.is_empty() {
+            Page::write_bytes(self, page, &buffer).map_err(errors::InsertRowError::IOError)?;
+        }
+        Ok(())
+    }
+    /// Checks that `row` fits the table's columns and a page, and returns its size.
+    pub fn row_size(&self, row: &[ColumnValue]) -> Result<usize, errors::InsertRowError> {
+        if row.len() != self.columns.len() {
             return Err(errors::InsertRowError::InsertedValuesDoNotMatchNumberOfTableColumns);
         }
         let mut row_size = 0;
@@ -42,126 +160,214 @@ impl Table {
                     if let ColumnType::Int = should_be._type {
                         row_size += std::mem::size_of::<i64>();
                     } else {
-                        panic!("Fields don't match. Expected Int.")
+                        return Err(errors::InsertRowError::UnmatchingType);
                     }
                 }
                 ColumnValue::Text(value) => {
                     if let ColumnType::Text = should_be._type {
                         row_size += value.capacity();
                     } else {
-                        panic!("Fields don't match. Expected Str.")
+                        return Err(errors::InsertRowError::UnmatchingType);
                     }
                 }
+                ColumnValue::Null => row_size += NULL.len(),
             }
         }
         if (row_size as i32) >= PAGE_SIZE {
-            panic!("ROW SIZE IS BIGGER THAN PAGE_SIZE");
-        }
-        let pages = fs::read_dir(&self.name)
-            .unwrap()
-            .map(|entry| entry.unwrap())
-            .filter(|entry| entry.file_name().to_str().unwrap().contains("page_"))
-            .collect::<Vec<_>>();
-        let last_page = pages.last();
-        let mut buffer = io::Cursor::new(Vec::new());
-        {
-            let mut writer = csv::WriterBuilder::new()
-                .has_headers(true)
-                .from_writer(&mut buffer);
-            writer.serialize(row).unwrap();
-
-            writer.flush().unwrap();
-        }
-        let bytes = buffer.into_inner();
-
-        if let Some(last_page) = last_page {
-            // Walk over the directory and get the file size of each file
-            let metadata = fs::metadata(last_page.path()).unwrap();
-            let file_size = metadata.len();
-            let row_size: u64 = row_size.try_into().unwrap();
-            // println!("filesize = {}, rowsize = {}", file_size, row_size);
-            if file_size + row_size < PAGE_SIZE as u64 {
-                Page::write_bytes(&self, (pages.len() - 1) as i64, &bytes).unwrap();
-            } else {
-                // println!("Page {} is too not empty enough", pages.len() - 1);
-                Page::write_bytes(&self, (pages.len()) as i64, &bytes).unwrap();
-            }
-        } else {
-            Page::write_bytes(&self, 0, &bytes).unwrap();
+            return Err(errors::InsertRowError::RowTooBig);
         }
-        Ok(())
+        Ok(row_size)
     }
-    pub fn select(&self, columns: &Vec<String>) -> Vec<ColumnValue> {
+    /// Reads the values of `columns` from every row, one row after the other.
+    pub fn select(&self, columns: &[String]) -> Vec<ColumnValue> {
         // indexes of selected table columns
         let column_indexes: Vec<usize> = columns
             .iter()
-            .map(|c| {
-                let column = self.columns.iter().position(|col| col.name == **c).unwrap();
-                column
-            })
+            .map(|c| self.columns.iter().position(|col| col.name == **c).unwrap())
             .collect();
-        let mut results: Vec<u8> = Vec::new();
-        let pages = fs::read_dir(&self.name)
-            .unwrap()
-            .map(|entry| entry.unwrap())
-            .filter(|entry| entry.file_name().to_str().unwrap().contains("page_"))
-            .collect::<Vec<_>>();
-        for (index, _) in pages.iter().enumerate() {
-            let mut page_content = Page::read(&self, index as i64).unwrap();
-            results.append(&mut page_content);
+        self.scan_columns(&column_indexes).flatten().collect()
+    }
+    /// Reads every row of the table, in page order.
+    pub fn rows(&self) -> Vec<Vec<ColumnValue>> {
+        self.scan().collect()
+    }
+    /// Lazily reads the table's rows one page at a time, so callers that stop
+    /// early don't pay for the pages they never look at.
+    pub fn scan(&self) -> TableScan<'_> {
+        self.scan_columns(&(0..self.columns.len()).collect::<Vec<_>>())
+    }
+    /// Like `scan`, but only decodes the columns at the given indexes, in that order.
+    pub fn scan_columns(&self, columns: &[usize]) -> TableScan<'_> {
+        TableScan {
+            table: self,
+            columns: columns.to_vec(),
+            pages: self.pages().len() as i64,
+            next_page: 0,
+            rows: Vec::new().into_iter(),
         }
-        let data = match std::str::from_utf8(&results) {
+    }
+}
+
+pub struct TableScan<'a> {
+    table: &'a Table,
+    columns: Vec<usize>,
+    pages: i64,
+    next_page: i64,
+    rows: std::vec::IntoIter<Vec<ColumnValue>>,
+}
+impl TableScan<'_> {
+    fn read_page(&self, page_num: i64) -> Vec<Vec<ColumnValue>> {
+        let page_content = Page::read(self.table, page_num).unwrap();
+        let data = match std::str::from_utf8(&page_content) {
             Ok(s) => s.to_owned(),
             Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
         };
         let mut csv_reader = csv::ReaderBuilder::new()
             .has_headers(false)
             .from_reader(data.as_bytes());
-        // let mut results = Vec::new();
         let mut rows = Vec::new();
         for result in csv_reader.records() {
-            // let mut seslected_columns = Vec::new();
             let record: csv::StringRecord = result.unwrap();
-            for index in column_indexes.iter() {
-                let column = &self.columns[*index];
-                match column._type {
-                    ColumnType::Int => {
-                        let column = record.get(*index).unwrap().parse::<i64>().unwrap();
-                        rows.push(ColumnValue::Int(column));
-                    }
-                    ColumnType::Text => {
-                        let column = record.get(*index).unwrap().to_string();
-                        rows.push(ColumnValue::Text(column));
-                    }
-                }
-            }
+            let row = self
+                .columns
+                .iter()
+                .map(|index| {
+                    let column = &self.table.columns[*index];
+                    ColumnValue::decode(record.get(*index).unwrap(), &column._type)
+                })
+                .collect();
+            rows.push(row);
         }
         rows
     }
 }
+impl Iterator for TableScan<'_> {
+    type Item = Vec<ColumnValue>;
 
-#[derive(Debug, Serialize, Deserialize)]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(row);
+            }
+            if self.next_page >= self.pages {
+                return None;
+            }
+            self.rows = self.read_page(self.next_page).into_iter();
+            self.next_page += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
     pub _type: ColumnType,
 }
 
+/// Rows produced by a query, along with the columns describing them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultSet {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<ColumnValue>>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum QueryResult {
-    Rows(Vec<ColumnValue>),
+    Rows(ResultSet),
     InsertRowSucceeded,
     CreateTableSucceeded,
+    DropTableSucceeded,
+    AlterTableSucceeded,
+    AnalyzeSucceeded,
+    TransactionSucceeded,
+    /// How many rows `COPY` read or wrote.
+    CopySucceeded(usize),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ColumnType {
     Int,
     Text,
 }
+impl ColumnType {
+    /// The name of the type in a column definition.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Int => "INT",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ColumnValue {
     Int(i64),
     // Float(f64),
     Text(String),
+    Null,
+}
+
+/// Values of different types sort like SQLite: NULL first, then integers, then text.
+impl Ord for ColumnValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (ColumnValue::Null, ColumnValue::Null) => Ordering::Equal,
+            (ColumnValue::Null, _) => Ordering::Less,
+            (_, ColumnValue::Null) => Ordering::Greater,
+            (ColumnValue::Int(a), ColumnValue::Int(b)) => a.cmp(b),
+            (ColumnValue::Int(_), ColumnValue::Text(_)) => Ordering::Less,
+            (ColumnValue::Text(_), ColumnValue::Int(_)) => Ordering::Greater,
+            (ColumnValue::Text(a), ColumnValue::Text(b)) => a.cmp(b),
+        }
+    }
+}
+impl PartialOrd for ColumnValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Encodes `row` as a CSV record of a page.
+fn encode_row(row: &[ColumnValue]) -> Vec<u8> {
+    let mut buffer = io::Cursor::new(Vec::new());
+    {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(&mut buffer);
+        writer
+            .write_record(row.iter().map(ColumnValue::encode))
+            .unwrap();
+
+        writer.flush().unwrap();
+    }
+    buffer.into_inner()
+}
+
+/// How `ColumnValue::Null` is stored in a page. Text values starting with a
+/// backslash get an extra one so they can't be mistaken for it.
+static NULL: &str = "\\N";
+
+impl ColumnValue {
+    /// Encodes the value as a CSV field of a page.
+    pub fn encode(&self) -> String {
+        match self {
+            ColumnValue::Int(value) => value.to_string(),
+            ColumnValue::Text(value) if value.starts_with('\\') => format!("\\{value}"),
+            ColumnValue::Text(value) => value.clone(),
+            ColumnValue::Null => NULL.to_string(),
+        }
+    }
+    /// Decodes a CSV field written by `encode` for a column of type `_type`.
+    pub fn decode(field: &str, _type: &ColumnType) -> ColumnValue {
+        if field == NULL {
+            return ColumnValue::Null;
+        }
+        match _type {
+            ColumnType::Int => ColumnValue::Int(field.parse::<i64>().unwrap()),
+            ColumnType::Text => match field.strip_prefix('\\') {
+                Some(escaped) => ColumnValue::Text(escaped.to_string()),
+                None => ColumnValue::Text(field.to_string()),
+            },
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use crate::internal::{
+    database::Database,
+    errors::QueryError,
+    table::{ColumnValue, QueryResult},
+};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Held by the test using the current directory, which the whole process shares.
+static CURRENT_DIR: Mutex<()> = Mutex::new(());
+static DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Puts the previous current directory back and removes the temporary one,
+/// even when the test panics.
+struct TempDir {
+    previous: PathBuf,
+    path: PathBuf,
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.previous);
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Runs `test` with a new empty directory as the current one, since the
+/// database keeps its files in the current directory.
+pub fn in_temp_dir<T>(test: impl FnOnce() -> T) -> T {
+    // a test that panicked still left the directory as it found it
+    let _guard = CURRENT_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let path = env::temp_dir().join(format!(
+        "litesql-test-{}-{}",
+        process::id(),
+        DIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&path).unwrap();
+    let _dir = TempDir {
+        previous: env::current_dir().unwrap(),
+        path: path.clone(),
+    };
+    env::set_current_dir(&path).unwrap();
+    test()
+}
+
+/// A table a test starts with: its name, its columns as in `a INT, b TEXT`,
+/// and the values of each of its rows, as in `1, 'x'`.
+pub type Fixture<'a> = (&'a str, &'a str, &'a [&'a str]);
+
+/// Runs `test` on a new database holding `tables`, in a new empty directory.
+pub fn with_tables<T>(tables: &[Fixture], test: impl FnOnce(&mut Database) -> T) -> T {
+    in_temp_dir(|| {
+        let mut database = Database::load().unwrap();
+        for (table, columns, rows) in tables {
+            execute(&mut database, &format!("CREATE TABLE {table} ({columns})"));
+            let names: Vec<&str> = columns
+                .split(',')
+                .filter_map(|column| column.split_whitespace().next())
+                .collect();
+            let names = names.join(", ");
+            for row in *rows {
+                let sql = format!("INSERT INTO {table} ({names}) VALUES ({row})");
+                execute(&mut database, &sql);
+            }
+        }
+        test(&mut database)
+    })
+}
+
+/// Runs the statement `sql`, panicking if it fails.
+pub fn execute(database: &mut Database, sql: &str) -> QueryResult {
+    match database.query(sql.to_string()) {
+        Ok(result) => result,
+        Err(e) => panic!("{sql:?} failed: {e:?}"),
+    }
+}
+
+/// The rows returned by `sql`.
+pub fn rows(database: &mut Database, sql: &str) -> Vec<Vec<ColumnValue>> {
+    match execute(database, sql) {
+        QueryResult::Rows(result) => result.rows,
+        other => panic!("{sql:?} returned {other:?} instead of rows"),
+    }
+}
+
+/// The values returned by `sql`, one row after the other.
+pub fn values(database: &mut Database, sql: &str) -> Vec<ColumnValue> {
+    rows(database, sql).concat()
+}
+
+/// The values returned by `sql`, which must all be integers.
+pub fn ints(database: &mut Database, sql: &str) -> Vec<i64> {
+    values(database, sql)
+        .into_iter()
+        .map(|value| match value {
+            ColumnValue::Int(value) => value,
+            other => panic!("{other:?} isn't an integer"),
+        })
+        .collect()
+}
+
+/// The error `sql` fails with.
+pub fn error(database: &mut Database, sql: &str) -> QueryError {
+    match database.query(sql.to_string()) {
+        Ok(result) => panic!("{sql:?} succeeded with {result:?}"),
+        Err(e) => e,
+    }
+}
+
+pub fn int(value: i64) -> ColumnValue {
+    ColumnValue::Int(value)
+}
+
+pub fn text(value: &str) -> ColumnValue {
+    ColumnValue::Text(value.to_string())
+}
@@ -0,0 +1,422 @@
+use crate::internal::{
+    aggregate::{Accumulator, Aggregate},
+    errors,
+    expression::{evaluate, expression_type, sub_expressions, Context, Field, Scope},
+    function::Functions,
+    sort::{compare, SortKey},
+    table::{ColumnType, ColumnValue},
+};
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, NamedWindowDefinition, WindowFrameBound, WindowFrameUnits,
+    WindowSpec, WindowType,
+};
+use std::{cmp::Ordering, collections::HashMap};
+
+#[derive(Debug, Clone)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    /// `lag(expr [, offset [, default]])`
+    Lag,
+    /// `lead(expr [, offset [, default]])`
+    Lead,
+    /// An aggregate such as `SUM(x) OVER (...)`, computed over the row's frame.
+    Aggregate(Aggregate),
+}
+
+/// A call to a window function, such as `rank() OVER (PARTITION BY a ORDER BY b)`.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub function: WindowFunction,
+    pub arguments: Vec<Expr>,
+    pub spec: WindowSpec,
+}
+impl Window {
+    /// Returns the window function `expr` calls, or `None` when it has no OVER
+    /// clause. `named` are the windows of the query's WINDOW clause.
+    pub fn from_expr(
+        expr: &Expr,
+        named: &[NamedWindowDefinition],
+        functions: Option<&Functions>,
+    ) -> Result<Option<Window>, errors::QueryError> {
+        let Expr::Function(function) = expr else {
+            return Ok(None);
+        };
+        let spec = match &function.over {
+            None => return Ok(None),
+            Some(WindowType::WindowSpec(spec)) => spec.clone(),
+            Some(WindowType::NamedWindow(name)) => named
+                .iter()
+                .find(|definition| definition.0 == *name)
+                .map(|definition| definition.1.clone())
+                .ok_or(errors::QueryError::UnknownWindow)?,
+        };
+        let arguments = function
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Some(Ok(expr.clone())),
+                FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => None,
+                _ => Some(Err(errors::QueryError::SyntaxError)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (window_function, arity) = match function.name.to_string().to_lowercase().as_str() {
+            "row_number" => (WindowFunction::RowNumber, 0..=0),
+            "rank" => (WindowFunction::Rank, 0..=0),
+            "dense_rank" => (WindowFunction::DenseRank, 0..=0),
+            "lag" => (WindowFunction::Lag, 1..=3),
+            "lead" => (WindowFunction::Lead, 1..=3),
+            _ => {
+                let mut call = function.clone();
+                call.over = None;
+                let aggregate = Aggregate::from_expr(&Expr::Function(call), functions)?
+                    .ok_or(errors::QueryError::UnknownFunction)?;
+                let arguments = aggregate.arguments.clone();
+                return Ok(Some(Window {
+                    function: WindowFunction::Aggregate(aggregate),
+                    arguments,
+                    spec,
+                }));
+            }
+        };
+        if !arity.contains(&arguments.len()) {
+            return Err(errors::QueryError::WrongNumberOfArguments);
+        }
+        Ok(Some(Window {
+            function: window_function,
+            arguments,
+            spec,
+        }))
+    }
+    pub fn result_type(
+        &self,
+        fields: &[Field],
+        context: Option<&Context>,
+    ) -> Result<ColumnType, errors::QueryError> {
+        match &self.function {
+            WindowFunction::Lag | WindowFunction::Lead => {
+                expression_type(&self.arguments[0], fields, context)
+            }
+            WindowFunction::Aggregate(aggregate) => aggregate.result_type(fields, context),
+            _ => Ok(ColumnType::Int),
+        }
+    }
+}
+
+/// Finds the window function calls in `expr`, appending the ones not seen yet to `found`.
+pub fn collect_windows(expr: &Expr, found: &mut Vec<Expr>) {
+    if let Expr::Function(function) = expr {
+        if function.over.is_some() {
+            if !found.contains(expr) {
+                found.push(expr.clone());
+            }
+            return;
+        }
+    }
+    for expr in sub_expressions(expr) {
+        collect_windows(expr, found);
+    }
+}
+
+/// Computes window functions over `rows`, which are described by the fields
+/// and computed expressions of `scope`. Each row gets the value of every
+/// window appended, in order, and the rows keep their order.
+pub fn evaluate_windows(
+    windows: &[Window],
+    scope: (&[Field], &[Expr]),
+    mut rows: Vec<Vec<ColumnValue>>,
+    context: &Context,
+) -> Result<Vec<Vec<ColumnValue>>, errors::QueryError> {
+    let (fields, computed) = scope;
+    let mut results = Vec::new();
+    for window in windows {
+        // partition keys, sort keys and arguments of every row
+        let mut partitions: HashMap<Vec<ColumnValue>, Vec<usize>> = HashMap::new();
+        let mut partition_order = Vec::new();
+        let mut sort_keys = Vec::new();
+        let mut arguments = Vec::new();
+        for (index, row) in rows.iter().enumerate() {
+            let scope = Scope::with_computed(fields, row, computed).within(Some(context));
+            let evaluate_all = |exprs: &mut dyn Iterator<Item = &Expr>| {
+                exprs
+                    .map(|expr| evaluate(expr, &scope))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let key = evaluate_all(&mut window.spec.partition_by.iter())?;
+            sort_keys.push(evaluate_all(
+                &mut window.spec.order_by.iter().map(|o| &o.expr),
+            )?);
+            arguments.push(evaluate_all(&mut window.arguments.iter())?);
+            let partition = partitions.entry(key).or_insert_with_key(|key| {
+                partition_order.push(key.clone());
+                Vec::new()
+            });
+            partition.push(index);
+        }
+        let keys: Vec<SortKey> = window
+            .spec
+            .order_by
+            .iter()
+            .map(|o| SortKey::new(o.asc, o.nulls_first))
+            .collect();
+        let mut values = vec![ColumnValue::Null; rows.len()];
+        for key in partition_order {
+            let mut partition = partitions.remove(&key).unwrap();
+            // a stable sort keeps peers in the order they came in
+            partition.sort_by(|a, b| compare(&keys, &sort_keys[*a], &sort_keys[*b]));
+            let peers = |a: usize, b: usize| {
+                compare(&keys, &sort_keys[partition[a]], &sort_keys[partition[b]])
+                    == Ordering::Equal
+            };
+            evaluate_partition(window, &partition, &peers, &arguments, &mut values)?;
+        }
+        results.push(values);
+    }
+    for (index, row) in rows.iter_mut().enumerate() {
+        row.extend(results.iter().map(|values| values[index].clone()));
+    }
+    Ok(rows)
+}
+
+/// Computes `window` for the rows of one sorted partition, given as indexes
+/// into `arguments` and `values`. `peers` tells whether two positions of the
+/// partition sort equally.
+fn evaluate_partition(
+    window: &Window,
+    partition: &[usize],
+    peers: &dyn Fn(usize, usize) -> bool,
+    arguments: &[Vec<ColumnValue>],
+    values: &mut [ColumnValue],
+) -> Result<(), errors::QueryError> {
+    let (mut rank, mut dense_rank) = (0, 0);
+    let mut accumulator = Accumulator::default();
+    let mut accumulated = 0;
+    for (position, row) in partition.iter().enumerate() {
+        if position == 0 || !peers(position - 1, position) {
+            rank = position as i64 + 1;
+            dense_rank += 1;
+        }
+        values[*row] = match &window.function {
+            WindowFunction::RowNumber => ColumnValue::Int(position as i64 + 1),
+            WindowFunction::Rank => ColumnValue::Int(rank),
+            WindowFunction::DenseRank => ColumnValue::Int(dense_rank),
+            WindowFunction::Lag | WindowFunction::Lead => {
+                let current = &arguments[*row];
+                let offset = match current.get(1) {
+                    Some(ColumnValue::Int(offset)) => *offset,
+                    None => 1,
+                    Some(_) => return Err(errors::QueryError::TypeMismatch),
+                };
+                let offset = match window.function {
+                    WindowFunction::Lag => -offset,
+                    _ => offset,
+                };
+                let other = (position as i64)
+                    .checked_add(offset)
+                    .and_then(|other| usize::try_from(other).ok())
+                    .and_then(|other| partition.get(other));
+                match other {
+                    Some(other) => arguments[*other][0].clone(),
+                    None => current.get(2).cloned().unwrap_or(ColumnValue::Null),
+                }
+            }
+            WindowFunction::Aggregate(aggregate) => {
+                let (start, end) = frame(&window.spec, position, partition.len(), peers)?;
+                if start == 0 && end >= accumulated && accumulator.value(aggregate).is_some() {
+                    // frames starting at the partition keep growing, so keep accumulating
+                    for row in &partition[accumulated..end] {
+                        accumulator.update(aggregate, arguments[*row].clone())?;
+                    }
+                    accumulated = end;
+                    accumulator.value(aggregate).unwrap()
+                } else {
+                    let mut accumulator = Accumulator::default();
+                    for row in &partition[start..end] {
+                        accumulator.update(aggregate, arguments[*row].clone())?;
+                    }
+                    accumulator.finish(aggregate)?
+                }
+            }
+        };
+    }
+    Ok(())
+}
+
+/// The frame of the row at `position` in a partition of `len` rows, as a
+/// range of positions. Without a frame clause it runs from the start of the
+/// partition to the row's last peer, or over the whole partition without ORDER BY.
+fn frame(
+    spec: &WindowSpec,
+    position: usize,
+    len: usize,
+    peers: &dyn Fn(usize, usize) -> bool,
+) -> Result<(usize, usize), errors::QueryError> {
+    let first_peer = || {
+        (0..position)
+            .rev()
+            .take_while(|p| peers(*p, position))
+            .last()
+    };
+    let last_peer = || {
+        (position + 1..len)
+            .take_while(|p| peers(position, *p))
+            .last()
+    };
+    let Some(frame) = &spec.window_frame else {
+        if spec.order_by.is_empty() {
+            return Ok((0, len));
+        }
+        return Ok((0, last_peer().unwrap_or(position) + 1));
+    };
+    let end_bound = frame
+        .end_bound
+        .as_ref()
+        .unwrap_or(&WindowFrameBound::CurrentRow);
+    let bound = |bound: &WindowFrameBound, is_start: bool| -> Result<i64, errors::QueryError> {
+        let position = position as i64;
+        Ok(match (bound, &frame.units) {
+            (WindowFrameBound::Preceding(None), _) => 0,
+            (WindowFrameBound::Following(None), _) => len as i64 - 1,
+            (WindowFrameBound::CurrentRow, WindowFrameUnits::Rows) => position,
+            // in RANGE mode the current row stands for all of its peers
+            (WindowFrameBound::CurrentRow, WindowFrameUnits::Range) if is_start => {
+                first_peer().unwrap_or(position as usize) as i64
+            }
+            (WindowFrameBound::CurrentRow, WindowFrameUnits::Range) => {
+                last_peer().unwrap_or(position as usize) as i64
+            }
+            (WindowFrameBound::Preceding(Some(offset)), WindowFrameUnits::Rows) => {
+                position - frame_offset(offset)?
+            }
+            (WindowFrameBound::Following(Some(offset)), WindowFrameUnits::Rows) => {
+                position + frame_offset(offset)?
+            }
+            _ => return Err(errors::QueryError::UnkownOperation),
+        })
+    };
+    let start = bound(&frame.start_bound, true)?.max(0);
+    let end = (bound(end_bound, false)? + 1).min(len as i64);
+    if start >= end {
+        return Ok((0, 0));
+    }
+    Ok((start as usize, end as usize))
+}
+
+/// The number of rows in `n PRECEDING` or `n FOLLOWING`.
+fn frame_offset(offset: &Expr) -> Result<i64, errors::QueryError> {
+    match evaluate(offset, &Scope::new(&[], &[]))? {
+        ColumnValue::Int(offset) if offset >= 0 => Ok(offset),
+        _ => Err(errors::QueryError::SyntaxError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internal::{
+        errors::QueryError,
+        table::ColumnValue::Null,
+        testing::{error, int, ints, rows, with_tables, Fixture},
+    };
+
+    /// Scores of two teams, with a tie in the first one.
+    const SCORES: Fixture = (
+        "scores",
+        "team INT, points INT",
+        &["1, 10", "1, 20", "1, 20", "1, 30", "2, 5", "2, 15"],
+    );
+
+    #[test]
+    fn ranking_functions() {
+        with_tables(&[SCORES], |database| {
+            let sql = "SELECT row_number() OVER (ORDER BY points), \
+                rank() OVER (ORDER BY points), dense_rank() OVER (ORDER BY points) \
+                FROM scores WHERE team = 1 ORDER BY points, 1";
+            let result = rows(database, sql);
+            let expected = [[1, 1, 1], [2, 2, 2], [3, 2, 2], [4, 4, 3]];
+            assert_eq!(result, expected.map(|row| row.map(int)));
+        });
+    }
+
+    #[test]
+    fn partitions() {
+        with_tables(&[SCORES], |database| {
+            let sql = "SELECT row_number() OVER (PARTITION BY team ORDER BY points DESC) \
+                FROM scores ORDER BY team, points DESC";
+            assert_eq!(ints(database, sql), [1, 2, 3, 4, 1, 2]);
+            let sql = "SELECT sum(points) OVER (PARTITION BY team) FROM scores ORDER BY team";
+            assert_eq!(ints(database, sql), [80, 80, 80, 80, 20, 20]);
+        });
+    }
+
+    #[test]
+    fn lag_and_lead() {
+        with_tables(&[SCORES], |database| {
+            let sql = "SELECT lag(points) OVER (ORDER BY points), \
+                lead(points, 2, 0) OVER (ORDER BY points) \
+                FROM scores WHERE team = 2 ORDER BY points";
+            let expected = [[Null, int(0)], [int(5), int(0)]];
+            assert_eq!(rows(database, sql), expected);
+        });
+    }
+
+    #[test]
+    fn default_frame_includes_peers() {
+        with_tables(&[SCORES], |database| {
+            // with ORDER BY, the frame runs to the last row tied with the current one
+            let sql = "SELECT sum(points) OVER (ORDER BY points) FROM scores \
+                WHERE team = 1 ORDER BY points";
+            assert_eq!(ints(database, sql), [10, 50, 50, 80]);
+        });
+    }
+
+    #[test]
+    fn rows_frames() {
+        with_tables(&[SCORES], |database| {
+            let sql = "SELECT sum(points) OVER (ORDER BY points \
+                ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) FROM scores \
+                WHERE team = 1 ORDER BY points, 1";
+            assert_eq!(ints(database, sql), [10, 30, 40, 50]);
+            let sql = "SELECT count(*) OVER (ORDER BY points \
+                ROWS BETWEEN CURRENT ROW AND UNBOUNDED FOLLOWING) FROM scores \
+                WHERE team = 1 ORDER BY points, 1 DESC";
+            assert_eq!(ints(database, sql), [4, 3, 2, 1]);
+            let sql = "SELECT max(points) OVER (ORDER BY points \
+                ROWS BETWEEN 1 FOLLOWING AND 2 FOLLOWING) FROM scores \
+                WHERE team = 2 ORDER BY points";
+            let result = rows(database, sql);
+            assert_eq!(result, [[int(15)], [Null]]);
+        });
+    }
+
+    #[test]
+    fn range_frames() {
+        with_tables(&[SCORES], |database| {
+            let sql = "SELECT count(*) OVER (ORDER BY points \
+                RANGE BETWEEN CURRENT ROW AND CURRENT ROW) FROM scores \
+                WHERE team = 1 ORDER BY points";
+            assert_eq!(ints(database, sql), [1, 2, 2, 1]);
+            let e = error(
+                database,
+                "SELECT count(*) OVER (ORDER BY points RANGE 1 PRECEDING) FROM scores",
+            );
+            assert!(matches!(e, QueryError::UnkownOperation));
+        });
+    }
+
+    #[test]
+    fn named_windows_and_misuse() {
+        with_tables(&[SCORES], |database| {
+            let sql = "SELECT rank() OVER w FROM scores WHERE team = 2 \
+                WINDOW w AS (ORDER BY points DESC) ORDER BY points";
+            assert_eq!(ints(database, sql), [2, 1]);
+            let e = error(database, "SELECT rank() OVER nope FROM scores");
+            assert!(matches!(e, QueryError::UnknownWindow));
+            let e = error(
+                database,
+                "SELECT team FROM scores WHERE rank() OVER (ORDER BY team) = 1",
+            );
+            assert!(matches!(e, QueryError::MisusedWindowFunction));
+        });
+    }
+}
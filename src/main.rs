@@ -0,0 +1,438 @@
+use litesql::internal::{
+    database::Database,
+    errors::BatchError,
+    table::{Column, ColumnValue, QueryResult, ResultSet},
+};
+use std::{
+    env, fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+static HISTORY_FILE: &str = ".litesql_history";
+static HELP: &str = "\
+.help                  Show this message
+.tables                List the tables
+.schema [TABLE]        Show the CREATE TABLE statements of the tables
+.open DIRECTORY        Use the database in DIRECTORY, creating it if needed
+.mode csv|json|table   Set how query results are printed
+.timer on|off          Show how long each statement took
+.history               Show the statements entered so far
+.quit                  Exit";
+
+/// How query results are printed.
+#[derive(Clone, Copy)]
+enum Mode {
+    Table,
+    Csv,
+    Json,
+}
+
+struct Shell {
+    database: Database,
+    mode: Mode,
+    timer: bool,
+    history: Vec<String>,
+    /// Where the history is kept between sessions, if there is a home directory.
+    history_file: Option<PathBuf>,
+}
+
+/// An interactive shell: reads statements ending with `;` and dot-commands
+/// from the standard input, and prints what they did.
+///
+/// Usage: `litesql [DIRECTORY]`, where the database lives in `DIRECTORY`,
+/// the current one by default.
+fn main() {
+    let history_file = env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILE));
+    let history = history_file
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|history| history.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    if let Some(directory) = env::args().nth(1) {
+        if let Err(e) = open(&directory) {
+            eprintln!("Error: cannot open {directory}: {e}");
+            std::process::exit(1);
+        }
+    }
+    let database = match Database::load() {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("Error: cannot open the database: {e:?}");
+            std::process::exit(1);
+        }
+    };
+    let mut shell = Shell {
+        database,
+        mode: Mode::Table,
+        timer: false,
+        history,
+        history_file,
+    };
+    let interactive = io::stdin().is_terminal();
+    let mut statement = String::new();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            print!(
+                "{}",
+                if statement.is_empty() {
+                    "litesql> "
+                } else {
+                    "   ...> "
+                }
+            );
+            io::stdout().flush().unwrap();
+        }
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        if statement.is_empty() && line.trim_start().starts_with('.') {
+            shell.remember(line.trim());
+            if !shell.command(line.trim()) {
+                break;
+            }
+            continue;
+        }
+        statement.push_str(&line);
+        statement.push('\n');
+        if line.trim_end().ends_with(';') {
+            let sql = std::mem::take(&mut statement);
+            shell.remember(sql.trim());
+            shell.run(&sql);
+        }
+    }
+    // a statement missing its final semicolon still runs
+    if !statement.trim().is_empty() {
+        shell.run(&statement);
+    }
+}
+
+/// Makes `directory`, which is created if needed, the current one, since the
+/// database keeps its files in the current directory.
+fn open(directory: &str) -> Result<(), io::Error> {
+    fs::create_dir_all(directory)?;
+    env::set_current_dir(directory)
+}
+
+impl Shell {
+    fn remember(&mut self, entry: &str) {
+        let entry = entry.replace('\n', " ");
+        if let Some(path) = &self.history_file {
+            let file = fs::OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{entry}");
+            }
+        }
+        self.history.push(entry);
+    }
+    /// Runs every statement of `sql`, printing what each one did.
+    fn run(&mut self, sql: &str) {
+        let start = Instant::now();
+        let result = self.database.execute_batch(sql);
+        let elapsed = start.elapsed();
+        let results = match result {
+            Ok(results) => results,
+            Err(BatchError { index, error }) => {
+                eprintln!("Error in statement {}: {error:?}", index + 1);
+                return;
+            }
+        };
+        for result in results {
+            match result {
+                QueryResult::Rows(result) => self.print(&result),
+                other => println!("{other:?}"),
+            }
+        }
+        if self.timer {
+            println!("Run Time: {:.6}s", elapsed.as_secs_f64());
+        }
+    }
+    /// Runs a dot-command. Returns false when the shell should exit.
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let argument = words.next();
+        match (command, argument) {
+            (".quit" | ".exit", _) => return false,
+            (".help", _) => println!("{HELP}"),
+            (".tables", _) => {
+                let names: Vec<&str> = self
+                    .database
+                    .tables()
+                    .iter()
+                    .map(|table| table.name.as_str())
+                    .collect();
+                println!("{}", names.join("  "));
+            }
+            (".schema", name) => {
+                for table in self.database.tables() {
+                    if name.is_none_or(|name| name == table.name) {
+                        println!("{}", create_table(&table.name, &table.columns));
+                    }
+                }
+            }
+            (".open", Some(directory)) => match open(directory) {
+                Ok(()) => match Database::load() {
+                    Ok(database) => self.database = database,
+                    Err(e) => eprintln!("Error: cannot open {directory}: {e:?}"),
+                },
+                Err(e) => eprintln!("Error: cannot open {directory}: {e}"),
+            },
+            (".mode", Some(mode)) => match mode {
+                "table" => self.mode = Mode::Table,
+                "csv" => self.mode = Mode::Csv,
+                "json" => self.mode = Mode::Json,
+                _ => eprintln!("Error: unknown mode {mode}, expected csv, json or table"),
+            },
+            (".timer", Some("on")) => self.timer = true,
+            (".timer", Some("off")) => self.timer = false,
+            (".history", _) => {
+                for (number, entry) in self.history.iter().enumerate() {
+                    println!("{:5}  {entry}", number + 1);
+                }
+            }
+            _ => eprintln!(
+                "Error: unknown command or invalid arguments: {line}. Enter .help for help"
+            ),
+        }
+        true
+    }
+    fn print(&self, result: &ResultSet) {
+        match self.mode {
+            Mode::Table => print!("{}", render_table(result)),
+            Mode::Csv => print!("{}", render_csv(result)),
+            Mode::Json => println!("{}", render_json(result)),
+        }
+    }
+}
+
+fn create_table(name: &str, columns: &[Column]) -> String {
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|column| format!("{} {}", column.name, column._type.name()))
+        .collect();
+    format!("CREATE TABLE {name} ({});", columns.join(", "))
+}
+
+fn text(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Int(value) => value.to_string(),
+        ColumnValue::Text(value) => value.clone(),
+        ColumnValue::Null => "NULL".to_string(),
+    }
+}
+
+/// Lays the rows out in a grid, numbers aligned to the right of their column.
+fn render_table(result: &ResultSet) -> String {
+    let rows: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| row.iter().map(text).collect())
+        .collect();
+    let widths: Vec<usize> = result
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            rows.iter()
+                .map(|row| row[index].chars().count())
+                .chain([column.name.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let border = widths
+        .iter()
+        .map(|width| "-".repeat(width + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let border = format!("+{border}+");
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+    let mut table = format!("{border}\n");
+    table.push_str(&line(
+        result
+            .columns
+            .iter()
+            .zip(&widths)
+            .map(|(column, width)| format!("{:<width$}", column.name))
+            .collect(),
+    ));
+    table.push_str(&format!("{border}\n"));
+    for (row, values) in rows.iter().zip(&result.rows) {
+        table.push_str(&line(
+            row.iter()
+                .zip(values)
+                .zip(&widths)
+                .map(|((cell, value), width)| match value {
+                    ColumnValue::Int(_) => format!("{cell:>width$}"),
+                    _ => format!("{cell:<width$}"),
+                })
+                .collect(),
+        ));
+    }
+    if !rows.is_empty() {
+        table.push_str(&format!("{border}\n"));
+    }
+    table
+}
+
+/// A header line and then the rows, NULLs as empty fields.
+fn render_csv(result: &ResultSet) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let header = result.columns.iter().map(|column| &column.name);
+    writer.write_record(header).unwrap();
+    for row in &result.rows {
+        let fields = row.iter().map(|value| match value {
+            ColumnValue::Null => String::new(),
+            value => text(value),
+        });
+        writer.write_record(fields).unwrap();
+    }
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+/// The rows as an array of objects keyed by column name.
+fn render_json(result: &ResultSet) -> String {
+    let rows: Vec<serde_json::Value> = result
+        .rows
+        .iter()
+        .map(|row| {
+            let object = result
+                .columns
+                .iter()
+                .zip(row)
+                .map(|(column, value)| {
+                    let value = match value {
+                        ColumnValue::Int(value) => serde_json::Value::from(*value),
+                        ColumnValue::Text(value) => serde_json::Value::from(value.as_str()),
+                        ColumnValue::Null => serde_json::Value::Null,
+                    };
+                    (column.name.clone(), value)
+                })
+                .collect();
+            serde_json::Value::Object(object)
+        })
+        .collect();
+    serde_json::Value::Array(rows).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use litesql::internal::table::ColumnType;
+
+    fn result() -> ResultSet {
+        let column = |name: &str, _type| Column {
+            name: name.to_string(),
+            _type,
+        };
+        ResultSet {
+            columns: vec![
+                column("id", ColumnType::Int),
+                column("name", ColumnType::Text),
+            ],
+            rows: vec![
+                vec![
+                    ColumnValue::Int(7),
+                    ColumnValue::Text("ann, \"a\"".to_string()),
+                ],
+                vec![ColumnValue::Int(1234), ColumnValue::Null],
+            ],
+        }
+    }
+
+    fn shell() -> Shell {
+        Shell {
+            database: Database::new(),
+            mode: Mode::Table,
+            timer: false,
+            history: Vec::new(),
+            history_file: None,
+        }
+    }
+
+    #[test]
+    fn tables_align_numbers_to_the_right() {
+        assert_eq!(
+            render_table(&result()),
+            "\
++------+----------+
+| id   | name     |
++------+----------+
+|    7 | ann, \"a\" |
+| 1234 | NULL     |
++------+----------+
+"
+        );
+        let empty = ResultSet {
+            rows: Vec::new(),
+            ..result()
+        };
+        assert_eq!(render_table(&empty).lines().count(), 3);
+    }
+
+    #[test]
+    fn csv_quotes_fields_and_leaves_nulls_empty() {
+        assert_eq!(
+            render_csv(&result()),
+            "id,name\n7,\"ann, \"\"a\"\"\"\n1234,\n"
+        );
+    }
+
+    #[test]
+    fn json_keys_rows_by_column() {
+        assert_eq!(
+            render_json(&result()),
+            r#"[{"id":7,"name":"ann, \"a\""},{"id":1234,"name":null}]"#
+        );
+    }
+
+    #[test]
+    fn schema_is_shown_as_create_table() {
+        let result = result();
+        assert_eq!(
+            create_table("people", &result.columns),
+            "CREATE TABLE people (id INT, name TEXT);"
+        );
+    }
+
+    #[test]
+    fn dot_commands() {
+        let mut shell = shell();
+        assert!(shell.command(".mode csv"));
+        assert!(matches!(shell.mode, Mode::Csv));
+        assert!(shell.command(".mode xml"));
+        assert!(matches!(shell.mode, Mode::Csv));
+        assert!(shell.command(".timer on"));
+        assert!(shell.timer);
+        assert!(shell.command(".timer off"));
+        assert!(!shell.timer);
+        assert!(shell.command(".nope"));
+        assert!(!shell.command(".quit"));
+        assert!(!shell.command(".exit"));
+    }
+
+    #[test]
+    fn history_keeps_entries_on_one_line() {
+        let mut shell = shell();
+        shell.remember("SELECT 1,\n  2;");
+        shell.remember(".tables");
+        assert_eq!(shell.history, ["SELECT 1,   2;", ".tables"]);
+    }
+
+    #[test]
+    fn open_creates_the_directory() {
+        let previous = env::current_dir().unwrap();
+        let root = env::temp_dir().join(format!("litesql-shell-{}", std::process::id()));
+        let directory = root.join("nested");
+        let opened = open(directory.to_str().unwrap());
+        let current = env::current_dir().unwrap();
+        let expected = directory.canonicalize().unwrap();
+        env::set_current_dir(previous).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        opened.unwrap();
+        assert_eq!(current, expected);
+    }
+}